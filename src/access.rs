@@ -0,0 +1,137 @@
+use crate::config;
+use crate::db::DatabasePool;
+use crate::models;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use teloxide::types::Message;
+
+// 用户的访问权限判断结果
+#[derive(Debug, Clone)]
+pub struct AccessReport {
+    pub user_id: u64,
+    pub whitelist_enabled: bool,
+    pub is_admin: bool,
+    pub is_super_admin: bool,
+    pub whitelisted: bool,
+    // 最终是否允许使用机器人
+    pub allowed: bool,
+}
+
+// 判断用户是否可以使用机器人：白名单关闭时所有人可用，否则管理员和白名单用户可用
+// check_whitelist 和 /checkaccess 都使用这里的判断
+pub async fn evaluate_access(
+    pool: &DatabasePool,
+    user_id: u64,
+) -> Result<AccessReport, Box<dyn Error + Send + Sync>> {
+    let whitelist_enabled = config::whitelist_enabled();
+    let is_admin = models::Admin::is_admin(pool, user_id).await?;
+    let is_super_admin = is_admin && models::Admin::is_super_admin(pool, user_id).await?;
+    let whitelisted = models::WhitelistUser::is_user_whitelisted(pool, user_id).await?;
+
+    Ok(AccessReport {
+        user_id,
+        whitelist_enabled,
+        is_admin,
+        is_super_admin,
+        whitelisted,
+        allowed: !whitelist_enabled || is_admin || whitelisted,
+    })
+}
+
+// 按用户缓存的访问权限，避免每条消息都查询管理员表和白名单表
+// 缓存在 ttl 后过期；添加/移除白名单用户或管理员后需要调用 invalidate 立即失效
+#[derive(Clone)]
+pub struct AccessCache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<u64, (Instant, AccessReport)>>>,
+}
+
+impl AccessCache {
+    // ttl 为 0 时不缓存，每次都查询数据库
+    pub fn new(ttl: Duration) -> Self {
+        AccessCache {
+            ttl,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // 优先返回未过期的缓存结果，否则查询数据库并缓存
+    pub async fn evaluate(
+        &self,
+        pool: &DatabasePool,
+        user_id: u64,
+    ) -> Result<AccessReport, Box<dyn Error + Send + Sync>> {
+        if let Some(report) = self.get(user_id) {
+            return Ok(report);
+        }
+
+        let report = evaluate_access(pool, user_id).await?;
+        if !self.ttl.is_zero() {
+            self.lock()
+                .insert(user_id, (Instant::now(), report.clone()));
+        }
+        Ok(report)
+    }
+
+    // 使某个用户的缓存失效
+    pub fn invalidate(&self, user_id: u64) {
+        self.lock().remove(&user_id);
+    }
+
+    // 清空所有缓存，用于无法确定受影响用户的情况（如移除管理员、切换数据库）
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn get(&self, user_id: u64) -> Option<AccessReport> {
+        let mut entries = self.lock();
+        match entries.get(&user_id) {
+            Some((cached_at, report)) if cached_at.elapsed() < self.ttl => Some(report.clone()),
+            Some(_) => {
+                entries.remove(&user_id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, (Instant, AccessReport)>> {
+        match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl AccessReport {
+    // 格式化为便于查看的文本
+    pub fn to_text(&self) -> String {
+        let yes_no = |value: bool| if value { "是" } else { "否" };
+        let decision = if self.allowed {
+            "✅ 允许使用"
+        } else {
+            "⛔ 禁止使用"
+        };
+
+        format!(
+            "用户 {} 的访问权限:\n\n白名单功能: {}\n管理员: {}\n超级管理员: {}\n在白名单中: {}\n\n最终结果: {}",
+            self.user_id,
+            if self.whitelist_enabled { "开启" } else { "关闭" },
+            yes_no(self.is_admin),
+            yes_no(self.is_super_admin),
+            yes_no(self.whitelisted),
+            decision
+        )
+    }
+}
+
+// 以聊天身份发送的消息（频道消息，或在群组中以频道、匿名管理员身份发言）是否可以处理
+// 只处理频道中的消息，并且频道必须在 allowlist 中；群组中无法确认真实的发送者，一律不处理
+pub fn sender_chat_allowed(msg: &Message, allowlist: &[i64]) -> bool {
+    match &msg.sender_chat {
+        Some(sender_chat) => msg.chat.is_channel() && allowlist.contains(&sender_chat.id.0),
+        None => false,
+    }
+}
@@ -0,0 +1,212 @@
+use crate::db::DatabasePool;
+use std::error::Error;
+
+// 聚合统计报告，只包含汇总数据，不涉及具体用户或聊天
+#[derive(Debug)]
+pub struct AnalyticsReport {
+    pub days: i64,
+    pub messages_per_day: Vec<(String, i64)>,
+    pub top_models: Vec<(String, i64)>,
+    pub avg_latency_ms: Option<f64>,
+    pub voice_messages: i64,
+    pub text_messages: i64,
+}
+
+pub struct Analytics;
+
+impl Analytics {
+    // 生成最近 days 天的聚合统计报告
+    pub async fn report(
+        pool: &DatabasePool,
+        days: i64,
+    ) -> Result<AnalyticsReport, Box<dyn Error + Send + Sync>> {
+        Ok(AnalyticsReport {
+            days,
+            messages_per_day: Self::messages_per_day(pool, days).await?,
+            top_models: Self::top_models(pool, days, 5).await?,
+            avg_latency_ms: Self::average_latency_ms(pool, days).await?,
+            voice_messages: Self::count_by_source(pool, days, "voice").await?,
+            text_messages: Self::count_by_source(pool, days, "text").await?,
+        })
+    }
+
+    // 每日消息数
+    pub async fn messages_per_day(
+        pool: &DatabasePool,
+        days: i64,
+    ) -> Result<Vec<(String, i64)>, Box<dyn Error + Send + Sync>> {
+        let rows = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_as::<_, (String, i64)>(
+                    "SELECT date(timestamp) AS day, COUNT(*) FROM messages
+                     WHERE timestamp >= datetime('now', 'localtime', '-' || ? || ' days')
+                     GROUP BY day
+                     ORDER BY day ASC",
+                )
+                .bind(days)
+                .fetch_all(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_as::<_, (String, i64)>(
+                    "SELECT CAST(DATE(timestamp) AS TEXT) AS day, COUNT(*) FROM messages
+                     WHERE timestamp >= CURRENT_TIMESTAMP - make_interval(days => $1::INT)
+                     GROUP BY day
+                     ORDER BY day ASC",
+                )
+                .bind(days)
+                .fetch_all(db)
+                .await?
+            }
+        };
+
+        Ok(rows)
+    }
+
+    // 使用次数最多的模型
+    pub async fn top_models(
+        pool: &DatabasePool,
+        days: i64,
+        limit: i64,
+    ) -> Result<Vec<(String, i64)>, Box<dyn Error + Send + Sync>> {
+        let rows = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_as::<_, (String, i64)>(
+                    "SELECT model, COUNT(*) AS uses FROM messages
+                     WHERE role = 'assistant' AND model IS NOT NULL
+                       AND timestamp >= datetime('now', 'localtime', '-' || ? || ' days')
+                     GROUP BY model
+                     ORDER BY uses DESC
+                     LIMIT ?",
+                )
+                .bind(days)
+                .bind(limit)
+                .fetch_all(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_as::<_, (String, i64)>(
+                    "SELECT model, COUNT(*) AS uses FROM messages
+                     WHERE role = 'assistant' AND model IS NOT NULL
+                       AND timestamp >= CURRENT_TIMESTAMP - make_interval(days => $1::INT)
+                     GROUP BY model
+                     ORDER BY uses DESC
+                     LIMIT $2",
+                )
+                .bind(days)
+                .bind(limit)
+                .fetch_all(db)
+                .await?
+            }
+        };
+
+        Ok(rows)
+    }
+
+    // AI 回复的平均耗时（毫秒）
+    pub async fn average_latency_ms(
+        pool: &DatabasePool,
+        days: i64,
+    ) -> Result<Option<f64>, Box<dyn Error + Send + Sync>> {
+        let avg = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_scalar::<_, Option<f64>>(
+                    "SELECT AVG(latency_ms) FROM messages
+                     WHERE role = 'assistant' AND latency_ms IS NOT NULL
+                       AND timestamp >= datetime('now', 'localtime', '-' || ? || ' days')",
+                )
+                .bind(days)
+                .fetch_one(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_scalar::<_, Option<f64>>(
+                    "SELECT CAST(AVG(latency_ms) AS DOUBLE PRECISION) FROM messages
+                     WHERE role = 'assistant' AND latency_ms IS NOT NULL
+                       AND timestamp >= CURRENT_TIMESTAMP - make_interval(days => $1::INT)",
+                )
+                .bind(days)
+                .fetch_one(db)
+                .await?
+            }
+        };
+
+        Ok(avg)
+    }
+
+    // 按来源（text/voice）统计用户消息数
+    pub async fn count_by_source(
+        pool: &DatabasePool,
+        days: i64,
+        source: &str,
+    ) -> Result<i64, Box<dyn Error + Send + Sync>> {
+        let count = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_scalar::<_, i64>(
+                    "SELECT COUNT(*) FROM messages
+                     WHERE role = 'user' AND source = ?
+                       AND timestamp >= datetime('now', 'localtime', '-' || ? || ' days')",
+                )
+                .bind(source)
+                .bind(days)
+                .fetch_one(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_scalar::<_, i64>(
+                    "SELECT COUNT(*) FROM messages
+                     WHERE role = 'user' AND source = $1
+                       AND timestamp >= CURRENT_TIMESTAMP - make_interval(days => $2::INT)",
+                )
+                .bind(source)
+                .bind(days)
+                .fetch_one(db)
+                .await?
+            }
+        };
+
+        Ok(count)
+    }
+}
+
+impl AnalyticsReport {
+    // 格式化为紧凑的文本报告
+    pub fn to_text(&self) -> String {
+        let mut report = format!("📊 最近 {} 天使用统计\n\n", self.days);
+
+        report.push_str("每日消息数:\n");
+        if self.messages_per_day.is_empty() {
+            report.push_str("  (无数据)\n");
+        }
+        for (day, count) in &self.messages_per_day {
+            report.push_str(&format!("  {}: {}\n", day, count));
+        }
+
+        report.push_str("\n常用模型:\n");
+        if self.top_models.is_empty() {
+            report.push_str("  (无数据)\n");
+        }
+        for (model, uses) in &self.top_models {
+            report.push_str(&format!("  {}: {} 次\n", model, uses));
+        }
+
+        match self.avg_latency_ms {
+            Some(latency) => report.push_str(&format!("\n平均回复耗时: {:.0} ms\n", latency)),
+            None => report.push_str("\n平均回复耗时: (无数据)\n"),
+        }
+
+        let total = self.voice_messages + self.text_messages;
+        if total > 0 {
+            report.push_str(&format!(
+                "语音/文字消息: {} / {} (语音占比 {:.1}%)\n",
+                self.voice_messages,
+                self.text_messages,
+                self.voice_messages as f64 * 100.0 / total as f64
+            ));
+        } else {
+            report.push_str("语音/文字消息: (无数据)\n");
+        }
+
+        report
+    }
+}
@@ -0,0 +1,129 @@
+use crate::config;
+use crate::error::AppError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// 密钥被限流后、服务端没有返回 Retry-After 时暂停使用的时间
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(60);
+
+// 一组 OpenAI 密钥，按顺序轮流使用；某个密钥返回 429 后暂停使用，后续请求改用其他密钥
+#[derive(Clone)]
+pub struct ApiKeys {
+    keys: Arc<Vec<String>>,
+    state: Arc<Mutex<KeyState>>,
+}
+
+struct KeyState {
+    // 下一次从哪个密钥开始查找
+    next: usize,
+    // 每个密钥被限流到什么时候
+    limited_until: Vec<Option<Instant>>,
+}
+
+impl ApiKeys {
+    // 没有有效密钥时返回 None
+    pub fn new(keys: Vec<String>) -> Option<Self> {
+        let keys: Vec<String> = keys
+            .into_iter()
+            .map(|key| key.trim().to_string())
+            .filter(|key| !key.is_empty())
+            .collect();
+        if keys.is_empty() {
+            return None;
+        }
+
+        let count = keys.len();
+        Some(ApiKeys {
+            keys: Arc::new(keys),
+            state: Arc::new(Mutex::new(KeyState {
+                next: 0,
+                limited_until: vec![None; count],
+            })),
+        })
+    }
+
+    // 读取 OPENAI_API_KEYS（逗号分隔），未设置时使用单个 OPENAI_API_KEY
+    // 两者都支持通过 *_FILE 从文件读取
+    pub fn from_env() -> Result<Option<Self>, AppError> {
+        let keys = match config::read_secret("OPENAI_API_KEYS")? {
+            Some(keys) => keys,
+            None => match config::read_secret("OPENAI_API_KEY")? {
+                Some(key) => key,
+                None => return Ok(None),
+            },
+        };
+        Ok(ApiKeys::new(keys.split(',').map(str::to_string).collect()))
+    }
+
+    // 密钥数量
+    pub fn count(&self) -> usize {
+        self.keys.len()
+    }
+
+    // 全部密钥，启动检查时逐个验证
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    // 轮流取下一个没有被限流的密钥；全部被限流时使用最早恢复的那个
+    pub fn next(&self) -> String {
+        let mut state = self.lock();
+        let now = Instant::now();
+        let count = self.keys.len();
+
+        let available = (0..count)
+            .map(|offset| (state.next + offset) % count)
+            .find(|&index| state.limited_until[index].is_none_or(|until| until <= now));
+        let index = available.unwrap_or_else(|| {
+            (0..count)
+                .min_by_key(|&index| state.limited_until[index])
+                .unwrap_or(0)
+        });
+
+        state.next = (index + 1) % count;
+        self.keys[index].clone()
+    }
+
+    // 是否还有没有被限流的密钥
+    pub fn has_available(&self) -> bool {
+        let now = Instant::now();
+        self.lock()
+            .limited_until
+            .iter()
+            .any(|until| until.is_none_or(|until| until <= now))
+    }
+
+    // 标记密钥被限流，cooldown 内不再优先使用
+    pub fn mark_rate_limited(&self, key: &str, cooldown: Option<Duration>) {
+        let Some(index) = self.keys.iter().position(|candidate| candidate == key) else {
+            return;
+        };
+        let cooldown = cooldown.unwrap_or(DEFAULT_COOLDOWN);
+        self.lock().limited_until[index] = Some(Instant::now() + cooldown);
+        log::warn!(
+            "OpenAI 密钥 {} 被限流，{:?} 内改用其他密钥",
+            mask(key),
+            cooldown
+        );
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, KeyState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+// 日志中只显示密钥末尾几位
+pub fn mask(key: &str) -> String {
+    let tail: String = key
+        .chars()
+        .rev()
+        .take(4)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+    format!("...{}", tail)
+}
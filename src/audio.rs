@@ -0,0 +1,101 @@
+use teloxide::types::Message;
+
+// 转录接口支持的音频扩展名
+const SUPPORTED_EXTENSIONS: [&str; 10] = [
+    "flac", "m4a", "mp3", "mp4", "mpeg", "mpga", "oga", "ogg", "wav", "webm",
+];
+
+// 可以转录的音频文件：语音消息、音频消息或音频类型的文件
+// file_name 和 mime_type 随请求发送给转录接口，用于识别音频格式
+#[derive(Debug, Clone)]
+pub struct AudioFile {
+    pub file_id: String,
+    // 文件大小（字节）
+    pub size: u32,
+    pub file_name: String,
+    pub mime_type: String,
+}
+
+impl AudioFile {
+    // 从消息中取出可以转录的音频，不是音频时返回 None
+    pub fn from_message(msg: &Message) -> Option<AudioFile> {
+        if let Some(voice) = msg.voice() {
+            // 语音消息固定为 OGG/Opus
+            return Some(AudioFile {
+                file_id: voice.file.id.clone(),
+                size: voice.file.size,
+                file_name: "audio.oga".to_string(),
+                mime_type: "audio/ogg".to_string(),
+            });
+        }
+
+        let (file, file_name, mime_type) = if let Some(audio) = msg.audio() {
+            (
+                &audio.file,
+                audio.file_name.as_deref(),
+                audio.mime_type.as_ref().map(|mime| mime.essence_str()),
+            )
+        } else if let Some(document) = msg.document() {
+            (
+                &document.file,
+                document.file_name.as_deref(),
+                document.mime_type.as_ref().map(|mime| mime.essence_str()),
+            )
+        } else {
+            return None;
+        };
+
+        // 优先使用文件名中的扩展名，没有时根据 MIME 类型推断
+        let extension = file_name
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(_, extension)| extension.to_ascii_lowercase())
+            .filter(|extension| SUPPORTED_EXTENSIONS.contains(&extension.as_str()))
+            .or_else(|| mime_type.and_then(extension_for_mime).map(str::to_string));
+
+        // 普通文件只有能识别出音频格式时才处理
+        let is_audio = mime_type.is_some_and(|mime| mime.starts_with("audio/"));
+        let extension = match extension {
+            Some(extension) => extension,
+            None if msg.audio().is_some() || is_audio => "ogg".to_string(),
+            None => return None,
+        };
+
+        Some(AudioFile {
+            file_id: file.id.clone(),
+            size: file.size,
+            file_name: format!("audio.{}", extension),
+            mime_type: mime_type
+                .filter(|mime| mime.starts_with("audio/") || mime.starts_with("video/"))
+                .map(str::to_string)
+                .unwrap_or_else(|| mime_for_extension(&extension).to_string()),
+        })
+    }
+}
+
+// 根据 MIME 类型推断扩展名
+fn extension_for_mime(mime: &str) -> Option<&'static str> {
+    let extension = match mime {
+        "audio/mpeg" | "audio/mp3" => "mp3",
+        "audio/mp4" | "audio/m4a" | "audio/x-m4a" | "audio/aac" => "m4a",
+        "audio/wav" | "audio/x-wav" | "audio/wave" | "audio/vnd.wave" => "wav",
+        "audio/ogg" | "audio/opus" => "ogg",
+        "audio/webm" => "webm",
+        "audio/flac" | "audio/x-flac" => "flac",
+        "video/mp4" => "mp4",
+        _ => return None,
+    };
+    Some(extension)
+}
+
+// 根据扩展名推断 MIME 类型
+fn mime_for_extension(extension: &str) -> &'static str {
+    match extension {
+        "mp3" | "mpeg" | "mpga" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "mp4" => "video/mp4",
+        "wav" => "audio/wav",
+        "webm" => "audio/webm",
+        "flac" => "audio/flac",
+        _ => "audio/ogg",
+    }
+}
@@ -0,0 +1,76 @@
+// /broadcast 通知：向 sessions 中的每个聊天发送同一条消息并统计结果
+use crate::db::DatabasePool;
+use crate::error::AppError;
+use crate::{models, retry};
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::{ApiError, RequestError};
+
+// 两条通知之间的间隔，避免触发 Telegram 每秒约 30 条消息的限制
+const BROADCAST_INTERVAL: Duration = Duration::from_millis(50);
+
+// /broadcast 的发送结果
+#[derive(Debug, PartialEq, Eq)]
+pub struct BroadcastReport {
+    pub sent: u64,
+    // 已屏蔽机器人的聊天，跳过不计为失败
+    pub blocked: u64,
+    pub failed: u64,
+}
+
+impl BroadcastReport {
+    pub fn to_text(&self) -> String {
+        format!(
+            "✅ 通知发送完成\n成功: {}\n已屏蔽机器人: {}\n失败: {}",
+            self.sent, self.blocked, self.failed
+        )
+    }
+}
+
+// 向 sessions 中的每个聊天发送通知；被限流时按 retry_after 等待后重试，单个聊天失败不影响其他聊天
+pub async fn broadcast(
+    bot: &Bot,
+    db_pool: &DatabasePool,
+    caller_id: u64,
+    text: &str,
+) -> Result<BroadcastReport, AppError> {
+    let chat_ids = models::Session::get_all_chat_ids(db_pool).await?;
+    let mut report = BroadcastReport {
+        sent: 0,
+        blocked: 0,
+        failed: 0,
+    };
+
+    for chat_id in chat_ids {
+        let result = retry::retry_with_progress(
+            retry::DEFAULT_MAX_RETRIES,
+            retry::DEFAULT_BASE_DELAY,
+            || bot.send_message(ChatId(chat_id), text).send(),
+            retry::classify_request_error,
+            |_, _| async {},
+        )
+        .await;
+
+        match result {
+            Ok(_) => report.sent += 1,
+            Err(RequestError::Api(ApiError::BotBlocked)) => {
+                log::info!("聊天 {} 已屏蔽机器人，跳过通知", chat_id);
+                report.blocked += 1;
+            }
+            Err(e) => {
+                log::warn!("向聊天 {} 发送通知失败: {:?}", chat_id, e);
+                report.failed += 1;
+            }
+        }
+        tokio::time::sleep(BROADCAST_INTERVAL).await;
+    }
+
+    let details = format!(
+        "成功 {}，已屏蔽 {}，失败 {}",
+        report.sent, report.blocked, report.failed
+    );
+    models::AuditLog::record(db_pool, caller_id, "broadcast", None, Some(&details)).await?;
+    log::info!("超级管理员 {} 发送了通知: {}", caller_id, details);
+
+    Ok(report)
+}
@@ -0,0 +1,56 @@
+// 命令消息的识别和参数解析，main.rs 中的命令处理和 tests/ 中的测试共用
+
+// 消息文本是否是命令（以 "/" 开头）；被编辑的命令消息只记录日志，不会重新执行
+pub fn is_command_text(text: &str) -> bool {
+    text.starts_with('/')
+}
+
+// 解析 /adduser 的参数：第一个词是用户ID，紧接着可以是 @用户名，其余部分作为备注
+pub fn parse_add_user_args(arg: &str) -> Option<(u64, Option<&str>, Option<&str>)> {
+    let mut parts = arg.trim().splitn(2, char::is_whitespace);
+    let user_id = parts.next()?.parse::<u64>().ok()?;
+    let mut rest = parts.next().map(str::trim).unwrap_or_default();
+
+    let mut username = None;
+    if let Some(after_at) = rest.strip_prefix('@') {
+        let mut parts = after_at.splitn(2, char::is_whitespace);
+        username = parts.next().filter(|name| !name.is_empty());
+        rest = parts.next().map(str::trim).unwrap_or_default();
+    }
+
+    let notes = Some(rest).filter(|notes| !notes.is_empty());
+    Some((user_id, username, notes))
+}
+
+// 解析 /translate 的参数：第一个词是目标语言代码，其余部分是要翻译的文本（保留原有的换行）
+pub fn parse_translate_args(arg: &str) -> (Option<&str>, Option<String>) {
+    let arg = arg.trim();
+    let (code, rest) = match arg.split_once(char::is_whitespace) {
+        Some((code, rest)) => (code, rest.trim()),
+        None => (arg, ""),
+    };
+    let code = Some(code).filter(|code| !code.is_empty());
+    let text = Some(rest.to_string()).filter(|text| !text.is_empty());
+    (code, text)
+}
+
+// 校验语言代码格式，例如 en、zh、zh-CN
+pub fn is_valid_language_code(code: &str) -> bool {
+    (2..=10).contains(&code.len()) && code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+// 解析 /removeuser 的参数：用户ID，可以带 --purge 表示彻底删除
+pub fn parse_remove_user_args(arg: &str) -> Option<(u64, bool)> {
+    let mut user_id = None;
+    let mut purge = false;
+    for word in arg.split_whitespace() {
+        if word == "--purge" {
+            purge = true;
+        } else if user_id.is_none() {
+            user_id = Some(word.parse::<u64>().ok()?);
+        } else {
+            return None;
+        }
+    }
+    user_id.map(|user_id| (user_id, purge))
+}
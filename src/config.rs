@@ -0,0 +1,636 @@
+use crate::api_keys::ApiKeys;
+use crate::error::AppError;
+use crate::i18n::Lang;
+use crate::{context, focus, persona, providers, DEFAULT_MODEL};
+use std::env;
+use std::time::Duration;
+
+// 聊天请求使用的 temperature
+pub const TEMPERATURE: f64 = 0.7;
+
+// 每次请求最多查询的历史消息条数的默认值和上限，实际携带的消息再按 token 预算截取
+const DEFAULT_HISTORY_LIMIT: i64 = 10;
+pub const MAX_HISTORY_LIMIT: i64 = 50;
+
+// 转录音频默认的大小上限（20MB）
+const DEFAULT_MAX_AUDIO_BYTES: u64 = 20 * 1024 * 1024;
+
+// 历史消息默认的 token 预算
+const DEFAULT_HISTORY_TOKEN_BUDGET: usize = 3000;
+
+// 读取布尔类型的环境变量，未设置或无法识别时使用默认值
+pub fn env_flag(name: &str, default: bool) -> bool {
+    match env::var(name) {
+        Ok(value) => match value.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => true,
+            "false" | "0" | "no" | "off" => false,
+            _ => default,
+        },
+        Err(_) => default,
+    }
+}
+
+// 每次请求携带的历史消息 token 预算，可通过 HISTORY_TOKEN_BUDGET 配置
+pub fn history_token_budget() -> usize {
+    env::var("HISTORY_TOKEN_BUDGET")
+        .ok()
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .unwrap_or(DEFAULT_HISTORY_TOKEN_BUDGET)
+}
+
+// 每次请求最多查询的历史消息条数（HISTORY_LIMIT），默认 10，限制在 1 到 MAX_HISTORY_LIMIT 之间
+pub fn history_limit() -> i64 {
+    env::var("HISTORY_LIMIT")
+        .ok()
+        .and_then(|value| value.trim().parse::<i64>().ok())
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .clamp(1, MAX_HISTORY_LIMIT)
+}
+
+// 聊天实际使用的历史消息条数：/context 的设置优先，其次是 HISTORY_LIMIT，最后是默认值 10
+pub fn effective_history_limit(chat_limit: Option<i64>) -> i64 {
+    match chat_limit {
+        Some(limit) => limit.clamp(1, MAX_HISTORY_LIMIT),
+        None => history_limit(),
+    }
+}
+
+// 读取密钥：优先使用环境变量 NAME，未设置时从 NAME_FILE 指定的文件读取（去掉首尾空白）
+// 指定了文件但无法读取时返回错误
+pub fn read_secret(name: &str) -> Result<Option<String>, AppError> {
+    if let Ok(value) = env::var(name) {
+        if !value.trim().is_empty() {
+            return Ok(Some(value));
+        }
+    }
+
+    let file_var = format!("{}_FILE", name);
+    match env::var(&file_var) {
+        Ok(path) if !path.trim().is_empty() => {
+            let path = path.trim();
+            let content = std::fs::read_to_string(path).map_err(|e| {
+                AppError::Config(format!("无法读取 {} 指定的文件 {}: {}", file_var, path, e))
+            })?;
+            let secret = content.trim();
+            if secret.is_empty() {
+                return Err(AppError::Config(format!(
+                    "{} 指定的文件 {} 为空",
+                    file_var, path
+                )));
+            }
+            Ok(Some(secret.to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+// 是否启用白名单，默认启用；ENABLE_WHITELIST=false 时所有人都可以使用机器人
+// 兼容旧的 WHITELIST_ENABLED，两者都设置时以 ENABLE_WHITELIST 为准
+pub fn whitelist_enabled() -> bool {
+    if env::var("ENABLE_WHITELIST").is_ok() {
+        env_flag("ENABLE_WHITELIST", true)
+    } else {
+        env_flag("WHITELIST_ENABLED", true)
+    }
+}
+
+// 日志格式（LOG_FORMAT）：pretty（默认，便于阅读）或 json（每行一个 JSON 对象，便于日志系统采集）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+// 读取 LOG_FORMAT，无法识别的值按默认格式处理（此时日志尚未初始化，无法提示）
+pub fn log_format() -> LogFormat {
+    match env::var("LOG_FORMAT") {
+        Ok(format) if format.trim().eq_ignore_ascii_case("json") => LogFormat::Json,
+        _ => LogFormat::Pretty,
+    }
+}
+
+// 接收更新的方式（BOT_MODE）：polling（默认，长轮询）或 webhook
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BotMode {
+    Polling,
+    Webhook { url: reqwest::Url, port: u16 },
+}
+
+// webhook 模式默认监听的端口
+const DEFAULT_WEBHOOK_PORT: u16 = 8443;
+
+// 读取 BOT_MODE；webhook 模式下 WEBHOOK_URL 必须设置且是有效的地址，否则启动失败
+pub fn bot_mode() -> Result<BotMode, AppError> {
+    let mode = env::var("BOT_MODE").unwrap_or_default();
+    match mode.trim().to_ascii_lowercase().as_str() {
+        "" | "polling" => Ok(BotMode::Polling),
+        "webhook" => {
+            let url = env::var("WEBHOOK_URL")
+                .ok()
+                .filter(|url| !url.trim().is_empty())
+                .ok_or_else(|| {
+                    AppError::Config("BOT_MODE=webhook 时必须设置 WEBHOOK_URL".to_string())
+                })?;
+            let url = url.trim().parse::<reqwest::Url>().map_err(|e| {
+                AppError::Config(format!("WEBHOOK_URL 无效 ({}): {}", url.trim(), e))
+            })?;
+            let port = match env::var("WEBHOOK_PORT") {
+                Ok(port) => port.trim().parse::<u16>().map_err(|e| {
+                    AppError::Config(format!("WEBHOOK_PORT 无效 ({}): {}", port.trim(), e))
+                })?,
+                Err(_) => DEFAULT_WEBHOOK_PORT,
+            };
+            Ok(BotMode::Webhook { url, port })
+        }
+        other => Err(AppError::Config(format!(
+            "BOT_MODE 无效: {}，可选值: polling、webhook",
+            other
+        ))),
+    }
+}
+
+// 健康检查服务的端口（HEALTH_PORT），未设置时不启动
+pub fn health_port() -> Option<u16> {
+    env::var("HEALTH_PORT")
+        .ok()
+        .and_then(|value| value.trim().parse::<u16>().ok())
+}
+
+// 指标服务的端口（METRICS_PORT），未设置时不提供 /metrics；与 HEALTH_PORT 相同时共用一个服务
+pub fn metrics_port() -> Option<u16> {
+    env::var("METRICS_PORT")
+        .ok()
+        .and_then(|value| value.trim().parse::<u16>().ok())
+}
+
+// OpenAI 组织ID（OPENAI_ORG_ID），设置后作为 OpenAI-Organization 请求头发送
+pub fn openai_org_id() -> Option<String> {
+    env::var("OPENAI_ORG_ID")
+        .ok()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+// OpenAI 项目ID（OPENAI_PROJECT_ID），设置后作为 OpenAI-Project 请求头发送
+pub fn openai_project_id() -> Option<String> {
+    env::var("OPENAI_PROJECT_ID")
+        .ok()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+// OpenAI 请求的超时时间（OPENAI_TIMEOUT_SECS），默认 60 秒
+pub fn openai_timeout() -> Duration {
+    let secs = env::var("OPENAI_TIMEOUT_SECS")
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(60);
+    Duration::from_secs(secs)
+}
+
+// 白名单和管理员查询结果的缓存时间（秒），默认 60 秒，设置为 0 时不缓存
+pub fn access_cache_ttl() -> Duration {
+    let secs = env::var("ACCESS_CACHE_TTL_SECS")
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .unwrap_or(60);
+    Duration::from_secs(secs)
+}
+
+// 耗时命令（/export、/summary 等）的每用户冷却时间（秒），默认 30 秒，设置为 0 时不限制
+pub fn command_cooldown() -> Duration {
+    let secs = env::var("COMMAND_COOLDOWN_SECS")
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .unwrap_or(30);
+    Duration::from_secs(secs)
+}
+
+// 是否允许模型调用内置工具（ENABLE_TOOLS，如获取当前时间），默认关闭，需要支持 tools 的模型和服务
+pub fn tools_enabled() -> bool {
+    env_flag("ENABLE_TOOLS", false)
+}
+
+// 是否处理图片消息（ENABLE_VISION），默认关闭，需要支持图片输入的模型
+pub fn vision_enabled() -> bool {
+    env_flag("ENABLE_VISION", false)
+}
+
+// 处理图片消息使用的模型，默认 gpt-4o-mini
+pub fn vision_model() -> String {
+    env::var("VISION_MODEL")
+        .ok()
+        .map(|model| model.trim().to_string())
+        .filter(|model| !model.is_empty())
+        .unwrap_or_else(|| DEFAULT_MODEL.to_string())
+}
+
+// 默认的界面语言（UI_LANGUAGE），未设置或不支持时使用中文
+pub fn default_ui_language() -> Lang {
+    env::var("UI_LANGUAGE")
+        .ok()
+        .and_then(|code| Lang::from_code(&code))
+        .unwrap_or(Lang::Zh)
+}
+
+// 是否向管理员显示具体的错误信息（VERBOSE_ERRORS），默认关闭
+pub fn verbose_errors() -> bool {
+    env_flag("VERBOSE_ERRORS", false)
+}
+
+// 是否在数据库出错时降级为不带历史的单轮请求，默认关闭
+pub fn degrade_on_db_error() -> bool {
+    env_flag("DEGRADE_ON_DB_ERROR", false)
+}
+
+// 是否在重试等待期间更新占位消息提示用户，默认关闭
+pub fn show_retry_status() -> bool {
+    env_flag("SHOW_RETRY_STATUS", false)
+}
+
+// 等待回复时是否发送"思考中"的占位消息（SHOW_THINKING），默认开启；关闭时只显示"正在输入"
+pub fn show_thinking() -> bool {
+    env_flag("SHOW_THINKING", true)
+}
+
+// 自定义的占位消息文字（THINKING_TEXT），未设置时使用界面语言对应的"思考中"
+pub fn thinking_text() -> Option<String> {
+    env::var("THINKING_TEXT")
+        .ok()
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+// 自定义的群组欢迎语（GROUP_GREETING），设置为空字符串时不发送；未设置时使用界面语言对应的默认欢迎语
+pub fn group_greeting() -> Option<String> {
+    env::var("GROUP_GREETING").ok()
+}
+
+// 编辑已发送的命令时是否提示用户重新发送，默认静默忽略
+pub fn notify_edited_commands() -> bool {
+    env::var("EDITED_COMMAND_MODE")
+        .map(|mode| mode.eq_ignore_ascii_case("notify"))
+        .unwrap_or(false)
+}
+
+// 用户编辑最新一条提问后是否按编辑后的内容重新回答（REGENERATE_ON_EDIT），默认只更新保存的内容
+pub fn regenerate_on_edit() -> bool {
+    env_flag("REGENERATE_ON_EDIT", false)
+}
+
+// 全局默认回复语言，"auto" 表示跟随用户的输入语言
+pub fn default_reply_language() -> String {
+    env::var("REPLY_LANGUAGE")
+        .ok()
+        .map(|lang| lang.trim().to_string())
+        .filter(|lang| !lang.is_empty())
+        .unwrap_or_else(|| "auto".to_string())
+}
+
+// 通过 DISABLED_COMMANDS（逗号分隔的命令名）禁用的命令
+pub fn disabled_commands() -> Vec<String> {
+    env::var("DISABLED_COMMANDS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().trim_start_matches('/').to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+// 是否需要先征得同意才保存聊天消息，默认关闭
+pub fn privacy_consent_required() -> bool {
+    env_flag("PRIVACY_CONSENT", false)
+}
+
+// 自定义的询问是否同意保存消息的文字（PRIVACY_CONSENT_PROMPT），未设置时使用界面语言对应的默认文字
+pub fn privacy_consent_prompt() -> Option<String> {
+    env::var("PRIVACY_CONSENT_PROMPT")
+        .ok()
+        .filter(|prompt| !prompt.trim().is_empty())
+}
+
+// 是否检测并记录模型拒绝回答的情况，默认关闭
+pub fn detect_refusals() -> bool {
+    env_flag("DETECT_REFUSALS", false)
+}
+
+// 是否处理频道中的消息，默认关闭；关闭时静默忽略
+// 频道消息没有真实用户，开启白名单时按 CHANNEL_ALLOWLIST 检查发送消息的频道
+pub fn channel_posts_enabled() -> bool {
+    env_flag("CHANNEL_POSTS", false)
+}
+
+// 开启白名单时允许使用机器人的频道ID（CHANNEL_ALLOWLIST，逗号分隔，频道ID为负数），默认为空
+pub fn channel_allowlist() -> Vec<i64> {
+    env::var("CHANNEL_ALLOWLIST")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse::<i64>().ok())
+        .collect()
+}
+
+// 每个用户每小时最多的请求次数，未设置或为 0 时不限制；管理员不受限制
+pub fn user_hourly_limit() -> Option<u32> {
+    env::var("USER_HOURLY_LIMIT")
+        .ok()
+        .and_then(|value| value.trim().parse::<u32>().ok())
+        .filter(|limit| *limit > 0)
+}
+
+// 每次回复最多生成的 token 数（OPENAI_MAX_TOKENS），未设置或为 0 时不限制
+pub fn max_tokens() -> Option<u32> {
+    env::var("OPENAI_MAX_TOKENS")
+        .ok()
+        .and_then(|value| value.trim().parse::<u32>().ok())
+        .filter(|max_tokens| *max_tokens > 0)
+}
+
+// 转录音频文件的大小上限（字节），默认 20MB，与 Telegram 机器人可下载的上限一致
+pub fn max_audio_bytes() -> u64 {
+    env::var("MAX_AUDIO_BYTES")
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .filter(|bytes| *bytes > 0)
+        .unwrap_or(DEFAULT_MAX_AUDIO_BYTES)
+}
+
+// 是否按 Telegram MarkdownV2 格式发送AI回复（REPLY_PARSE_MODE=MarkdownV2），默认纯文本
+pub fn markdown_replies() -> bool {
+    env::var("REPLY_PARSE_MODE").is_ok_and(|mode| mode.trim().eq_ignore_ascii_case("MarkdownV2"))
+}
+
+// 附加在每条回复末尾的页脚（如免责声明），默认不附加
+pub fn reply_footer() -> Option<String> {
+    env::var("REPLY_FOOTER")
+        .ok()
+        .map(|footer| footer.trim().to_string())
+        .filter(|footer| !footer.is_empty())
+}
+
+// 语音助手模式使用的语音
+pub fn tts_voice() -> String {
+    env::var("TTS_VOICE").unwrap_or_else(|_| "alloy".to_string())
+}
+
+// 当前生效的运行配置，只记录密钥是否存在，不保存密钥本身
+#[derive(Debug)]
+pub struct Config {
+    pub default_model: &'static str,
+    pub temperature: f64,
+    pub max_tokens: Option<u32>,
+    pub history_limit: i64,
+    pub history_token_budget: usize,
+    pub database_backend: &'static str,
+    pub bot_mode: Result<BotMode, String>,
+    pub log_format: LogFormat,
+    pub health_port: Option<u16>,
+    pub metrics_port: Option<u16>,
+    pub whitelist_enabled: bool,
+    pub access_cache_ttl_secs: u64,
+    pub reply_language: String,
+    pub ui_language: &'static str,
+    pub overflow_policy: context::OverflowPolicy,
+    pub fallback_model: String,
+    pub focus_debounce_secs: u64,
+    pub notify_edited_commands: bool,
+    pub regenerate_on_edit: bool,
+    pub show_retry_status: bool,
+    pub show_thinking: bool,
+    pub thinking_text: Option<String>,
+    pub verbose_errors: bool,
+    pub degrade_on_db_error: bool,
+    pub privacy_consent_required: bool,
+    pub detect_refusals: bool,
+    pub channel_posts: bool,
+    pub channel_allowlist: Vec<i64>,
+    pub reply_footer: Option<String>,
+    pub markdown_replies: bool,
+    pub tools_enabled: bool,
+    pub vision_enabled: bool,
+    pub vision_model: String,
+    pub max_audio_bytes: u64,
+    pub user_hourly_limit: Option<u32>,
+    pub command_cooldown_secs: u64,
+    pub disabled_commands: Vec<String>,
+    pub openai_base_url: String,
+    pub openai_timeout_secs: u64,
+    pub openai_org_id: Option<String>,
+    pub openai_project_id: Option<String>,
+    pub provider_routes: Result<usize, String>,
+    pub chat_personas: Result<usize, String>,
+    pub tts_voice: String,
+    pub openai_key_count: usize,
+    pub has_telegram_token: bool,
+}
+
+impl Config {
+    // 从环境变量读取当前配置
+    pub fn from_env() -> Self {
+        // 只显示数据库类型，连接地址中可能包含密码
+        let database_backend = match env::var("DATABASE_URL") {
+            Ok(url) if url.starts_with("postgres:") => "PostgreSQL",
+            _ => "SQLite",
+        };
+
+        Config {
+            default_model: DEFAULT_MODEL,
+            temperature: TEMPERATURE,
+            max_tokens: max_tokens(),
+            history_limit: history_limit(),
+            history_token_budget: history_token_budget(),
+            database_backend,
+            bot_mode: bot_mode().map_err(|e| e.to_string()),
+            log_format: log_format(),
+            health_port: health_port(),
+            metrics_port: metrics_port(),
+            whitelist_enabled: whitelist_enabled(),
+            access_cache_ttl_secs: access_cache_ttl().as_secs(),
+            reply_language: default_reply_language(),
+            ui_language: default_ui_language().code(),
+            overflow_policy: context::overflow_policy(),
+            fallback_model: context::fallback_model(),
+            focus_debounce_secs: focus::debounce_window().as_secs(),
+            notify_edited_commands: notify_edited_commands(),
+            regenerate_on_edit: regenerate_on_edit(),
+            show_retry_status: show_retry_status(),
+            show_thinking: show_thinking(),
+            thinking_text: thinking_text(),
+            verbose_errors: verbose_errors(),
+            degrade_on_db_error: degrade_on_db_error(),
+            privacy_consent_required: privacy_consent_required(),
+            detect_refusals: detect_refusals(),
+            channel_posts: channel_posts_enabled(),
+            channel_allowlist: channel_allowlist(),
+            reply_footer: reply_footer(),
+            markdown_replies: markdown_replies(),
+            tools_enabled: tools_enabled(),
+            vision_enabled: vision_enabled(),
+            vision_model: vision_model(),
+            max_audio_bytes: max_audio_bytes(),
+            user_hourly_limit: user_hourly_limit(),
+            command_cooldown_secs: command_cooldown().as_secs(),
+            disabled_commands: disabled_commands(),
+            openai_base_url: providers::default_base_url(),
+            openai_timeout_secs: openai_timeout().as_secs(),
+            openai_org_id: openai_org_id(),
+            openai_project_id: openai_project_id(),
+            provider_routes: providers::validate(),
+            chat_personas: persona::validate(),
+            tts_voice: tts_voice(),
+            openai_key_count: match ApiKeys::from_env() {
+                Ok(Some(keys)) => keys.count(),
+                _ => 0,
+            },
+            has_telegram_token: matches!(read_secret("TELEGRAM_BOT_TOKEN"), Ok(Some(_))),
+        }
+    }
+
+    // 格式化为便于查看的文本
+    pub fn to_text(&self) -> String {
+        let on_off = |enabled: bool| if enabled { "开启" } else { "关闭" };
+        let present = |present: bool| if present { "已设置" } else { "未设置" };
+
+        let disabled_commands = if self.disabled_commands.is_empty() {
+            "无".to_string()
+        } else {
+            self.disabled_commands.join(", ")
+        };
+        let provider_routes = match &self.provider_routes {
+            Ok(0) => "未配置（全部使用默认接口）".to_string(),
+            Ok(count) => format!("{} 条", count),
+            Err(e) => format!("配置无效: {}", e),
+        };
+        let bot_mode = match &self.bot_mode {
+            Ok(BotMode::Polling) => "长轮询".to_string(),
+            Ok(BotMode::Webhook { url, port }) => format!("webhook（{}，端口 {}）", url, port),
+            Err(e) => format!("配置无效: {}", e),
+        };
+        let chat_personas = match &self.chat_personas {
+            Ok(count) => format!("{} 个聊天", count),
+            Err(e) => format!("配置无效: {}", e),
+        };
+
+        let lines = [
+            format!("默认模型: {}", self.default_model),
+            format!("temperature: {}", self.temperature),
+            format!(
+                "单次回复 token 上限: {}",
+                self.max_tokens
+                    .map(|max_tokens| max_tokens.to_string())
+                    .unwrap_or_else(|| "不限制".to_string())
+            ),
+            format!("历史消息条数上限: {}", self.history_limit),
+            format!("历史消息 token 预算: {}", self.history_token_budget),
+            format!("数据库: {}", self.database_backend),
+            format!("接收更新方式: {}", bot_mode),
+            format!(
+                "日志格式: {}",
+                match self.log_format {
+                    LogFormat::Pretty => "pretty",
+                    LogFormat::Json => "json",
+                }
+            ),
+            format!(
+                "健康检查端口: {}",
+                self.health_port
+                    .map(|port| port.to_string())
+                    .unwrap_or_else(|| "未启用".to_string())
+            ),
+            format!(
+                "指标端口: {}",
+                self.metrics_port
+                    .map(|port| port.to_string())
+                    .unwrap_or_else(|| "未启用".to_string())
+            ),
+            format!("白名单: {}", on_off(self.whitelist_enabled)),
+            format!("权限缓存时间: {} 秒", self.access_cache_ttl_secs),
+            format!("默认回复语言: {}", self.reply_language),
+            format!("默认界面语言: {}", self.ui_language),
+            format!("上下文超限处理: {:?}", self.overflow_policy),
+            format!("备用模型: {}", self.fallback_model),
+            format!("专注模式合并等待: {} 秒", self.focus_debounce_secs),
+            format!("编辑命令提示: {}", on_off(self.notify_edited_commands)),
+            format!("编辑提问后重新回答: {}", on_off(self.regenerate_on_edit)),
+            format!("重试提示: {}", on_off(self.show_retry_status)),
+            format!(
+                "思考中提示: {}",
+                match (self.show_thinking, &self.thinking_text) {
+                    (false, _) => "关闭".to_string(),
+                    (true, Some(text)) => format!("开启（{}）", text),
+                    (true, None) => "开启（默认文字）".to_string(),
+                }
+            ),
+            format!("管理员错误详情: {}", on_off(self.verbose_errors)),
+            format!("数据库出错时降级: {}", on_off(self.degrade_on_db_error)),
+            format!("隐私同意: {}", on_off(self.privacy_consent_required)),
+            format!("拒绝回答检测: {}", on_off(self.detect_refusals)),
+            format!("频道消息: {}", on_off(self.channel_posts)),
+            format!(
+                "允许的频道: {}",
+                if self.channel_allowlist.is_empty() {
+                    "无".to_string()
+                } else {
+                    self.channel_allowlist
+                        .iter()
+                        .map(|id| id.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                }
+            ),
+            format!(
+                "每用户每小时请求上限: {}",
+                self.user_hourly_limit
+                    .map(|limit| limit.to_string())
+                    .unwrap_or_else(|| "不限制".to_string())
+            ),
+            format!(
+                "耗时命令冷却时间: {}",
+                if self.command_cooldown_secs == 0 {
+                    "不限制".to_string()
+                } else {
+                    format!("{} 秒", self.command_cooldown_secs)
+                }
+            ),
+            format!("回复页脚: {}", self.reply_footer.as_deref().unwrap_or("无")),
+            format!(
+                "回复格式: {}",
+                if self.markdown_replies {
+                    "MarkdownV2"
+                } else {
+                    "纯文本"
+                }
+            ),
+            format!("音频大小上限: {}", format_megabytes(self.max_audio_bytes)),
+            format!("工具调用: {}", on_off(self.tools_enabled)),
+            format!(
+                "图片理解: {}（模型 {}）",
+                on_off(self.vision_enabled),
+                self.vision_model
+            ),
+            format!("禁用的命令: {}", disabled_commands),
+            format!("OpenAI 接口地址: {}", self.openai_base_url),
+            format!("OpenAI 请求超时: {} 秒", self.openai_timeout_secs),
+            format!(
+                "OpenAI 组织ID: {}",
+                self.openai_org_id.as_deref().unwrap_or("未设置")
+            ),
+            format!(
+                "OpenAI 项目ID: {}",
+                self.openai_project_id.as_deref().unwrap_or("未设置")
+            ),
+            format!("模型路由: {}", provider_routes),
+            format!("聊天人设: {}", chat_personas),
+            format!("语音助手语音: {}", self.tts_voice),
+            format!("OpenAI 密钥: {} 个", self.openai_key_count),
+            format!("TELEGRAM_BOT_TOKEN: {}", present(self.has_telegram_token)),
+        ];
+
+        format!("当前生效的配置:\n\n{}", lines.join("\n"))
+    }
+}
+
+// 将字节数格式化为 MB
+pub fn format_megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
@@ -0,0 +1,172 @@
+use serde_json::Value;
+use std::env;
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::CoreBPE;
+
+// 为模型回复预留的 token 数
+pub const REPLY_RESERVE_TOKENS: usize = 1024;
+
+// 自动总结时保留原文的最近消息条数
+pub const KEEP_RECENT_MESSAGES: usize = 4;
+
+// 分段总结时为总结指令预留的 token 数
+const SUMMARY_PROMPT_TOKENS: usize = 256;
+
+// 未配置上限的模型使用的默认上下文长度
+const UNKNOWN_MODEL_CONTEXT_LIMIT: usize = 8192;
+
+// 内置的模型上下文长度
+const DEFAULT_CONTEXT_LIMITS: &[(&str, usize)] = &[
+    ("gpt-4o", 128_000),
+    ("gpt-4o-mini", 128_000),
+    ("gpt-4-turbo", 128_000),
+];
+
+// 上下文超出模型上限时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // 总结较早的历史消息
+    Summarize,
+    // 切换到更大上下文的模型
+    Upgrade,
+    // 直接返回错误
+    Error,
+}
+
+// 从 ON_CONTEXT_OVERFLOW 读取处理方式，默认总结
+pub fn overflow_policy() -> OverflowPolicy {
+    match env::var("ON_CONTEXT_OVERFLOW") {
+        Ok(value) => match value.trim().to_lowercase().as_str() {
+            "summarize" | "" => OverflowPolicy::Summarize,
+            "upgrade" => OverflowPolicy::Upgrade,
+            "error" => OverflowPolicy::Error,
+            other => {
+                log::warn!("无效的 ON_CONTEXT_OVERFLOW: {}，使用 summarize", other);
+                OverflowPolicy::Summarize
+            }
+        },
+        Err(_) => OverflowPolicy::Summarize,
+    }
+}
+
+// 上下文超限时切换到的模型
+pub fn fallback_model() -> String {
+    env::var("CONTEXT_FALLBACK_MODEL").unwrap_or_else(|_| "gpt-4o".to_string())
+}
+
+// 模型的上下文长度，可通过 MODEL_CONTEXT_LIMITS=模型=长度,模型=长度 覆盖
+pub fn context_limit(model: &str) -> usize {
+    if let Ok(config) = env::var("MODEL_CONTEXT_LIMITS") {
+        for entry in config.split(',') {
+            if let Some((name, limit)) = entry.split_once('=') {
+                if name.trim() == model {
+                    match limit.trim().parse::<usize>() {
+                        Ok(limit) => return limit,
+                        Err(_) => log::warn!("无效的模型上下文长度配置: {}", entry),
+                    }
+                }
+            }
+        }
+    }
+
+    DEFAULT_CONTEXT_LIMITS
+        .iter()
+        .find(|(name, _)| *name == model)
+        .map(|(_, limit)| *limit)
+        .unwrap_or(UNKNOWN_MODEL_CONTEXT_LIMIT)
+}
+
+// 使用模型对应的分词器计算 token 数，第二个返回值表示是否为精确计数
+// 没有对应分词器的模型退回到粗略估算
+pub fn count_tokens(text: &str, model: &str) -> (usize, bool) {
+    let bpe: Option<&CoreBPE> = match get_tokenizer(model) {
+        Some(Tokenizer::O200kBase) => Some(tiktoken_rs::o200k_base_singleton()),
+        Some(Tokenizer::Cl100kBase) => Some(tiktoken_rs::cl100k_base_singleton()),
+        Some(Tokenizer::P50kBase) => Some(tiktoken_rs::p50k_base_singleton()),
+        Some(Tokenizer::P50kEdit) => Some(tiktoken_rs::p50k_edit_singleton()),
+        Some(Tokenizer::R50kBase) | Some(Tokenizer::Gpt2) => {
+            Some(tiktoken_rs::r50k_base_singleton())
+        }
+        None => None,
+    };
+
+    match bpe {
+        Some(bpe) => (bpe.encode_with_special_tokens(text).len(), true),
+        None => (estimate_tokens(text), false),
+    }
+}
+
+// 粗略估算文本的 token 数：ASCII 约 4 个字符一个 token，其他字符（如中文）按一个字符一个 token
+pub fn estimate_tokens(text: &str) -> usize {
+    let ascii = text.chars().filter(|c| c.is_ascii()).count();
+    let other = text.chars().count() - ascii;
+    ascii.div_ceil(4) + other
+}
+
+// 估算单条聊天消息的 token 数，额外计入少量格式开销
+pub fn estimate_message_tokens(content: &str) -> usize {
+    estimate_tokens(content) + 4
+}
+
+// 估算一组聊天消息的 token 数
+pub fn estimate_messages_tokens(messages: &[Value]) -> usize {
+    messages
+        .iter()
+        .map(|message| estimate_message_tokens(message["content"].as_str().unwrap_or_default()))
+        .sum()
+}
+
+// 判断消息是否超出模型可用的上下文长度（已扣除回复预留）
+pub fn exceeds_limit(messages: &[Value], model: &str) -> bool {
+    estimate_messages_tokens(messages) + REPLY_RESERVE_TOKENS > context_limit(model)
+}
+
+// 总结对话时每段对话记录可用的 token 数（已扣除回复和总结指令的预留）
+pub fn summary_chunk_budget(model: &str) -> usize {
+    context_limit(model)
+        .saturating_sub(REPLY_RESERVE_TOKENS + SUMMARY_PROMPT_TOKENS)
+        .max(SUMMARY_PROMPT_TOKENS)
+}
+
+// 将对话记录按行分为若干段，每段的估算 token 数不超过 token_budget，用于分段总结过长的对话
+// 单行超出预算时截断到预算以内
+pub fn split_transcript(lines: &[String], token_budget: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut used = 0;
+    for line in lines {
+        let line = truncate_to_tokens(line, token_budget);
+        // 换行符按一个 token 计算
+        let tokens = estimate_tokens(line) + 1;
+        if !current.is_empty() && used + tokens > token_budget {
+            chunks.push(std::mem::take(&mut current));
+            used = 0;
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+        used += tokens;
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+// 截取文本开头估算 token 数不超过 token_budget 的部分
+fn truncate_to_tokens(text: &str, token_budget: usize) -> &str {
+    let mut ascii = 0;
+    let mut other = 0;
+    for (index, c) in text.char_indices() {
+        if c.is_ascii() {
+            ascii += 1;
+        } else {
+            other += 1;
+        }
+        if usize::div_ceil(ascii, 4) + other > token_budget {
+            return &text[..index];
+        }
+    }
+    text
+}
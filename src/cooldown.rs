@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// 耗时命令的每用户冷却时间，只保存在内存中，重启后清空
+// 按（用户, 命令名）分别计时，用户在冷却期间仍然可以使用其他命令
+#[derive(Clone)]
+pub struct CommandCooldowns {
+    cooldown: Duration,
+    last_used: Arc<Mutex<HashMap<(u64, &'static str), Instant>>>,
+}
+
+impl CommandCooldowns {
+    // cooldown 为 0 时不限制
+    pub fn new(cooldown: Duration) -> Self {
+        CommandCooldowns {
+            cooldown,
+            last_used: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // 还需等待的时间，冷却已结束时返回 None；只检查，不记录使用
+    pub fn remaining(&self, user_id: u64, command: &'static str) -> Option<Duration> {
+        if self.cooldown.is_zero() {
+            return None;
+        }
+
+        let used_at = *self.lock().get(&(user_id, command))?;
+        self.cooldown.checked_sub(used_at.elapsed())
+    }
+
+    // 记录一次使用，从现在开始冷却；在命令真正开始耗时的工作后调用，
+    // 参数错误、没有权限等提前返回的情况不占用冷却时间
+    pub fn mark_used(&self, user_id: u64, command: &'static str) {
+        if self.cooldown.is_zero() {
+            return;
+        }
+
+        let mut last_used = self.lock();
+        let now = Instant::now();
+        // 顺便清理已经结束的冷却，避免记录无限增长
+        last_used.retain(|_, used_at| now.duration_since(*used_at) < self.cooldown);
+        last_used.insert((user_id, command), now);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(u64, &'static str), Instant>> {
+        match self.last_used.lock() {
+            Ok(last_used) => last_used,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
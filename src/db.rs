@@ -1,6 +1,15 @@
-use sqlx::{Error as SqlxError, Pool, Postgres, Sqlite};
+use crate::i18n::{t, Key, Lang};
+use arc_swap::ArcSwap;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use futures_util::{Stream, TryStreamExt};
+use sqlx::postgres::PgRow;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteRow};
+use sqlx::{Column, Error as SqlxError, Pool, Postgres, Row, Sqlite, TypeInfo, ValueRef};
 use std::env;
 use std::error::Error;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub enum DatabasePool {
@@ -21,6 +30,261 @@ impl DatabasePool {
         }
         Ok(())
     }
+
+    // 检查连接是否可用
+    pub async fn ping(&self) -> Result<(), SqlxError> {
+        self.execute("SELECT 1").await
+    }
+
+    // 执行只读查询（/sql 使用），每个值都转换为字符串，最多保留 max_rows 行
+    // 不是单条 SELECT 语句时直接拒绝；PostgreSQL 还会在只读事务中执行
+    pub async fn query_rows(
+        &self,
+        sql: &str,
+        max_rows: usize,
+    ) -> Result<QueryRows, Box<dyn Error + Send + Sync>> {
+        let sql = read_only_statement(sql).ok_or("只允许执行单条 SELECT 查询")?;
+
+        match self {
+            DatabasePool::Sqlite(pool) => {
+                collect_rows(sqlx::query(sql).fetch(pool), max_rows, sqlite_cell).await
+            }
+            DatabasePool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                sqlx::query("SET TRANSACTION READ ONLY")
+                    .execute(&mut *tx)
+                    .await?;
+                let result =
+                    collect_rows(sqlx::query(sql).fetch(&mut *tx), max_rows, postgres_cell).await;
+                tx.rollback().await?;
+                result
+            }
+        }
+    }
+}
+
+// 逐行读取查询结果，只保留前 max_rows 行，其余的行只计数，避免大表整个加载到内存
+async fn collect_rows<R: Row>(
+    mut stream: impl Stream<Item = Result<R, SqlxError>> + Unpin,
+    max_rows: usize,
+    cell: fn(&R, usize) -> String,
+) -> Result<QueryRows, Box<dyn Error + Send + Sync>> {
+    let mut columns = Vec::new();
+    let mut rows = Vec::new();
+    let mut omitted = 0;
+    while let Some(row) = stream.try_next().await? {
+        if rows.is_empty() && omitted == 0 {
+            columns = column_names(&row);
+        }
+        if rows.len() < max_rows {
+            rows.push((0..row.len()).map(|i| cell(&row, i)).collect());
+        } else {
+            omitted += 1;
+        }
+    }
+
+    Ok(QueryRows {
+        columns,
+        rows,
+        omitted,
+    })
+}
+
+// query_rows 的结果，omitted 为超出行数上限没有返回的行数
+#[derive(Debug)]
+pub struct QueryRows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    pub omitted: usize,
+}
+
+// /sql 表格中每个单元格最多显示的字符数
+const MAX_SQL_CELL_CHARS: usize = 40;
+
+// /sql 表格的最大长度，留出余量给转义字符和省略提示，避免超出 Telegram 单条消息上限
+const MAX_SQL_TABLE_CHARS: usize = 3000;
+
+// 格式化后的表格，shown 为表格中的行数，omitted 为没有显示的行数（包括查询时就没有保留的行）
+#[derive(Debug)]
+pub struct QueryTable {
+    pub text: String,
+    pub shown: usize,
+    pub omitted: usize,
+}
+
+impl QueryRows {
+    // 格式化为按列对齐的表格，单元格过长时截断；表格超出长度上限时继续减少显示的行数
+    pub fn to_table(&self) -> QueryTable {
+        let clip = |value: &str| -> String {
+            // 换行会打乱表格，替换为空格
+            let value = value.replace(['\n', '\r'], " ");
+            if value.chars().count() > MAX_SQL_CELL_CHARS {
+                let clipped: String = value.chars().take(MAX_SQL_CELL_CHARS - 1).collect();
+                format!("{}…", clipped)
+            } else {
+                value
+            }
+        };
+        let header: Vec<String> = self.columns.iter().map(|name| clip(name)).collect();
+        let rows: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|value| clip(value)).collect())
+            .collect();
+
+        let mut shown = rows.len();
+        loop {
+            let mut widths: Vec<usize> = header.iter().map(|name| name.chars().count()).collect();
+            for row in &rows[..shown] {
+                for (width, value) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(value.chars().count());
+                }
+            }
+            let format_line = |cells: &[String]| -> String {
+                cells
+                    .iter()
+                    .zip(&widths)
+                    .map(|(value, width)| {
+                        let padding = width - value.chars().count();
+                        format!("{}{}", value, " ".repeat(padding))
+                    })
+                    .collect::<Vec<String>>()
+                    .join(" | ")
+                    .trim_end()
+                    .to_string()
+            };
+
+            let mut lines = vec![format_line(&header)];
+            lines.push(
+                widths
+                    .iter()
+                    .map(|width| "-".repeat(*width))
+                    .collect::<Vec<String>>()
+                    .join("-+-"),
+            );
+            lines.extend(rows[..shown].iter().map(|row| format_line(row)));
+            let table = lines.join("\n");
+
+            if table.chars().count() <= MAX_SQL_TABLE_CHARS || shown <= 1 {
+                return QueryTable {
+                    text: table,
+                    shown,
+                    omitted: self.rows.len() - shown + self.omitted,
+                };
+            }
+            shown -= 1;
+        }
+    }
+}
+
+impl QueryTable {
+    // 表格下方的行数说明
+    pub fn note(&self, lang: Lang) -> String {
+        if self.omitted > 0 {
+            t(lang, Key::SqlRowsOmitted)
+                .replace("{shown}", &self.shown.to_string())
+                .replace("{omitted}", &self.omitted.to_string())
+        } else {
+            t(lang, Key::SqlRowCount).replace("{count}", &self.shown.to_string())
+        }
+    }
+}
+
+// 简单的关键字检查：去掉首尾空白和末尾的分号后，必须以 SELECT 开头且不包含其他语句
+// 返回可以执行的语句，不符合要求时返回 None
+pub fn read_only_statement(sql: &str) -> Option<&str> {
+    let sql = sql.trim().trim_end_matches(';').trim_end();
+    let keyword = sql.split_whitespace().next()?;
+    if !keyword.eq_ignore_ascii_case("select") || sql.contains(';') {
+        return None;
+    }
+    Some(sql)
+}
+
+// 列名，查询没有返回任何行时无法取得
+fn column_names<R: Row>(row: &R) -> Vec<String> {
+    row.columns()
+        .iter()
+        .map(|column| column.name().to_string())
+        .collect()
+}
+
+// SQLite 的值按实际存储类型转换为字符串
+fn sqlite_cell(row: &SqliteRow, index: usize) -> String {
+    if row.try_get_raw(index).is_ok_and(|value| value.is_null()) {
+        return "NULL".to_string();
+    }
+    if let Ok(value) = row.try_get::<String, _>(index) {
+        return value;
+    }
+    if let Ok(value) = row.try_get::<i64, _>(index) {
+        return value.to_string();
+    }
+    if let Ok(value) = row.try_get::<f64, _>(index) {
+        return value.to_string();
+    }
+    if let Ok(value) = row.try_get::<Vec<u8>, _>(index) {
+        return format!("<{} 字节>", value.len());
+    }
+    format!("<{}>", row.columns()[index].type_info().name())
+}
+
+// PostgreSQL 的值按列类型转换为字符串，不支持的类型显示类型名
+fn postgres_cell(row: &PgRow, index: usize) -> String {
+    if row.try_get_raw(index).is_ok_and(|value| value.is_null()) {
+        return "NULL".to_string();
+    }
+    if let Ok(value) = row.try_get::<String, _>(index) {
+        return value;
+    }
+    if let Ok(value) = row.try_get::<i64, _>(index) {
+        return value.to_string();
+    }
+    if let Ok(value) = row.try_get::<i32, _>(index) {
+        return value.to_string();
+    }
+    if let Ok(value) = row.try_get::<i16, _>(index) {
+        return value.to_string();
+    }
+    if let Ok(value) = row.try_get::<f64, _>(index) {
+        return value.to_string();
+    }
+    if let Ok(value) = row.try_get::<f32, _>(index) {
+        return value.to_string();
+    }
+    if let Ok(value) = row.try_get::<bool, _>(index) {
+        return value.to_string();
+    }
+    if let Ok(value) = row.try_get::<DateTime<Utc>, _>(index) {
+        return value.to_rfc3339();
+    }
+    if let Ok(value) = row.try_get::<NaiveDateTime, _>(index) {
+        return value.to_string();
+    }
+    if let Ok(value) = row.try_get::<NaiveDate, _>(index) {
+        return value.to_string();
+    }
+    if let Ok(value) = row.try_get::<serde_json::Value, _>(index) {
+        return value.to_string();
+    }
+    if let Ok(value) = row.try_get::<Vec<u8>, _>(index) {
+        return format!("<{} 字节>", value.len());
+    }
+    format!("<{}>", row.columns()[index].type_info().name())
+}
+
+// 可在运行时替换的连接池，每次处理消息时取出当前的连接池使用
+pub type SharedPool = Arc<ArcSwap<DatabasePool>>;
+
+// 重新建立连接池，验证可用后再替换
+// 正在执行的查询持有旧连接池的引用，会在旧连接池上完成
+pub async fn reconnect(shared: &SharedPool) -> Result<(), Box<dyn Error + Send + Sync>> {
+    log::info!("正在重新连接数据库");
+    let pool = init_db().await?;
+    pool.ping().await?;
+    shared.store(Arc::new(pool));
+    log::info!("数据库连接池已替换");
+    Ok(())
 }
 
 // 初始化数据库
@@ -31,137 +295,566 @@ pub async fn init_db() -> Result<DatabasePool, Box<dyn Error + Send + Sync>> {
 
     log::info!("正在连接数据库: {}", database_url);
 
-    // 判断使用哪种数据库
-    if database_url.starts_with("postgres:") {
-        // PostgreSQL
-        let pool = sqlx::postgres::PgPoolOptions::new()
-            .max_connections(5)
-            .connect(&database_url)
-            .await?;
+    connect(&database_url).await
+}
 
-        // 创建表
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS sessions (
-                id SERIAL PRIMARY KEY,
-                chat_id BIGINT NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            )",
-        )
-        .execute(&pool)
-        .await?;
+// 连接指定的数据库并执行尚未执行的迁移
+pub async fn connect(database_url: &str) -> Result<DatabasePool, Box<dyn Error + Send + Sync>> {
+    let options = PoolConfig::from_env();
+    log::info!(
+        "数据库连接池: 最大连接数 {}，获取连接超时 {} 秒，建立连接超时 {} 秒",
+        options.max_connections,
+        options.acquire_timeout.as_secs(),
+        options.connect_timeout.as_secs()
+    );
 
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS messages (
-                id SERIAL PRIMARY KEY,
-                session_id INTEGER NOT NULL,
-                role TEXT NOT NULL,
-                content TEXT NOT NULL,
-                timestamp TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                FOREIGN KEY (session_id) REFERENCES sessions(id)
-            )",
+    // 判断使用哪种数据库
+    // 建立连接的超时通过 tokio::time::timeout 实现，连接池本身只支持获取连接的超时
+    let pool = if database_url.starts_with("postgres:") {
+        let connecting = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(options.max_connections)
+            .acquire_timeout(options.acquire_timeout)
+            .connect(database_url);
+        DatabasePool::Postgres(
+            tokio::time::timeout(options.connect_timeout, connecting)
+                .await
+                .map_err(|_| "连接数据库超时")??,
         )
-        .execute(&pool)
-        .await?;
+    } else {
+        // 每个连接都开启外键约束，并使用 WAL 日志模式，读写可以并发进行
+        let connect_options = SqliteConnectOptions::from_str(database_url)?
+            .foreign_keys(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        // 内存数据库（如测试使用的 sqlite::memory:）每个连接都是独立的空库，
+        // 只保留一个不会过期的连接，所有查询才能看到同一份数据
+        let mut pool_options = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(options.max_connections)
+            .acquire_timeout(options.acquire_timeout);
+        if is_sqlite_memory(database_url) {
+            pool_options = pool_options
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None);
+        }
+        let connecting = pool_options.connect_with(connect_options);
+        DatabasePool::Sqlite(
+            tokio::time::timeout(options.connect_timeout, connecting)
+                .await
+                .map_err(|_| "连接数据库超时")??,
+        )
+    };
+
+    init_schema(&pool).await?;
+
+    match pool {
+        DatabasePool::Sqlite(_) => log::info!("SQLite 数据库初始化完成"),
+        DatabasePool::Postgres(_) => log::info!("PostgreSQL 数据库初始化完成"),
+    }
+    Ok(pool)
+}
+
+// 在已经建立的连接池上创建表结构（执行尚未执行的迁移）并添加 ADMIN_USER_IDS 中的初始管理员
+// 测试可以用自己创建的连接池调用
+pub async fn init_schema(pool: &DatabasePool) -> Result<(), Box<dyn Error + Send + Sync>> {
+    run_migrations(pool).await?;
+
+    // 添加初始管理员
+    add_initial_admins(pool).await
+}
+
+// 是否是 SQLite 内存数据库地址
+fn is_sqlite_memory(database_url: &str) -> bool {
+    database_url.contains(":memory:") || database_url.contains("mode=memory")
+}
+
+// 连接池设置，从 DB_MAX_CONNECTIONS、DB_ACQUIRE_TIMEOUT_SECS 和 DB_CONNECT_TIMEOUT_SECS 读取
+struct PoolConfig {
+    max_connections: u32,
+    acquire_timeout: Duration,
+    connect_timeout: Duration,
+}
+
+impl PoolConfig {
+    fn from_env() -> Self {
+        let read = |name: &str, default: u64| {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(default)
+        };
+
+        PoolConfig {
+            max_connections: read("DB_MAX_CONNECTIONS", 5).min(u32::MAX as u64) as u32,
+            acquire_timeout: Duration::from_secs(read("DB_ACQUIRE_TIMEOUT_SECS", 30)),
+            connect_timeout: Duration::from_secs(read("DB_CONNECT_TIMEOUT_SECS", 10)),
+        }
+    }
+}
+
+// 一次数据库结构迁移，两种数据库各自的 SQL 按顺序在同一个事务中执行
+struct Migration {
+    version: i64,
+    description: &'static str,
+    sqlite: &'static [&'static str],
+    postgres: &'static [&'static str],
+}
 
-        // 创建白名单表
-        sqlx::query(
+// 按版本号排列的迁移，已发布的迁移不要修改，新的结构变更追加到末尾
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "基线表结构",
+        sqlite: &[
+            "CREATE TABLE IF NOT EXISTS sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            chat_id INTEGER NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT (datetime('now','localtime')),
+            show_timestamps INTEGER DEFAULT 0,
+            reply_lang TEXT,
+            voice_assistant INTEGER DEFAULT 0,
+            privacy_consent TEXT,
+            model TEXT,
+            temperature REAL
+        )",
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_sessions_chat_id ON sessions (chat_id)",
+            "CREATE TABLE IF NOT EXISTS messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            session_id INTEGER NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            timestamp TIMESTAMP DEFAULT (datetime('now','localtime')),
+            source TEXT,
+            model TEXT,
+            latency_ms BIGINT,
+            prompt_tokens BIGINT,
+            completion_tokens BIGINT,
+            FOREIGN KEY (session_id) REFERENCES sessions(id)
+        )",
             "CREATE TABLE IF NOT EXISTS whitelist_users (
-                id SERIAL PRIMARY KEY,
-                user_id BIGINT NOT NULL UNIQUE,
-                username TEXT,
-                added_by BIGINT NOT NULL,
-                added_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                notes TEXT
-            )",
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL UNIQUE,
+            username TEXT,
+            added_by INTEGER NOT NULL,
+            added_at TIMESTAMP DEFAULT (datetime('now','localtime')),
+            notes TEXT
+        )",
+            "CREATE TABLE IF NOT EXISTS admins (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL UNIQUE,
+            username TEXT,
+            is_super INTEGER DEFAULT 0,
+            added_at TIMESTAMP DEFAULT (datetime('now','localtime'))
+        )",
+            "CREATE TABLE IF NOT EXISTS finish_reason_counts (
+            model TEXT NOT NULL,
+            finish_reason TEXT NOT NULL,
+            count INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (model, finish_reason)
+        )",
+            "CREATE TABLE IF NOT EXISTS refusals (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            chat_id INTEGER NOT NULL,
+            model TEXT NOT NULL,
+            prompt TEXT NOT NULL,
+            reply TEXT NOT NULL,
+            created_at TIMESTAMP DEFAULT (datetime('now','localtime'))
+        )",
+            "CREATE TABLE IF NOT EXISTS user_preferences (
+            user_id INTEGER PRIMARY KEY,
+            model TEXT,
+            updated_at TIMESTAMP DEFAULT (datetime('now','localtime'))
+        )",
+            // window_start 为 Unix 时间戳，单位秒
+            "CREATE TABLE IF NOT EXISTS rate_limits (
+            user_id INTEGER PRIMARY KEY,
+            window_start INTEGER NOT NULL,
+            count INTEGER NOT NULL
+        )",
+            "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            actor_id INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            target_id INTEGER,
+            details TEXT,
+            created_at TIMESTAMP DEFAULT (datetime('now','localtime'))
+        )",
+        ],
+        postgres: &[
+            "CREATE TABLE IF NOT EXISTS sessions (
+            id BIGSERIAL PRIMARY KEY,
+            chat_id BIGINT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            show_timestamps BOOLEAN DEFAULT FALSE,
+            reply_lang TEXT,
+            voice_assistant BOOLEAN DEFAULT FALSE,
+            privacy_consent TEXT,
+            model TEXT,
+            temperature DOUBLE PRECISION
+        )",
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_sessions_chat_id ON sessions (chat_id)",
+            "CREATE TABLE IF NOT EXISTS messages (
+            id BIGSERIAL PRIMARY KEY,
+            session_id BIGINT NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            timestamp TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            source TEXT,
+            model TEXT,
+            latency_ms BIGINT,
+            prompt_tokens BIGINT,
+            completion_tokens BIGINT,
+            FOREIGN KEY (session_id) REFERENCES sessions(id)
+        )",
+            "CREATE TABLE IF NOT EXISTS whitelist_users (
+            id SERIAL PRIMARY KEY,
+            user_id BIGINT NOT NULL UNIQUE,
+            username TEXT,
+            added_by BIGINT NOT NULL,
+            added_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            notes TEXT
+        )",
+            "CREATE TABLE IF NOT EXISTS admins (
+            id SERIAL PRIMARY KEY,
+            user_id BIGINT NOT NULL UNIQUE,
+            username TEXT,
+            is_super BOOLEAN DEFAULT FALSE,
+            added_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+            "CREATE TABLE IF NOT EXISTS finish_reason_counts (
+            model TEXT NOT NULL,
+            finish_reason TEXT NOT NULL,
+            count BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY (model, finish_reason)
+        )",
+            "CREATE TABLE IF NOT EXISTS refusals (
+            id BIGSERIAL PRIMARY KEY,
+            chat_id BIGINT NOT NULL,
+            model TEXT NOT NULL,
+            prompt TEXT NOT NULL,
+            reply TEXT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+            "CREATE TABLE IF NOT EXISTS user_preferences (
+            user_id BIGINT PRIMARY KEY,
+            model TEXT,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+            // window_start 为 Unix 时间戳，单位秒
+            "CREATE TABLE IF NOT EXISTS rate_limits (
+            user_id BIGINT PRIMARY KEY,
+            window_start BIGINT NOT NULL,
+            count BIGINT NOT NULL
+        )",
+            "CREATE TABLE IF NOT EXISTS audit_log (
+            id BIGSERIAL PRIMARY KEY,
+            actor_id BIGINT NOT NULL,
+            action TEXT NOT NULL,
+            target_id BIGINT,
+            details TEXT,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "会话界面语言",
+        sqlite: &["ALTER TABLE sessions ADD COLUMN ui_lang TEXT"],
+        postgres: &["ALTER TABLE sessions ADD COLUMN IF NOT EXISTS ui_lang TEXT"],
+    },
+    Migration {
+        version: 3,
+        description: "白名单用户停用标记",
+        sqlite: &["ALTER TABLE whitelist_users ADD COLUMN is_active INTEGER NOT NULL DEFAULT 1"],
+        postgres: &[
+            "ALTER TABLE whitelist_users ADD COLUMN IF NOT EXISTS is_active BOOLEAN NOT NULL DEFAULT TRUE",
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "消息对应的 Telegram 消息ID",
+        sqlite: &["ALTER TABLE messages ADD COLUMN tg_message_id INTEGER"],
+        postgres: &["ALTER TABLE messages ADD COLUMN IF NOT EXISTS tg_message_id BIGINT"],
+    },
+    Migration {
+        version: 5,
+        description: "会话历史消息条数上限",
+        sqlite: &["ALTER TABLE sessions ADD COLUMN history_limit INTEGER"],
+        postgres: &["ALTER TABLE sessions ADD COLUMN IF NOT EXISTS history_limit BIGINT"],
+    },
+    Migration {
+        version: 6,
+        description: "会话助手名称",
+        sqlite: &["ALTER TABLE sessions ADD COLUMN bot_name TEXT"],
+        postgres: &["ALTER TABLE sessions ADD COLUMN IF NOT EXISTS bot_name TEXT"],
+    },
+];
+
+// 执行尚未执行的迁移，返回本次执行的迁移数量
+// 已执行的版本记录在 schema_version 表中，重复调用不会重复执行
+pub async fn run_migrations(pool: &DatabasePool) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    match pool {
+        DatabasePool::Sqlite(_) => {
+            pool.execute(
+                "CREATE TABLE IF NOT EXISTS schema_version (
+                    version INTEGER PRIMARY KEY,
+                    description TEXT NOT NULL,
+                    applied_at TIMESTAMP DEFAULT (datetime('now','localtime'))
+                )",
+            )
+            .await?
+        }
+        DatabasePool::Postgres(_) => {
+            pool.execute(
+                "CREATE TABLE IF NOT EXISTS schema_version (
+                    version BIGINT PRIMARY KEY,
+                    description TEXT NOT NULL,
+                    applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                )",
+            )
+            .await?
+        }
+    }
+
+    let version_query = "SELECT COALESCE(MAX(version), 0) FROM schema_version";
+    let current: i64 = match pool {
+        DatabasePool::Sqlite(db) => sqlx::query_scalar(version_query).fetch_one(db).await?,
+        DatabasePool::Postgres(db) => sqlx::query_scalar(version_query).fetch_one(db).await?,
+    };
+
+    // 引入版本记录之前创建的数据库：先补齐旧版本缺少的列，使其与基线表结构一致
+    if current == 0 && table_exists(pool, "sessions").await? {
+        log::info!("检测到未记录版本的旧数据库，正在升级到基线表结构");
+        upgrade_legacy_schema(pool).await?;
+    }
+
+    let mut applied = 0;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        apply_migration(pool, migration).await?;
+        log::info!(
+            "已执行数据库迁移 {}: {}",
+            migration.version,
+            migration.description
+        );
+        applied += 1;
+    }
+
+    if applied == 0 {
+        log::info!("数据库结构已是最新版本 {}", current);
+    }
+    Ok(applied)
+}
+
+// 在一个事务中执行迁移并记录版本，失败时整个迁移回滚
+async fn apply_migration(pool: &DatabasePool, migration: &Migration) -> Result<(), SqlxError> {
+    match pool {
+        DatabasePool::Sqlite(db) => {
+            let mut tx = db.begin().await?;
+            for statement in migration.sqlite {
+                sqlx::query(statement).execute(&mut *tx).await?;
+            }
+            sqlx::query("INSERT INTO schema_version (version, description) VALUES (?, ?)")
+                .bind(migration.version)
+                .bind(migration.description)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+        }
+        DatabasePool::Postgres(db) => {
+            let mut tx = db.begin().await?;
+            for statement in migration.postgres {
+                sqlx::query(statement).execute(&mut *tx).await?;
+            }
+            sqlx::query("INSERT INTO schema_version (version, description) VALUES ($1, $2)")
+                .bind(migration.version)
+                .bind(migration.description)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+        }
+    }
+    Ok(())
+}
+
+// 检查表是否存在
+async fn table_exists(pool: &DatabasePool, table: &str) -> Result<bool, SqlxError> {
+    let count: i64 = match pool {
+        DatabasePool::Sqlite(db) => {
+            sqlx::query_scalar(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+            )
+            .bind(table)
+            .fetch_one(db)
+            .await?
+        }
+        DatabasePool::Postgres(db) => {
+            sqlx::query_scalar(
+                "SELECT COUNT(*) FROM information_schema.tables
+                 WHERE table_schema = current_schema() AND table_name = $1",
+            )
+            .bind(table)
+            .fetch_one(db)
+            .await?
+        }
+    };
+    Ok(count > 0)
+}
+
+// 将旧数据库升级到基线表结构：id 列改为 BIGINT、合并重复会话并补充后来新增的列
+// 旧数据库中缺少的表由基线迁移创建
+async fn upgrade_legacy_schema(pool: &DatabasePool) -> Result<(), SqlxError> {
+    if let DatabasePool::Postgres(db) = pool {
+        migrate_pg_bigint_ids(db).await?;
+    }
+    add_unique_session_index(pool).await?;
+    add_message_stats_columns(pool).await?;
+    add_session_settings_columns(pool).await?;
+    Ok(())
+}
+
+// 将 PostgreSQL 中会话和消息的 id 列从 INTEGER 迁移到 BIGINT
+// 避免长期运行的实例耗尽 32 位 id 范围
+async fn migrate_pg_bigint_ids(pool: &Pool<Postgres>) -> Result<(), SqlxError> {
+    let columns = [
+        ("sessions", "id", Some("sessions_id_seq")),
+        ("messages", "id", Some("messages_id_seq")),
+        ("messages", "session_id", None),
+    ];
+
+    for (table, column, sequence) in columns {
+        let data_type: Option<String> = sqlx::query_scalar(
+            "SELECT data_type FROM information_schema.columns
+             WHERE table_schema = current_schema() AND table_name = $1 AND column_name = $2",
         )
-        .execute(&pool)
+        .bind(table)
+        .bind(column)
+        .fetch_optional(pool)
         .await?;
 
-        // 创建管理员表
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS admins (
-                id SERIAL PRIMARY KEY,
-                user_id BIGINT NOT NULL UNIQUE,
-                username TEXT,
-                is_super BOOLEAN DEFAULT FALSE,
-                added_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            )",
-        )
-        .execute(&pool)
+        if data_type.as_deref() != Some("integer") {
+            continue;
+        }
+
+        sqlx::query(&format!(
+            "ALTER TABLE {} ALTER COLUMN {} TYPE BIGINT",
+            table, column
+        ))
+        .execute(pool)
         .await?;
 
-        // 添加初始管理员
-        let pool_ref = &DatabasePool::Postgres(pool.clone());
-        add_initial_admins(pool_ref).await?;
+        if let Some(sequence) = sequence {
+            sqlx::query(&format!("ALTER SEQUENCE {} AS BIGINT", sequence))
+                .execute(pool)
+                .await?;
+        }
 
-        log::info!("PostgreSQL 数据库初始化完成");
-        Ok(DatabasePool::Postgres(pool))
-    } else {
-        // SQLite
-        let pool = sqlx::sqlite::SqlitePoolOptions::new()
-            .max_connections(5)
-            .connect(&database_url)
-            .await?;
+        log::info!("已将 {}.{} 迁移为 BIGINT", table, column);
+    }
 
-        // 创建表
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS sessions (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                chat_id INTEGER NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                updated_at TIMESTAMP DEFAULT (datetime('now','localtime'))
-            )",
-        )
-        .execute(&pool)
-        .await?;
+    Ok(())
+}
 
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS messages (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                session_id INTEGER NOT NULL,
-                role TEXT NOT NULL,
-                content TEXT NOT NULL,
-                timestamp TIMESTAMP DEFAULT (datetime('now','localtime')),
-                FOREIGN KEY (session_id) REFERENCES sessions(id)
-            )",
+// 为 sessions.chat_id 添加唯一索引，保证每个聊天只有一个会话
+// 旧版本可能因并发创建出重复会话，建索引前先把消息合并到最早的会话并删除多余的会话
+async fn add_unique_session_index(pool: &DatabasePool) -> Result<(), SqlxError> {
+    let duplicate_query = "SELECT COUNT(*) FROM (
+        SELECT chat_id FROM sessions GROUP BY chat_id HAVING COUNT(*) > 1
+    ) AS duplicates";
+    let duplicates: i64 = match pool {
+        DatabasePool::Sqlite(db) => sqlx::query_scalar(duplicate_query).fetch_one(db).await?,
+        DatabasePool::Postgres(db) => sqlx::query_scalar(duplicate_query).fetch_one(db).await?,
+    };
+
+    if duplicates > 0 {
+        pool.execute(
+            "UPDATE messages SET session_id = (
+                SELECT MIN(s2.id) FROM sessions s1
+                JOIN sessions s2 ON s2.chat_id = s1.chat_id
+                WHERE s1.id = messages.session_id
+            )
+            WHERE session_id NOT IN (SELECT MIN(id) FROM sessions GROUP BY chat_id)",
         )
-        .execute(&pool)
         .await?;
-
-        // 创建白名单表
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS whitelist_users (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                user_id INTEGER NOT NULL UNIQUE,
-                username TEXT,
-                added_by INTEGER NOT NULL,
-                added_at TIMESTAMP DEFAULT (datetime('now','localtime')),
-                notes TEXT
-            )",
+        pool.execute(
+            "DELETE FROM sessions WHERE id NOT IN (SELECT MIN(id) FROM sessions GROUP BY chat_id)",
         )
-        .execute(&pool)
         .await?;
+        log::info!("已合并 {} 个聊天的重复会话", duplicates);
+    }
 
-        // 创建管理员表
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS admins (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                user_id INTEGER NOT NULL UNIQUE,
-                username TEXT,
-                is_super INTEGER DEFAULT 0, 
-                added_at TIMESTAMP DEFAULT (datetime('now','localtime'))
-            )",
-        )
-        .execute(&pool)
+    pool.execute("CREATE UNIQUE INDEX IF NOT EXISTS idx_sessions_chat_id ON sessions (chat_id)")
         .await?;
-        // 添加初始管理员
-        let pool_ref = &DatabasePool::Sqlite(pool.clone());
-        add_initial_admins(pool_ref).await?;
+    Ok(())
+}
 
-        log::info!("SQLite 数据库初始化完成");
-        Ok(DatabasePool::Sqlite(pool))
+// 为已存在的表补充新增列（列已存在时跳过）
+async fn add_column_if_missing(
+    pool: &DatabasePool,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), SqlxError> {
+    match pool {
+        DatabasePool::Sqlite(db) => {
+            // SQLite 不支持 ADD COLUMN IF NOT EXISTS，需要先查询表结构
+            let exists: i64 =
+                sqlx::query_scalar("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?")
+                    .bind(table)
+                    .bind(column)
+                    .fetch_one(db)
+                    .await?;
+
+            if exists == 0 {
+                sqlx::query(&format!(
+                    "ALTER TABLE {} ADD COLUMN {} {}",
+                    table, column, definition
+                ))
+                .execute(db)
+                .await?;
+                log::info!("已添加列 {}.{}", table, column);
+            }
+        }
+        DatabasePool::Postgres(db) => {
+            sqlx::query(&format!(
+                "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}",
+                table, column, definition
+            ))
+            .execute(db)
+            .await?;
+        }
     }
+    Ok(())
+}
+
+// 添加消息统计所需的列：来源（text/voice）、模型、回复耗时和 token 用量
+async fn add_message_stats_columns(pool: &DatabasePool) -> Result<(), SqlxError> {
+    add_column_if_missing(pool, "messages", "source", "TEXT").await?;
+    add_column_if_missing(pool, "messages", "model", "TEXT").await?;
+    add_column_if_missing(pool, "messages", "latency_ms", "BIGINT").await?;
+    add_column_if_missing(pool, "messages", "prompt_tokens", "BIGINT").await?;
+    add_column_if_missing(pool, "messages", "completion_tokens", "BIGINT").await?;
+    Ok(())
+}
+
+// 添加每个聊天的设置列
+async fn add_session_settings_columns(pool: &DatabasePool) -> Result<(), SqlxError> {
+    let bool_false = match pool {
+        DatabasePool::Sqlite(_) => "INTEGER DEFAULT 0",
+        DatabasePool::Postgres(_) => "BOOLEAN DEFAULT FALSE",
+    };
+    add_column_if_missing(pool, "sessions", "show_timestamps", bool_false).await?;
+    add_column_if_missing(pool, "sessions", "reply_lang", "TEXT").await?;
+    add_column_if_missing(pool, "sessions", "voice_assistant", bool_false).await?;
+    add_column_if_missing(pool, "sessions", "privacy_consent", "TEXT").await?;
+    add_column_if_missing(pool, "sessions", "model", "TEXT").await?;
+    let real = match pool {
+        DatabasePool::Sqlite(_) => "REAL",
+        DatabasePool::Postgres(_) => "DOUBLE PRECISION",
+    };
+    add_column_if_missing(pool, "sessions", "temperature", real).await?;
+    Ok(())
 }
 
 // 添加初始管理员
@@ -0,0 +1,87 @@
+use crate::i18n::{self, Key, Lang};
+use std::error::Error;
+use teloxide::{DownloadError, RequestError};
+
+// 应用中的错误类型，调用方可以按类型区分数据库、AI 服务、Telegram 等不同的失败原因
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("数据库错误: {0}")]
+    Database(#[from] sqlx::Error),
+
+    // 无法连接 AI 服务（DNS 失败、连接被拒绝、连接超时等）
+    #[error("网络连接失败: {0}")]
+    Network(reqwest::Error),
+
+    // 已经连接，但在 OPENAI_TIMEOUT_SECS 内没有完成请求
+    #[error("请求超时: {0}")]
+    Timeout(reqwest::Error),
+
+    // 发送请求或读取响应时的其他错误
+    #[error("请求 AI 服务失败: {0}")]
+    Request(reqwest::Error),
+
+    // AI 服务返回了非成功状态码，或者返回了错误信封（{"error": {...}}）
+    #[error("GPT API 错误 ({status}): {}", .message.as_deref().unwrap_or(.body))]
+    OpenAi {
+        status: reqwest::StatusCode,
+        body: String,
+        // 错误信封中的 error.message
+        message: Option<String>,
+    },
+
+    // AI 服务返回了无法解析的响应
+    #[error("无法解析 AI 服务的响应: {0}")]
+    InvalidResponse(String),
+
+    #[error("Telegram 接口错误: {0}")]
+    Telegram(#[from] RequestError),
+
+    #[error("下载 Telegram 文件失败: {0}")]
+    Download(#[from] DownloadError),
+
+    #[error("配置错误: {0}")]
+    Config(String),
+
+    #[error("语音转录失败: {0}")]
+    Transcription(String),
+}
+
+impl AppError {
+    // 给用户看的提示对应的界面文字，不包含内部细节
+    pub fn user_message_key(&self) -> Key {
+        match self {
+            AppError::Database(_) => Key::ErrorDatabase,
+            AppError::Network(_) => Key::ErrorNetwork,
+            AppError::Timeout(_) => Key::ErrorTimeout,
+            AppError::Request(_) | AppError::OpenAi { .. } | AppError::InvalidResponse(_) => {
+                Key::ErrorService
+            }
+            AppError::Telegram(_) | AppError::Download(_) => Key::ErrorTelegram,
+            AppError::Config(_) => Key::ErrorConfig,
+            AppError::Transcription(_) => Key::ErrorTranscription,
+        }
+    }
+}
+
+// 将发送请求时的错误转换为错误类型：连接失败（包括连接超时）归为网络错误，
+// 连接后等待或读取响应超时归为请求超时
+impl From<reqwest::Error> for AppError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() {
+            AppError::Network(e)
+        } else if e.is_timeout() {
+            AppError::Timeout(e)
+        } else {
+            AppError::Request(e)
+        }
+    }
+}
+
+// 根据错误类型生成给用户看的提示，无法识别的错误使用通用提示
+pub fn user_message(lang: Lang, e: &(dyn Error + Send + Sync + 'static)) -> &'static str {
+    let key = match e.downcast_ref::<AppError>() {
+        Some(e) => e.user_message_key(),
+        None => Key::ErrorGeneric,
+    };
+    i18n::t(lang, key)
+}
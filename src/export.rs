@@ -0,0 +1,64 @@
+use crate::db::DatabasePool;
+use crate::models;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+// 每次从数据库读取的消息条数
+const PAGE_SIZE: i64 = 500;
+
+// 发送给用户的文件名，包含聊天ID和导出日期
+pub fn file_name(chat_id: i64) -> String {
+    format!(
+        "chat_{}_{}.json",
+        chat_id,
+        chrono::Local::now().format("%Y%m%d")
+    )
+}
+
+// 临时文件路径，同一聊天同时导出时不会冲突
+pub fn temp_path(chat_id: i64) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    std::env::temp_dir().join(format!("gpt_bot_export_{}_{}.json", chat_id, nanos))
+}
+
+// 将聊天的全部消息写入 path（JSON 数组，每条消息一行），返回写入的消息数
+// 逐页读取并写入文件，不会把整个历史拼成一个字符串
+pub async fn write_chat_history(
+    pool: &DatabasePool,
+    chat_id: i64,
+    path: &Path,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let mut writer = BufWriter::new(File::create(path).await?);
+    writer.write_all(b"[").await?;
+
+    let mut count = 0;
+    let mut last_id = 0;
+    loop {
+        let page =
+            models::Message::get_all_messages_by_chat_id(pool, chat_id, last_id, PAGE_SIZE).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        for message in &page {
+            writer
+                .write_all(if count == 0 { b"\n  " } else { b",\n  " })
+                .await?;
+            writer
+                .write_all(serde_json::to_string(message)?.as_bytes())
+                .await?;
+            count += 1;
+        }
+    }
+
+    writer.write_all(b"\n]\n").await?;
+    writer.flush().await?;
+    Ok(count)
+}
@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// 默认合并窗口（秒）
+const DEFAULT_DEBOUNCE_SECS: u64 = 3;
+
+// 专注模式：开启后，同一聊天在短时间内连续发送的文本会合并为一次请求
+#[derive(Clone, Default)]
+pub struct FocusStore {
+    inner: Arc<Mutex<FocusInner>>,
+}
+
+#[derive(Default)]
+struct FocusInner {
+    enabled: HashSet<i64>,
+    pending: HashMap<i64, PendingBatch>,
+}
+
+// 等待合并的消息
+struct PendingBatch {
+    parts: Vec<String>,
+    generation: u64,
+}
+
+impl FocusStore {
+    // 开启或关闭聊天的专注模式
+    pub fn set_enabled(&self, chat_id: i64, enabled: bool) {
+        if let Ok(mut inner) = self.inner.lock() {
+            if enabled {
+                inner.enabled.insert(chat_id);
+            } else {
+                inner.enabled.remove(&chat_id);
+            }
+        }
+    }
+
+    // 聊天是否开启了专注模式
+    pub fn is_enabled(&self, chat_id: i64) -> bool {
+        self.inner
+            .lock()
+            .map(|inner| inner.enabled.contains(&chat_id))
+            .unwrap_or(false)
+    }
+
+    // 将消息加入缓冲区，返回加入后的批次编号
+    pub fn push(&self, chat_id: i64, text: &str) -> u64 {
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let batch = inner.pending.entry(chat_id).or_insert(PendingBatch {
+            parts: Vec::new(),
+            generation: 0,
+        });
+        batch.parts.push(text.to_string());
+        batch.generation += 1;
+        batch.generation
+    }
+
+    // 窗口内没有新消息（批次编号未变）时取出合并后的文本
+    pub fn take_if_current(&self, chat_id: i64, generation: u64) -> Option<String> {
+        let mut inner = self.inner.lock().ok()?;
+
+        if inner.pending.get(&chat_id)?.generation != generation {
+            return None;
+        }
+
+        inner
+            .pending
+            .remove(&chat_id)
+            .map(|batch| batch.parts.join("\n"))
+    }
+}
+
+// 合并窗口，可通过 FOCUS_DEBOUNCE_SECS 配置
+pub fn debounce_window() -> Duration {
+    let secs = env::var("FOCUS_DEBOUNCE_SECS")
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_DEBOUNCE_SECS);
+    Duration::from_secs(secs)
+}
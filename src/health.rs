@@ -0,0 +1,65 @@
+use crate::db::SharedPool;
+use crate::metrics::Metrics;
+use axum::{
+    http::{header, StatusCode},
+    routing::get,
+    Router,
+};
+use std::error::Error;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+// Prometheus 文本格式的 Content-Type
+const METRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// 运维 HTTP 服务：db_pool 不为空时提供 GET /healthz（数据库可用时返回 200，否则返回 503），
+// metrics 不为空时提供 GET /metrics；HEALTH_PORT 和 METRICS_PORT 相同时两者由同一个服务提供
+// 服务在机器人令牌通过启动检查之后才启动，因此令牌无效时端口不会有响应
+pub async fn start(
+    port: u16,
+    db_pool: Option<SharedPool>,
+    metrics: Option<Metrics>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    // 在启动时绑定端口，端口被占用时直接启动失败
+    let listener = TcpListener::bind(address).await?;
+
+    let mut app = Router::new();
+    if let Some(db_pool) = db_pool {
+        app = app.route("/healthz", get(move || healthz(db_pool.clone())));
+        log::info!("健康检查服务已启动: http://{}/healthz", address);
+    }
+    if let Some(metrics) = metrics {
+        app = app.route(
+            "/metrics",
+            get(move || {
+                let metrics = metrics.clone();
+                async move {
+                    (
+                        [(header::CONTENT_TYPE, METRICS_CONTENT_TYPE)],
+                        metrics.encode(),
+                    )
+                }
+            }),
+        );
+        log::info!("指标服务已启动: http://{}/metrics", address);
+    }
+
+    // 在同一个运行时中后台运行，不阻塞消息处理
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            log::error!("运维 HTTP 服务异常退出: {:?}", e);
+        }
+    });
+    Ok(())
+}
+
+async fn healthz(db_pool: SharedPool) -> (StatusCode, &'static str) {
+    match db_pool.load().ping().await {
+        Ok(()) => (StatusCode::OK, "ok"),
+        Err(e) => {
+            log::warn!("健康检查失败，数据库不可用: {:?}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, "database unavailable")
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+// 正在处理请求的聊天，同一聊天同时只处理一条消息，避免历史交错写入和重复回复
+#[derive(Clone, Default)]
+pub struct InFlightChats {
+    chats: Arc<Mutex<HashSet<i64>>>,
+}
+
+// 持有期间聊天处于处理中，被丢弃时释放（成功、出错或提前返回都会释放）
+pub struct InFlightGuard {
+    chats: Arc<Mutex<HashSet<i64>>>,
+    chat_id: i64,
+}
+
+impl InFlightChats {
+    // 标记聊天为处理中；已有请求正在处理时返回 None
+    pub fn try_acquire(&self, chat_id: i64) -> Option<InFlightGuard> {
+        let mut chats = match self.chats.lock() {
+            Ok(chats) => chats,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if !chats.insert(chat_id) {
+            return None;
+        }
+
+        Some(InFlightGuard {
+            chats: self.chats.clone(),
+            chat_id,
+        })
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut chats = match self.chats.lock() {
+            Ok(chats) => chats,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        chats.remove(&self.chat_id);
+    }
+}
@@ -0,0 +1,102 @@
+use chrono::{Local, NaiveDateTime};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// 错误信息最多保留的字符数
+const MAX_ERROR_CHARS: usize = 500;
+
+// 某个聊天最近一次的错误
+#[derive(Debug, Clone)]
+pub struct LastError {
+    pub trace_id: String,
+    pub message: String,
+    pub occurred_at: NaiveDateTime,
+}
+
+// 按聊天保存最近一次错误，只保存在内存中
+#[derive(Clone, Default)]
+pub struct LastErrorStore {
+    errors: Arc<Mutex<HashMap<i64, LastError>>>,
+}
+
+impl LastErrorStore {
+    // 记录错误并返回追踪编号
+    pub fn record(&self, chat_id: i64, error: &str) -> String {
+        let now = Local::now();
+        let trace_id = format!(
+            "{:08x}",
+            (now.timestamp_micros() as u64 ^ chat_id as u64) as u32
+        );
+
+        let last_error = LastError {
+            trace_id: trace_id.clone(),
+            message: sanitize(error),
+            occurred_at: now.naive_local(),
+        };
+
+        if let Ok(mut errors) = self.errors.lock() {
+            errors.insert(chat_id, last_error);
+        }
+
+        trace_id
+    }
+
+    // 获取聊天最近一次的错误
+    pub fn get(&self, chat_id: i64) -> Option<LastError> {
+        self.errors
+            .lock()
+            .ok()
+            .and_then(|errors| errors.get(&chat_id).cloned())
+    }
+
+    // 处理成功后清除错误记录
+    pub fn clear(&self, chat_id: i64) {
+        if let Ok(mut errors) = self.errors.lock() {
+            errors.remove(&chat_id);
+        }
+    }
+}
+
+// 隐藏错误信息中可能出现的密钥，并限制长度
+fn sanitize(error: &str) -> String {
+    let redacted = error
+        .split(' ')
+        .map(|word| {
+            if looks_like_secret(word) {
+                "[已隐藏]"
+            } else {
+                word
+            }
+        })
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    if redacted.chars().count() > MAX_ERROR_CHARS {
+        let truncated: String = redacted.chars().take(MAX_ERROR_CHARS).collect();
+        format!("{}...", truncated)
+    } else {
+        redacted
+    }
+}
+
+// OpenAI 密钥（sk-...）或 Telegram 机器人令牌（数字:字符串）
+fn looks_like_secret(word: &str) -> bool {
+    let word =
+        word.trim_matches(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_' && c != ':');
+
+    if word.contains("sk-") {
+        return true;
+    }
+
+    match word.split_once(':') {
+        Some((id, token)) => {
+            !id.is_empty()
+                && id.chars().all(|c| c.is_ascii_digit())
+                && token.len() >= 30
+                && token
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        }
+        None => false,
+    }
+}
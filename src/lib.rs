@@ -0,0 +1,38 @@
+// 机器人的各个模块，main.rs 和 tests/ 中的集成测试共用
+pub mod access;
+pub mod analytics;
+pub mod api_keys;
+pub mod audio;
+pub mod broadcast;
+pub mod commands;
+pub mod config;
+pub mod context;
+pub mod cooldown;
+pub mod db;
+pub mod error;
+pub mod export;
+pub mod focus;
+pub mod health;
+pub mod i18n;
+pub mod in_flight;
+pub mod last_error;
+pub mod markdown;
+pub mod metrics;
+pub mod migrate;
+pub mod models;
+pub mod openai;
+pub mod persona;
+pub mod privacy;
+pub mod providers;
+pub mod quote;
+pub mod rate_limit;
+pub mod refusal;
+pub mod reply;
+pub mod retry;
+pub mod thinking;
+pub mod tools;
+pub mod typing;
+pub mod voice_actions;
+
+// 默认聊天模型
+pub const DEFAULT_MODEL: &str = "gpt-4o-mini";
@@ -1,19 +1,64 @@
+use access::AccessCache;
+use api_keys::ApiKeys;
+use arc_swap::ArcSwap;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use cooldown::CommandCooldowns;
 use dotenv::dotenv;
+use error::AppError;
+use focus::FocusStore;
+use i18n::{t, Key, Lang};
+use in_flight::InFlightChats;
+use last_error::LastErrorStore;
+use openai::OpenAiClient;
 use reqwest::multipart::{Form, Part};
 use serde::Deserialize;
 use serde_json::Value;
-use std::env;
 use std::error::Error;
-use teloxide::{net::Download, prelude::*, types::File as TgFile, utils::command::BotCommands};
+use std::sync::Arc;
+use teloxide::{
+    net::Download,
+    prelude::*,
+    types::{
+        BotCommand, File as TgFile, InlineKeyboardMarkup, InputFile, MessageId, ParseMode,
+        ReplyParameters,
+    },
+    update_listeners::webhooks,
+    utils::command::BotCommands,
+    RequestError,
+};
 
 // 引入模块
-mod db;
-mod models;
+use gpt_bot_rs::{
+    access, analytics, api_keys, audio, broadcast, commands, config, context, cooldown, db, error,
+    export, focus, health, i18n, in_flight, last_error, markdown, migrate, models, openai, persona,
+    privacy, providers, quote, rate_limit, refusal, reply, retry, thinking, tools, typing,
+    voice_actions, DEFAULT_MODEL,
+};
 
-// OpenAI响应结构
+// 允许使用的聊天模型列表
+const ALLOWED_MODELS: &[&str] = &["gpt-4o", "gpt-4o-mini", "gpt-4-turbo"];
+
+// /setname 设置的助手名字的最大字符数
+const MAX_BOT_NAME_CHARS: usize = 32;
+
+// /sql 最多显示的行数
+const MAX_SQL_ROWS: usize = 20;
+
+// 语音转录接口的响应
 #[derive(Deserialize, Debug)]
 struct OpenAIResponse {
     text: String,
+    // 仅在 response_format=verbose_json 时返回
+    #[serde(default)]
+    segments: Vec<TranscriptionSegment>,
+}
+
+// 转录分段（verbose_json）
+#[derive(Deserialize, Debug)]
+struct TranscriptionSegment {
+    start: f64,
+    text: String,
 }
 
 // 定义命令
@@ -30,18 +75,132 @@ enum Command {
     Start,
     #[command(description = "测试机器人是否在线")]
     Ping,
+    #[command(description = "查看您的用户ID、聊天ID和权限")]
+    Whoami,
     #[command(description = "清除聊天历史记录")]
     Clear,
-    #[command(description = "添加用户到白名单 (仅管理员可用)")]
+    #[command(description = "导出本聊天的历史记录 (JSON 文件)")]
+    Export,
+    #[command(description = "重新生成最近一次的回复")]
+    Regenerate,
+    #[command(description = "删除最近的 N 条消息记录 (默认 1 条)")]
+    Forget(String),
+    // 备注中可以包含空格，因此整段参数交给 parse_add_user_args 处理
+    #[command(
+        description = "添加用户到白名单 (仅管理员可用)",
+        parse_with = "default"
+    )]
     AddUser(String),
-    #[command(description = "从白名单移除用户 (仅管理员可用)")]
+    #[command(description = "停用白名单用户，--purge 彻底删除 (仅管理员可用)")]
     RemoveUser(String),
-    #[command(description = "列出所有白名单用户 (仅管理员可用)")]
-    ListUsers,
+    #[command(description = "列出白名单用户，all 包含已停用的用户 (仅管理员可用)")]
+    ListUsers(String),
     #[command(description = "添加管理员 (仅超级管理员可用)")]
     AddAdmin(String),
+    // 参数可以是 "<用户ID> confirm"，整段交给 remove_admin 处理
+    #[command(
+        description = "移除管理员，移除自己需加 confirm (仅超级管理员可用)",
+        parse_with = "default"
+    )]
+    RemoveAdmin(String),
     #[command(description = "列出所有管理员 (仅管理员可用)")]
     ListAdmins,
+    #[command(description = "转让超级管理员身份，confirm 将自己降级 (仅超级管理员可用)")]
+    Transfer(String),
+    #[command(description = "检查某个用户的访问权限 (仅超级管理员可用)")]
+    CheckAccess(String),
+    #[command(description = "查看最近30天的聚合使用统计 (仅超级管理员可用)")]
+    Analytics,
+    #[command(description = "语音转录是否显示时间戳 (on/off)")]
+    Timestamps(String),
+    #[command(description = "固定回复语言，如 en、zh (auto 跟随输入，default 恢复默认)")]
+    ReplyLang(String),
+    #[command(description = "切换机器人界面语言 (zh/en)")]
+    Lang(String),
+    #[command(description = "切换本聊天使用的模型 (default 恢复默认)")]
+    Model(String),
+    #[command(description = "设置您在所有聊天中的默认模型 (default 清除)")]
+    MyModel(String),
+    #[command(description = "设置本聊天的 temperature，范围 0.0-2.0 (default 恢复默认)")]
+    Temperature(String),
+    #[command(description = "设置本聊天每次携带的历史消息条数，最多 50 条 (default 恢复默认)")]
+    Context(String),
+    // 名字中可以包含空格，整段参数都作为名字
+    #[command(
+        description = "为本聊天的助手起名字 (default 清除)",
+        parse_with = "default"
+    )]
+    SetName(String),
+    #[command(description = "查看当前聊天的设置")]
+    Settings,
+    #[command(description = "查看您的个人设置")]
+    MySettings,
+    #[command(description = "查看本聊天最近一次的错误")]
+    LastError,
+    #[command(description = "查看或撤回保存消息记录的同意")]
+    Privacy,
+    #[command(description = "将较早的对话压缩为摘要，节省上下文")]
+    Compress,
+    #[command(description = "将整段对话替换为一条摘要，confirm 确认执行")]
+    Summary(String),
+    // 文本中可以包含空格，整段参数都需要计数
+    #[command(
+        description = "计算文本的 token 数 (不带参数时计算被回复的消息)",
+        parse_with = "default"
+    )]
+    Tokens(String),
+    // 要翻译的文本可以包含空格，整段参数交给 parse_translate_args 处理
+    #[command(
+        description = "翻译被回复的消息或命令后的文本，如 /translate en 你好 (不保存到对话历史)",
+        parse_with = "default"
+    )]
+    Translate(String),
+    #[command(description = "专注模式：合并短时间内连续发送的消息 (on/off)")]
+    Focus(String),
+    #[command(description = "语音助手模式：语音提问时用语音回复 (on/off)")]
+    VoiceAssistant(String),
+    #[command(description = "查看各模型回复结束原因的统计 (仅管理员可用)")]
+    FinishReasons,
+    #[command(description = "查看最近的模型拒绝回答记录 (仅管理员可用)")]
+    Refusals,
+    #[command(description = "查看最近30天各聊天的 token 用量 (仅管理员可用)")]
+    Usage,
+    #[command(description = "重新建立数据库连接 (仅超级管理员可用)")]
+    DbReconnect,
+    #[command(description = "将 SQLite 中的数据迁移到 PostgreSQL (仅超级管理员可用)")]
+    MigrateDb(String),
+    #[command(description = "查看当前生效的配置，不含密钥 (仅超级管理员可用)")]
+    Config,
+    // 通知内容可以包含空格和换行，整段参数都作为通知内容
+    #[command(
+        description = "向所有聊天发送通知 (仅超级管理员可用)",
+        parse_with = "default"
+    )]
+    Broadcast(String),
+    // SQL 语句可以包含空格，整段参数交给 DatabasePool::query_rows 检查
+    #[command(
+        description = "执行只读 SELECT 查询并以表格显示结果 (仅超级管理员可用)",
+        parse_with = "default"
+    )]
+    Sql(String),
+}
+
+// 初始化日志：默认使用 pretty_env_logger；LOG_FORMAT=json 时使用 tracing 输出 JSON，
+// log 宏的记录也会转为 JSON，日志级别同样由 RUST_LOG 控制
+fn init_logging() {
+    match config::log_format() {
+        config::LogFormat::Pretty => pretty_env_logger::init(),
+        config::LogFormat::Json => {
+            let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("error"));
+            tracing_subscriber::fmt()
+                .json()
+                .flatten_event(true)
+                .with_current_span(false)
+                .with_env_filter(filter)
+                .init();
+        }
+    }
 }
 
 #[tokio::main]
@@ -49,46 +208,162 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     // 加载环境变量
     dotenv().ok();
 
-    // 获取环境变量
-    let tg_token = env::var("TELEGRAM_BOT_TOKEN").expect("TELEGRAM_BOT_TOKEN not found");
-    let openai_token = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not found");
+    // 获取环境变量，也可以通过 *_FILE 从文件读取密钥
+    let tg_token =
+        config::read_secret("TELEGRAM_BOT_TOKEN")?.expect("TELEGRAM_BOT_TOKEN not found");
+    let api_keys = ApiKeys::from_env()?.expect("OPENAI_API_KEYS or OPENAI_API_KEY not found");
 
     // 初始化日志
-    pretty_env_logger::init();
+    init_logging();
     log::info!("Starting telegram bot...");
 
-    // 初始化数据库
-    let db_pool = db::init_db().await?;
+    // 检查模型路由配置
+    log::info!("OpenAI 接口地址: {}", providers::default_base_url());
+    log::info!("已加载 {} 个 OpenAI 密钥", api_keys.count());
+
+    // 所有 OpenAI 请求共用一个 HTTP 客户端，复用连接，避免每条消息都重新握手
+    let client = OpenAiClient::new(api_keys, config::openai_timeout())?;
+    if config::whitelist_enabled() {
+        log::info!("白名单已启用，只有管理员和白名单用户可以使用机器人");
+    } else {
+        log::warn!("白名单已关闭（ENABLE_WHITELIST=false），所有人都可以使用机器人");
+    }
+    // 接收更新的方式，webhook 模式缺少 WEBHOOK_URL 时直接启动失败
+    let bot_mode = config::bot_mode()?;
+    let routes = providers::validate()?;
+    if routes > 0 {
+        log::info!("已加载 {} 条模型路由规则", routes);
+    }
+
+    // 检查聊天人设配置
+    let personas = persona::validate()?;
+    if personas > 0 {
+        log::info!("已为 {} 个聊天配置人设", personas);
+    }
+
+    // 初始化数据库，连接池可以通过 /dbreconnect 在运行时替换
+    let db_pool: db::SharedPool = Arc::new(ArcSwap::from_pointee(db::init_db().await?));
     log::info!("Database initialized successfully");
 
     // 创建机器人
     let bot = Bot::new(tg_token);
 
+    // 启动检查：令牌或密钥无效时立即退出，而不是等到轮询或第一次对话时才报错
+    // 同时获取机器人自身信息，用于识别机器人被拉入群组的事件和群组中的 @提及
+    let me = match bot.get_me().await {
+        Ok(me) => me,
+        Err(e) => {
+            let e = AppError::Config(format!("TELEGRAM_BOT_TOKEN 无效或无法连接 Telegram: {}", e));
+            log::error!("启动检查失败: {}", e);
+            return Err(e.into());
+        }
+    };
+    log::info!("Telegram 令牌检查通过: @{}", me.username());
+    if let Err(e) = openai::check_api_keys(&client).await {
+        log::error!("启动检查失败: {}", e);
+        return Err(e.into());
+    }
+    log::info!("OpenAI 密钥检查通过");
+
     // 设置机器人命令
     setup_commands(&bot).await?;
     log::info!("Bot commands have been set");
 
+    let bot_id = me.id;
+    let bot_username = me.username().to_string();
+
+    // 令牌已通过启动检查，此时再启动健康检查和指标服务，两者端口相同时共用一个服务
+    match (config::health_port(), config::metrics_port()) {
+        (Some(health_port), Some(metrics_port)) if health_port == metrics_port => {
+            health::start(
+                health_port,
+                Some(db_pool.clone()),
+                Some(client.metrics.clone()),
+            )
+            .await?;
+        }
+        (health_port, metrics_port) => {
+            if let Some(port) = health_port {
+                health::start(port, Some(db_pool.clone()), None).await?;
+            }
+            if let Some(port) = metrics_port {
+                health::start(port, None, Some(client.metrics.clone())).await?;
+            }
+        }
+    }
+    log::info!("机器人用户名: @{}", bot_username);
+
+    // 群组欢迎语，设置为空字符串时不发送，未设置时按界面语言使用默认欢迎语
+    let group_greeting = config::group_greeting();
+
+    // 每个聊天最近一次的错误，供 /lasterror 查询
+    let last_errors = LastErrorStore::default();
+
+    // 开启了专注模式的聊天及其待合并的消息
+    let focus = FocusStore::default();
+
+    // 正在处理请求的聊天，同一聊天同时只处理一条消息
+    let in_flight = InFlightChats::default();
+
+    // 白名单和管理员查询结果的缓存
+    let access_cache = AccessCache::new(config::access_cache_ttl());
+
+    // 耗时命令的每用户冷却时间
+    let cooldowns = CommandCooldowns::new(config::command_cooldown());
+
     let db_pool_clone = db_pool.clone();
-    let openai_token_clone = openai_token.clone();
+    let client_clone = client.clone();
+    let last_errors_clone = last_errors.clone();
+    let in_flight_clone = in_flight.clone();
+    let access_cache_clone = access_cache.clone();
 
     // 更新处理器，根据消息类型分流
     let message_handler = Update::filter_message()
         .branch(
-            dptree::filter(|msg: Message| msg.voice().is_some()).endpoint(
+            dptree::filter(|msg: Message| msg.new_chat_members().is_some()).endpoint({
+                let db = db_pool.clone();
+                move |bot: Bot, msg: Message| {
+                    let db = db.load_full();
+                    let greeting = group_greeting.clone();
+                    async move {
+                        handle_new_chat_members(bot, msg, bot_id, &db, greeting.as_deref()).await
+                    }
+                }
+            }),
+        )
+        .branch(
+            dptree::filter(|msg: Message| audio::AudioFile::from_message(&msg).is_some()).endpoint(
                 move |bot: Bot, msg: Message| {
-                    let openai_token = openai_token_clone.clone();
+                    let client = client_clone.clone();
                     let db = db_pool_clone.clone();
+                    let last_errors = last_errors_clone.clone();
+                    let in_flight = in_flight_clone.clone();
+                    let access_cache = access_cache_clone.clone();
                     async move {
+                        let db = db.load_full();
+
                         // 检查白名单
-                        if !check_whitelist(&bot, &msg, &db).await {
+                        if !check_whitelist(&bot, &msg, &db, &access_cache).await {
                             return respond(());
                         }
 
-                        if let Err(err) =
-                            handle_voice_message(bot.clone(), msg.clone(), &openai_token, &db).await
+                        if let Err(err) = handle_voice_message(
+                            bot.clone(),
+                            msg.clone(),
+                            &client,
+                            &db,
+                            &last_errors,
+                            &in_flight,
+                            false,
+                        )
+                        .await
                         {
-                            log::error!("语音处理错误: {:?}", err);
-                            let _ = bot.send_message(msg.chat.id, "处理语音时发生错误").await;
+                            let trace_id = last_errors.record(msg.chat.id.0, &err.to_string());
+                            log::error!("[{}] 语音处理错误: {:?}", trace_id, err);
+                            let lang = i18n::chat_lang(&db, msg.chat.id.0).await;
+                            let _ = bot
+                                .send_message(msg.chat.id, t(lang, Key::VoiceFailed))
+                                .await;
                         }
                         respond(())
                     }
@@ -97,527 +372,3976 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         )
         .branch(dptree::entry().filter_command::<Command>().endpoint({
             let db = db_pool.clone();
-            let openai_token = openai_token.clone();
+            let client = client.clone();
+            let last_errors = last_errors.clone();
+            let focus = focus.clone();
+            let in_flight = in_flight.clone();
+            let access_cache = access_cache.clone();
+            let cooldowns = cooldowns.clone();
             move |bot: Bot, msg: Message, cmd: Command| {
                 let db = db.clone();
-                let openai_token = openai_token.clone();
-                async move { handle_command(bot, msg, cmd, &db, &openai_token).await }
+                let client = client.clone();
+                let last_errors = last_errors.clone();
+                let focus = focus.clone();
+                let in_flight = in_flight.clone();
+                let access_cache = access_cache.clone();
+                let cooldowns = cooldowns.clone();
+                async move {
+                    handle_command(
+                        bot,
+                        msg,
+                        cmd,
+                        &db,
+                        &client,
+                        &last_errors,
+                        &focus,
+                        &in_flight,
+                        &access_cache,
+                        &cooldowns,
+                    )
+                    .await
+                }
             }
         }))
+        .branch(
+            dptree::filter(|msg: Message| msg.photo().is_some()).endpoint({
+                let db = db_pool.clone();
+                let client = client.clone();
+                let last_errors = last_errors.clone();
+                let in_flight = in_flight.clone();
+                let access_cache = access_cache.clone();
+                let bot_username = bot_username.clone();
+                move |bot: Bot, msg: Message| {
+                    let db = db.clone();
+                    let client = client.clone();
+                    let last_errors = last_errors.clone();
+                    let in_flight = in_flight.clone();
+                    let access_cache = access_cache.clone();
+                    let bot_username = bot_username.clone();
+                    async move {
+                        // 未开启 ENABLE_VISION 时忽略图片；群组中同样只处理 @机器人 或回复机器人的图片
+                        if !config::vision_enabled()
+                            || !addressed_to_bot(&msg, bot_id, &bot_username)
+                        {
+                            return respond(());
+                        }
+
+                        let db = db.load_full();
+
+                        // 检查白名单
+                        if !check_whitelist(&bot, &msg, &db, &access_cache).await {
+                            return respond(());
+                        }
+
+                        handle_photo_message(
+                            bot,
+                            msg,
+                            &db,
+                            &client,
+                            &last_errors,
+                            &in_flight,
+                            &bot_username,
+                        )
+                        .await
+                    }
+                }
+            }),
+        )
         .branch(
             dptree::filter(|msg: Message| msg.text().is_some()).endpoint({
                 let db = db_pool.clone();
-                let openai_token = openai_token.clone();
+                let client = client.clone();
+                let last_errors = last_errors.clone();
+                let focus = focus.clone();
+                let in_flight = in_flight.clone();
+                let access_cache = access_cache.clone();
+                let bot_username = bot_username.clone();
                 move |bot: Bot, msg: Message| {
                     let db = db.clone();
-                    let openai_token = openai_token.clone();
+                    let client = client.clone();
+                    let last_errors = last_errors.clone();
+                    let focus = focus.clone();
+                    let in_flight = in_flight.clone();
+                    let access_cache = access_cache.clone();
+                    let bot_username = bot_username.clone();
                     async move {
+                        // 群组中只处理 @机器人 或回复机器人的消息，其他消息静默忽略
+                        if !addressed_to_bot(&msg, bot_id, &bot_username) {
+                            return respond(());
+                        }
+
+                        let db = db.load_full();
+
                         // 检查白名单
-                        if !check_whitelist(&bot, &msg, &db).await {
+                        if !check_whitelist(&bot, &msg, &db, &access_cache).await {
                             return respond(());
                         }
 
-                        handle_text_message(bot, msg, &db, &openai_token).await
+                        handle_text_message(
+                            bot,
+                            msg,
+                            &db,
+                            &client,
+                            &last_errors,
+                            &focus,
+                            &in_flight,
+                            &bot_username,
+                        )
+                        .await
                     }
                 }
             }),
         );
 
-    Dispatcher::builder(bot, message_handler)
+    // 编辑后的命令默认静默忽略，设置 EDITED_COMMAND_MODE=notify 时提示用户重新发送
+    let notify_edited_commands = config::notify_edited_commands();
+
+    // 编辑消息处理器，避免编辑命令时重复执行管理员操作
+    // 编辑普通文本时更新保存的提问，可选重新回答
+    let edited_message_handler =
+        Update::filter_edited_message()
+            .branch(
+                dptree::filter(|msg: Message| msg.text().is_some_and(commands::is_command_text))
+                    .endpoint({
+                        let db = db_pool.clone();
+                        move |bot: Bot, msg: Message| {
+                            let db = db.load_full();
+                            async move {
+                                handle_edited_command(bot, msg, &db, notify_edited_commands).await
+                            }
+                        }
+                    }),
+            )
+            .branch(
+                dptree::filter(|msg: Message| msg.text().is_some()).endpoint({
+                    let db = db_pool.clone();
+                    let client = client.clone();
+                    let last_errors = last_errors.clone();
+                    let in_flight = in_flight.clone();
+                    let access_cache = access_cache.clone();
+                    let bot_username = bot_username.clone();
+                    move |bot: Bot, msg: Message| {
+                        let db = db.clone();
+                        let client = client.clone();
+                        let last_errors = last_errors.clone();
+                        let in_flight = in_flight.clone();
+                        let access_cache = access_cache.clone();
+                        let bot_username = bot_username.clone();
+                        async move {
+                            if !addressed_to_bot(&msg, bot_id, &bot_username) {
+                                return respond(());
+                            }
+
+                            let db = db.load_full();
+
+                            // 检查白名单
+                            if !check_whitelist(&bot, &msg, &db, &access_cache).await {
+                                return respond(());
+                            }
+
+                            handle_edited_message(
+                                bot,
+                                msg,
+                                &db,
+                                &client,
+                                &last_errors,
+                                &in_flight,
+                                &bot_username,
+                            )
+                            .await
+                        }
+                    }
+                }),
+            );
+
+    // 按钮回调处理器（语音回复操作、隐私同意）
+    let callback_handler = Update::filter_callback_query()
+        .branch(
+            dptree::filter(|q: CallbackQuery| {
+                q.data
+                    .as_deref()
+                    .is_some_and(|data| data.starts_with(voice_actions::PREFIX))
+            })
+            .endpoint({
+                let db = db_pool.clone();
+                let client = client.clone();
+                let last_errors = last_errors.clone();
+                let in_flight = in_flight.clone();
+                let access_cache = access_cache.clone();
+                move |bot: Bot, q: CallbackQuery| {
+                    let db = db.load_full();
+                    let client = client.clone();
+                    let last_errors = last_errors.clone();
+                    let in_flight = in_flight.clone();
+                    let access_cache = access_cache.clone();
+                    async move {
+                        handle_voice_callback(
+                            bot,
+                            q,
+                            &db,
+                            &client,
+                            &last_errors,
+                            &in_flight,
+                            &access_cache,
+                        )
+                        .await
+                    }
+                }
+            }),
+        )
+        .branch(dptree::endpoint({
+            let db = db_pool.clone();
+            let access_cache = access_cache.clone();
+            move |bot: Bot, q: CallbackQuery| {
+                let db = db.load_full();
+                let access_cache = access_cache.clone();
+                async move { handle_privacy_callback(bot, q, &db, &access_cache).await }
+            }
+        }));
+
+    // 频道消息处理器：设置 CHANNEL_POSTS=true 时像普通文本一样回复，否则静默忽略
+    let channel_post_handler = Update::filter_channel_post().endpoint({
+        let db = db_pool.clone();
+        let client = client.clone();
+        let last_errors = last_errors.clone();
+        let focus = focus.clone();
+        let in_flight = in_flight.clone();
+        let access_cache = access_cache.clone();
+        move |bot: Bot, msg: Message| {
+            let db = db.load_full();
+            let client = client.clone();
+            let last_errors = last_errors.clone();
+            let focus = focus.clone();
+            let in_flight = in_flight.clone();
+            let access_cache = access_cache.clone();
+            let bot_username = bot_username.clone();
+            async move {
+                if !config::channel_posts_enabled() {
+                    return respond(());
+                }
+                // 开启白名单时只回复 CHANNEL_ALLOWLIST 中的频道
+                if !check_whitelist(&bot, &msg, &db, &access_cache).await {
+                    return respond(());
+                }
+                handle_text_message(
+                    bot,
+                    msg,
+                    &db,
+                    &client,
+                    &last_errors,
+                    &focus,
+                    &in_flight,
+                    &bot_username,
+                )
+                .await
+            }
+        }
+    });
+
+    let handler = dptree::entry()
+        .branch(message_handler)
+        .branch(edited_message_handler)
+        .branch(callback_handler)
+        .branch(channel_post_handler);
+
+    // 两种模式使用同一个处理树，只是接收更新的方式不同
+    let mut dispatcher = Dispatcher::builder(bot.clone(), handler)
         .default_handler(|upd| async move {
             log::warn!("未处理的更新: {:?}", upd);
         })
         .error_handler(LoggingErrorHandler::with_custom_text("处理消息时发生错误"))
         .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+        .build();
+
+    match bot_mode {
+        config::BotMode::Polling => {
+            log::info!("使用长轮询接收更新");
+            dispatcher.dispatch().await;
+        }
+        config::BotMode::Webhook { url, port } => {
+            // 这里只监听普通 HTTP，不处理 TLS：Telegram 要求 webhook 使用 HTTPS，
+            // 需要由反向代理（nginx、Caddy 等）终止 TLS，再把 WEBHOOK_URL 的请求转发到本机的 WEBHOOK_PORT
+            log::info!("使用 webhook 接收更新: {}，监听端口 {}", url, port);
+            let options = webhooks::Options::new(([0, 0, 0, 0], port).into(), url);
+            let listener = webhooks::axum(bot, options).await?;
+            dispatcher
+                .dispatch_with_listener(
+                    listener,
+                    LoggingErrorHandler::with_custom_text("webhook 接收更新时发生错误"),
+                )
+                .await;
+        }
+    }
 
     Ok(())
 }
 
 // 设置机器人命令列表
 async fn setup_commands(bot: &Bot) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let commands = Command::bot_commands();
+    let commands = enabled_bot_commands();
     bot.set_my_commands(commands).await?;
     Ok(())
 }
 
+// 命令是否被禁用，name 不带 "/" 前缀
+fn is_command_disabled(name: &str) -> bool {
+    config::disabled_commands()
+        .iter()
+        .any(|disabled| disabled == name)
+}
+
+// 未被禁用的命令列表，用于注册命令菜单和 /help
+fn enabled_bot_commands() -> Vec<BotCommand> {
+    Command::bot_commands()
+        .into_iter()
+        .filter(|command| !is_command_disabled(command.command.trim_start_matches('/')))
+        .collect()
+}
+
+// 从消息文本中取出命令名，例如 "/AddUser@my_bot 123" -> "adduser"
+fn command_name(text: &str) -> Option<String> {
+    let command = text.split_whitespace().next()?.strip_prefix('/')?;
+    let name = command.split('@').next()?;
+    Some(name.to_lowercase())
+}
+
+// 需要冷却时间的耗时命令及其名称，新增耗时命令时在这里添加
+fn cooldown_command(cmd: &Command) -> Option<&'static str> {
+    match cmd {
+        Command::Export => Some("export"),
+        Command::Compress => Some("compress"),
+        // 不带 confirm 时只显示消息数量，不需要冷却
+        Command::Summary(arg) if arg.trim().eq_ignore_ascii_case("confirm") => Some("summary"),
+        Command::Translate(_) => Some("translate"),
+        _ => None,
+    }
+}
+
+// 耗时命令在 COMMAND_COOLDOWN_SECS 内重复使用时提示剩余时间并返回 false
+// 只检查，冷却时间由各命令开始执行后调用 start_cooldown 记录；管理员不受限制；没有权限的用户交给各命令的白名单检查处理；查询权限出错时记录日志并放行
+async fn check_command_cooldown(
+    bot: &Bot,
+    msg: &Message,
+    cmd: &Command,
+    db_pool: &db::DatabasePool,
+    access_cache: &AccessCache,
+    cooldowns: &CommandCooldowns,
+    lang: i18n::Lang,
+) -> bool {
+    let (Some(command), Some(user)) = (cooldown_command(cmd), &msg.from) else {
+        return true;
+    };
+
+    match access_cache.evaluate(db_pool, user.id.0).await {
+        Ok(report) if report.allowed && !report.is_admin => {}
+        Ok(_) => return true,
+        Err(e) => {
+            log::error!("检查命令冷却时间错误: {:?}", e);
+            return true;
+        }
+    }
+
+    match cooldowns.remaining(user.id.0, command) {
+        None => true,
+        Some(remaining) => {
+            let seconds = remaining.as_secs_f64().ceil().max(1.0) as u64;
+            let text = t(lang, Key::CommandCooldown).replace("{seconds}", &seconds.to_string());
+            let _ = bot.send_message(msg.chat.id, text).await;
+            false
+        }
+    }
+}
+
+// 耗时命令开始执行后开始计算冷却时间
+fn start_cooldown(cooldowns: &CommandCooldowns, msg: &Message, command: Option<&'static str>) {
+    if let (Some(command), Some(user)) = (command, &msg.from) {
+        cooldowns.mark_used(user.id.0, command);
+    }
+}
+
 // 检查用户是否在白名单中
-async fn check_whitelist(bot: &Bot, msg: &Message, db_pool: &db::DatabasePool) -> bool {
-    if let Some(user) = &msg.from {
-        // 检查是否是管理员或在白名单中
-        match models::Admin::is_admin(db_pool, user.id.0).await {
-            Ok(true) => return true, // 管理员始终允许访问
-            _ => {}
+async fn check_whitelist(
+    bot: &Bot,
+    msg: &Message,
+    db_pool: &db::DatabasePool,
+    access_cache: &AccessCache,
+) -> bool {
+    if !config::whitelist_enabled() {
+        return true;
+    }
+
+    // 频道消息或以频道、匿名管理员身份的发言：没有可以检查白名单的用户，只处理 CHANNEL_ALLOWLIST 中的频道
+    if let Some(sender_chat) = &msg.sender_chat {
+        if config::channel_posts_enabled()
+            && access::sender_chat_allowed(msg, &config::channel_allowlist())
+        {
+            return true;
         }
+        log::debug!("忽略来自聊天 {} 的频道/匿名消息", sender_chat.id);
+        return false;
+    }
 
-        match models::WhitelistUser::is_user_whitelisted(db_pool, user.id.0).await {
-            Ok(true) => return true, // 白名单用户允许访问
-            Ok(false) => {
+    if let Some(user) = &msg.from {
+        match access_cache.evaluate(db_pool, user.id.0).await {
+            Ok(report) if report.allowed => true,
+            Ok(_) => {
                 // 用户不在白名单中，发送提示消息
+                let lang = i18n::chat_lang(db_pool, msg.chat.id.0).await;
                 let _ = bot
-                    .send_message(
-                        msg.chat.id,
-                        "⚠️ 您没有权限使用此机器人。请联系管理员将您添加到白名单。",
-                    )
+                    .send_message(msg.chat.id, t(lang, Key::NotWhitelisted))
                     .await;
-                return false;
+                false
             }
             Err(e) => {
                 log::error!("检查白名单错误: {:?}", e);
+                let lang = i18n::chat_lang(db_pool, msg.chat.id.0).await;
                 let _ = bot
-                    .send_message(
-                        msg.chat.id,
-                        "检查白名单时发生错误，请稍后再试或联系管理员。",
-                    )
+                    .send_message(msg.chat.id, t(lang, Key::WhitelistCheckFailed))
                     .await;
-                return false;
+                false
             }
         }
     } else {
         // 消息没有发送者信息
         log::warn!("消息没有发送者信息");
+        let lang = i18n::chat_lang(db_pool, msg.chat.id.0).await;
         let _ = bot
-            .send_message(msg.chat.id, "无法识别用户信息，请联系管理员。")
+            .send_message(msg.chat.id, t(lang, Key::UnknownUser))
             .await;
-        return false;
+        false
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_command(
     bot: Bot,
     msg: Message,
     cmd: Command,
-    db_pool: &db::DatabasePool,
-    openai_token: &str,
+    shared_db: &db::SharedPool,
+    client: &OpenAiClient,
+    last_errors: &LastErrorStore,
+    focus: &FocusStore,
+    in_flight: &InFlightChats,
+    access_cache: &AccessCache,
+    cooldowns: &CommandCooldowns,
 ) -> ResponseResult<()> {
-    match cmd {
-        Command::Help => {
-            bot.send_message(msg.chat.id, Command::descriptions().to_string())
+    // 取出当前的连接池，处理期间即使被 /dbreconnect 替换也继续使用它
+    let current_db = shared_db.load_full();
+    let db_pool = current_db.as_ref();
+    let lang = i18n::chat_lang(db_pool, msg.chat.id.0).await;
+
+    // 部署时通过 DISABLED_COMMANDS 禁用的命令
+    if let Some(name) = msg.text().and_then(command_name) {
+        if is_command_disabled(&name) {
+            bot.send_message(msg.chat.id, t(lang, Key::CommandDisabled))
                 .await?;
+            return Ok(());
         }
-        Command::Start => {
+    }
+
+    if !check_command_cooldown(&bot, &msg, &cmd, db_pool, access_cache, cooldowns, lang).await {
+        return Ok(());
+    }
+    let cooldown = cooldown_command(&cmd);
+
+    match cmd {
+        Command::Help => {
+            // 只列出未被禁用的命令
+            let help = enabled_bot_commands()
+                .iter()
+                .map(|command| format!("{} — {}", command.command, command.description))
+                .collect::<Vec<String>>()
+                .join("\n");
             bot.send_message(
                 msg.chat.id,
-                "👋 欢迎使用AI聊天机器人!\n\n你可以直接发送文字与我对话，或发送语音消息让我转录。\n使用 /help 查看所有命令。",
+                format!("{}\n\n{}", t(lang, Key::HelpHeader), help),
             )
             .await?;
         }
+        Command::Start => {
+            bot.send_message(msg.chat.id, t(lang, Key::Welcome)).await?;
+        }
         Command::Ping => {
-            bot.send_message(msg.chat.id, "我在线！").await?;
+            bot.send_message(msg.chat.id, t(lang, Key::Pong)).await?;
+        }
+        Command::Whoami => {
+            // 不检查白名单，方便还没有权限的用户查到自己的ID
+            if let Some(from) = &msg.from {
+                match access::evaluate_access(db_pool, from.id.0).await {
+                    Ok(report) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            format_whoami(lang, from, msg.chat.id, &report),
+                        )
+                        .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查用户访问权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::AccessCheckFailed))
+                            .await?;
+                    }
+                }
+            }
         }
         Command::Clear => {
             // 检查用户是否在白名单中
-            if !check_whitelist(&bot, &msg, db_pool).await {
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
                 return Ok(());
             }
 
             match models::Session::clear_history_by_chat_id(db_pool, msg.chat.id.0).await {
                 Ok(_) => {
-                    bot.send_message(msg.chat.id, "已清除聊天历史记录！")
+                    bot.send_message(msg.chat.id, t(lang, Key::HistoryCleared))
                         .await?;
                 }
                 Err(e) => {
                     log::error!("清除历史记录错误: {:?}", e);
-                    bot.send_message(msg.chat.id, "清除聊天历史时发生错误")
+                    bot.send_message(msg.chat.id, t(lang, Key::ClearFailed))
                         .await?;
                 }
             }
         }
-        Command::AddUser(arg) => {
-            // 检查发送者是否是管理员
-            if let Some(from) = &msg.from {
-                match models::Admin::is_admin(db_pool, from.id.0).await {
-                    Ok(true) => {
-                        // 解析用户ID
-                        match arg.trim().parse::<u64>() {
-                            Ok(user_id) => {
-                                // 获取可选备注
-                                let parts: Vec<&str> = arg.splitn(2, ' ').collect();
-                                let notes = if parts.len() > 1 {
-                                    Some(parts[1])
-                                } else {
-                                    None
-                                };
+        Command::Regenerate => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
 
-                                // 添加用户到白名单
-                                match models::WhitelistUser::add_user(
-                                    db_pool, user_id, None, from.id.0, notes,
-                                )
-                                .await
-                                {
-                                    Ok(_) => {
-                                        bot.send_message(
-                                            msg.chat.id,
-                                            format!("✅ 成功添加用户 {} 到白名单", user_id),
-                                        )
-                                        .await?;
-                                    }
-                                    Err(e) => {
-                                        log::error!("添加白名单用户错误: {:?}", e);
-                                        bot.send_message(msg.chat.id, "添加用户到白名单时发生错误")
-                                            .await?;
-                                    }
-                                }
-                            }
-                            Err(_) => {
-                                bot.send_message(
-                                    msg.chat.id,
-                                    "请提供有效的用户ID，格式：/adduser [用户ID] [备注]",
-                                )
-                                .await?;
-                            }
-                        }
-                    }
-                    Ok(false) => {
-                        bot.send_message(msg.chat.id, "⚠️ 您没有管理员权限，无法添加白名单用户")
-                            .await?;
-                    }
-                    Err(e) => {
-                        log::error!("检查管理员权限错误: {:?}", e);
-                        bot.send_message(msg.chat.id, "检查管理员权限时发生错误")
+            let user_id = msg.from.as_ref().map(|user| user.id.0);
+            regenerate_last_reply(
+                &bot,
+                msg.chat.id,
+                user_id,
+                None,
+                db_pool,
+                client,
+                last_errors,
+                in_flight,
+            )
+            .await?;
+        }
+        Command::Forget(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let count = match arg.trim() {
+                "" => 1,
+                value => match value.parse::<i64>() {
+                    Ok(count) if count >= 1 => count,
+                    _ => {
+                        bot.send_message(msg.chat.id, t(lang, Key::ForgetUsage))
                             .await?;
+                        return Ok(());
                     }
+                },
+            };
+
+            // 正在生成回复时不删除，避免删掉本次的提问后又保存了回复
+            let chat_id = msg.chat.id;
+            let Some(_in_flight) = acquire_in_flight(&bot, chat_id, db_pool, in_flight).await?
+            else {
+                return Ok(());
+            };
+
+            let deleted = match models::Session::find_or_create_by_chat_id(db_pool, chat_id.0).await
+            {
+                Ok(session_id) => models::Message::delete_last_n(db_pool, session_id, count).await,
+                Err(e) => Err(e),
+            };
+            let text = match deleted {
+                Ok(0) => t(lang, Key::ForgetNothing).to_string(),
+                Ok(deleted) => t(lang, Key::ForgetDone).replace("{count}", &deleted.to_string()),
+                Err(e) => {
+                    log::error!("删除最近的消息记录错误: {:?}", e);
+                    t(lang, Key::ForgetFailed).to_string()
+                }
+            };
+            bot.send_message(chat_id, text).await?;
+        }
+        Command::Export => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            start_cooldown(cooldowns, &msg, cooldown);
+            let chat_id = msg.chat.id;
+            let path = export::temp_path(chat_id.0);
+            let sent = match export::write_chat_history(db_pool, chat_id.0, &path).await {
+                Ok(0) => bot.send_message(chat_id, t(lang, Key::ExportEmpty)).await,
+                Ok(count) => {
+                    let file = InputFile::file(&path).file_name(export::file_name(chat_id.0));
+                    bot.send_document(chat_id, file)
+                        .caption(t(lang, Key::ExportCaption).replace("{count}", &count.to_string()))
+                        .await
+                }
+                Err(e) => {
+                    log::error!("导出聊天记录错误: {:?}", e);
+                    bot.send_message(chat_id, t(lang, Key::ExportFailed)).await
+                }
+            };
+
+            // 无论发送是否成功都删除临时文件
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("删除导出临时文件失败: {:?}", e);
                 }
             }
+            sent?;
         }
-        Command::RemoveUser(arg) => {
-            // 检查发送者是否是管理员
-            if let Some(from) = &msg.from {
-                match models::Admin::is_admin(db_pool, from.id.0).await {
-                    Ok(true) => {
-                        // 解析用户ID
-                        match arg.trim().parse::<u64>() {
-                            Ok(user_id) => {
-                                // 从白名单移除用户
-                                match models::WhitelistUser::remove_user(db_pool, user_id).await {
-                                    Ok(true) => {
-                                        bot.send_message(
-                                            msg.chat.id,
-                                            format!("✅ 已从白名单中移除用户 {}", user_id),
-                                        )
-                                        .await?;
-                                    }
-                                    Ok(false) => {
-                                        bot.send_message(
-                                            msg.chat.id,
-                                            format!("⚠️ 用户 {} 不在白名单中", user_id),
-                                        )
-                                        .await?;
-                                    }
-                                    Err(e) => {
-                                        log::error!("移除白名单用户错误: {:?}", e);
-                                        bot.send_message(msg.chat.id, "移除用户时发生错误").await?;
-                                    }
-                                }
-                            }
-                            Err(_) => {
-                                bot.send_message(
-                                    msg.chat.id,
-                                    "请提供有效的用户ID，格式：/removeuser [用户ID]",
-                                )
-                                .await?;
-                            }
-                        }
-                    }
-                    Ok(false) => {
-                        bot.send_message(msg.chat.id, "⚠️ 您没有管理员权限，无法移除白名单用户")
-                            .await?;
-                    }
-                    Err(e) => {
-                        log::error!("检查管理员权限错误: {:?}", e);
-                        bot.send_message(msg.chat.id, "检查管理员权限时发生错误")
-                            .await?;
-                    }
+        Command::Timestamps(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let enabled = match arg.trim().to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    bot.send_message(msg.chat.id, t(lang, Key::TimestampsUsage))
+                        .await?;
+                    return Ok(());
+                }
+            };
+
+            match models::Session::set_show_timestamps(db_pool, msg.chat.id.0, enabled).await {
+                Ok(_) => {
+                    let key = if enabled {
+                        Key::TimestampsOn
+                    } else {
+                        Key::TimestampsOff
+                    };
+                    bot.send_message(msg.chat.id, t(lang, key)).await?;
+                }
+                Err(e) => {
+                    log::error!("设置时间戳选项错误: {:?}", e);
+                    bot.send_message(msg.chat.id, t(lang, Key::SaveFailed))
+                        .await?;
                 }
             }
         }
-        Command::ListUsers => {
-            // 检查发送者是否是管理员
-            if let Some(from) = &msg.from {
-                match models::Admin::is_admin(db_pool, from.id.0).await {
-                    Ok(true) => {
-                        // 获取白名单用户列表
-                        match models::WhitelistUser::get_all_users(db_pool).await {
-                            Ok(users) => {
-                                let user_list = users
-                                    .iter()
-                                    .map(|user| {
-                                        format!("ID: {}, 备注: {:?}", user.user_id, user.notes)
-                                    })
-                                    .collect::<Vec<String>>()
-                                    .join("\n");
+        Command::Compress => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
 
-                                bot.send_message(
-                                    msg.chat.id,
-                                    format!("白名单用户列表:\n{}", user_list),
-                                )
-                                .await?;
-                            }
-                            Err(e) => {
-                                log::error!("获取白名单用户列表错误: {:?}", e);
-                                bot.send_message(msg.chat.id, "获取白名单用户列表时发生错误")
-                                    .await?;
-                            }
+            // 压缩期间不处理本聊天的新消息，避免新消息被一起压缩或删除
+            let Some(_in_flight) = acquire_in_flight(&bot, msg.chat.id, db_pool, in_flight).await?
+            else {
+                return Ok(());
+            };
+            let user_id = msg.from.as_ref().map(|user| user.id.0);
+            if !check_rate_limit(&bot, msg.chat.id, user_id, db_pool).await {
+                return Ok(());
+            }
+
+            start_cooldown(cooldowns, &msg, cooldown);
+            let thinking_message = bot
+                .send_message(msg.chat.id, t(lang, Key::Compressing))
+                .await?;
+            let model = resolve_model(db_pool, msg.chat.id.0, user_id)
+                .await
+                .unwrap_or_else(|_| DEFAULT_MODEL.to_string());
+            let text = match compress_history(db_pool, msg.chat.id.0, client, &model).await {
+                Ok(Some((collapsed, saved_tokens))) => t(lang, Key::Compressed)
+                    .replace("{count}", &collapsed.to_string())
+                    .replace("{tokens}", &saved_tokens.to_string()),
+                Ok(None) => t(lang, Key::NothingToCompress).to_string(),
+                Err(e) => {
+                    let trace_id = last_errors.record(msg.chat.id.0, &e.to_string());
+                    log::error!("[{}] 压缩对话错误: {:?}", trace_id, e);
+                    error::user_message(lang, e.as_ref()).to_string()
+                }
+            };
+            bot.edit_message_text(msg.chat.id, thinking_message.id, text)
+                .await?;
+        }
+        Command::Summary(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let chat_id = msg.chat.id;
+            match arg.trim() {
+                // 替换后原始消息无法恢复，先告知消息数量并要求确认
+                "" => {
+                    let text = match count_session_messages(db_pool, chat_id.0).await {
+                        Ok(count) if count < 2 => t(lang, Key::SummaryNothing).to_string(),
+                        Ok(count) => {
+                            t(lang, Key::SummaryConfirm).replace("{count}", &count.to_string())
                         }
+                        Err(e) => {
+                            log::error!("读取聊天历史错误: {:?}", e);
+                            t(lang, Key::SummaryReadFailed).to_string()
+                        }
+                    };
+                    bot.send_message(chat_id, text).await?;
+                }
+                arg if arg.eq_ignore_ascii_case("confirm") => {
+                    // 总结期间不处理本聊天的新消息，避免新消息被一起删除
+                    let Some(_in_flight) =
+                        acquire_in_flight(&bot, chat_id, db_pool, in_flight).await?
+                    else {
+                        return Ok(());
+                    };
+                    let user_id = msg.from.as_ref().map(|user| user.id.0);
+                    if !check_rate_limit(&bot, chat_id, user_id, db_pool).await {
+                        return Ok(());
                     }
-                    Ok(false) => {
-                        bot.send_message(msg.chat.id, "⚠️ 您没有管理员权限，无法查看白名单用户")
-                            .await?;
-                    }
-                    Err(e) => {
-                        log::error!("检查管理员权限错误: {:?}", e);
-                        bot.send_message(msg.chat.id, "检查管理员权限时发生错误")
-                            .await?;
-                    }
+
+                    start_cooldown(cooldowns, &msg, cooldown);
+                    let thinking_message =
+                        bot.send_message(chat_id, t(lang, Key::Summarizing)).await?;
+                    let model = resolve_model(db_pool, chat_id.0, user_id)
+                        .await
+                        .unwrap_or_else(|_| DEFAULT_MODEL.to_string());
+                    let text = match summarize_history(db_pool, chat_id.0, client, &model).await {
+                        Ok(Some((count, summary))) => format!(
+                            "{}\n\n{}",
+                            t(lang, Key::SummaryDone).replace("{count}", &count.to_string()),
+                            summary
+                        ),
+                        Ok(None) => t(lang, Key::SummaryNothing).to_string(),
+                        Err(e) => {
+                            let trace_id = last_errors.record(chat_id.0, &e.to_string());
+                            log::error!("[{}] 总结对话错误: {:?}", trace_id, e);
+                            error::user_message(lang, e.as_ref()).to_string()
+                        }
+                    };
+                    bot.edit_message_text(chat_id, thinking_message.id, text)
+                        .await?;
+                }
+                _ => {
+                    bot.send_message(chat_id, t(lang, Key::SummaryUsage))
+                        .await?;
                 }
             }
         }
-        Command::AddAdmin(arg) => {
-            // 检查发送者是否是超级管理员
-            if let Some(from) = &msg.from {
-                match models::Admin::is_super_admin(db_pool, from.id.0).await {
-                    Ok(true) => {
-                        // 解析用户ID
-                        match arg.trim().parse::<u64>() {
-                            Ok(user_id) => {
-                                // 添加管理员
-                                match models::Admin::add_admin(db_pool, user_id, None, false).await
-                                {
-                                    Ok(_) => {
-                                        bot.send_message(
-                                            msg.chat.id,
-                                            format!("✅ 成功添加管理员 {}", user_id),
-                                        )
-                                        .await?;
-                                    }
-                                    Err(e) => {
-                                        log::error!("添加管理员错误: {:?}", e);
-                                        bot.send_message(msg.chat.id, "添加管理员时发生错误")
-                                            .await?;
-                                    }
-                                }
-                            }
-                            Err(_) => {
-                                bot.send_message(
-                                    msg.chat.id,
-                                    "请提供有效的用户ID，格式：/addadmin [用户ID]",
-                                )
-                                .await?;
-                            }
-                        }
-                    }
-                    Ok(false) => {
-                        bot.send_message(msg.chat.id, "⚠️ 您没有超级管理员权限，无法添加管理员")
-                            .await?;
-                    }
-                    Err(e) => {
-                        log::error!("检查超级管理员权限错误: {:?}", e);
-                        bot.send_message(msg.chat.id, "检查超级管理员权限时发生错误")
+        Command::Privacy => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            if !config::privacy_consent_required() {
+                bot.send_message(msg.chat.id, t(lang, Key::PrivacyNotRequired))
+                    .await?;
+                return Ok(());
+            }
+
+            match models::Session::get_privacy_consent(db_pool, msg.chat.id.0).await {
+                Ok(consent) => {
+                    let keyboard = if consent.as_deref() == Some(privacy::GRANTED) {
+                        privacy::revoke_keyboard(lang)
+                    } else {
+                        privacy::consent_keyboard(lang)
+                    };
+                    bot.send_message(msg.chat.id, privacy::status_text(lang, consent.as_deref()))
+                        .reply_markup(keyboard)
+                        .await?;
+                }
+                Err(e) => {
+                    log::error!("读取隐私同意状态错误: {:?}", e);
+                    bot.send_message(msg.chat.id, t(lang, Key::ReadFailed))
+                        .await?;
+                }
+            }
+        }
+        Command::Tokens(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let text = match arg.trim() {
+                "" => quote::quoted_context(&msg),
+                text => Some(text.to_string()),
+            };
+
+            match text {
+                Some(text) => {
+                    let user_id = msg.from.as_ref().map(|user| user.id.0);
+                    let model = resolve_model(db_pool, msg.chat.id.0, user_id)
+                        .await
+                        .unwrap_or_else(|_| DEFAULT_MODEL.to_string());
+                    let (tokens, exact) = context::count_tokens(&text, &model);
+                    let method = if exact {
+                        Key::TokensExact
+                    } else {
+                        Key::TokensEstimated
+                    };
+                    let text = t(lang, Key::TokenCount)
+                        .replace("{tokens}", &tokens.to_string())
+                        .replace("{model}", &model)
+                        .replace("{method}", t(lang, method));
+                    bot.send_message(msg.chat.id, text).await?;
+                }
+                None => {
+                    bot.send_message(msg.chat.id, t(lang, Key::TokensUsage))
+                        .await?;
+                }
+            }
+        }
+        Command::Translate(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            // 回复某条消息时翻译那条消息，否则翻译语言代码后面的文本
+            let (lang_code, text) = commands::parse_translate_args(&arg);
+            let text = quote::quoted_context(&msg).or(text);
+            let (Some(lang_code), Some(text)) = (lang_code, text) else {
+                bot.send_message(msg.chat.id, t(lang, Key::TranslateUsage))
+                    .await?;
+                return Ok(());
+            };
+            if !commands::is_valid_language_code(lang_code) {
+                bot.send_message(msg.chat.id, t(lang, Key::InvalidLanguageCode))
+                    .await?;
+                return Ok(());
+            }
+
+            let user_id = msg.from.as_ref().map(|user| user.id.0);
+            if !check_rate_limit(&bot, msg.chat.id, user_id, db_pool).await {
+                return Ok(());
+            }
+
+            start_cooldown(cooldowns, &msg, cooldown);
+            let thinking_message = thinking::send_thinking(&bot, msg.chat.id, lang).await?;
+            let typing = typing::TypingIndicator::start(bot.clone(), msg.chat.id);
+            let model = resolve_model(db_pool, msg.chat.id.0, user_id)
+                .await
+                .unwrap_or_else(|_| DEFAULT_MODEL.to_string());
+            // 译文只发送给用户，不保存到对话历史，避免影响之后的上下文
+            let result = translate_text(client, &model, lang_code, &text).await;
+            drop(typing);
+
+            match result {
+                Ok(translation) => {
+                    thinking::delete_thinking(&bot, msg.chat.id, thinking_message.as_ref()).await;
+                    send_reply(&bot, msg.chat.id, &translation, Some(msg.id), None).await?;
+                }
+                Err(e) => {
+                    let trace_id = last_errors.record(msg.chat.id.0, &e.to_string());
+                    log::error!("[{}] 翻译错误: {:?}", trace_id, e);
+                    let text = error_reply(db_pool, lang, user_id, &e).await;
+                    thinking::show_in_thinking(&bot, msg.chat.id, thinking_message.as_ref(), text)
+                        .await?;
+                }
+            }
+        }
+        Command::VoiceAssistant(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let enabled = match arg.trim().to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    bot.send_message(msg.chat.id, t(lang, Key::VoiceAssistantUsage))
+                        .await?;
+                    return Ok(());
+                }
+            };
+
+            match models::Session::set_voice_assistant(db_pool, msg.chat.id.0, enabled).await {
+                Ok(_) => {
+                    let key = if enabled {
+                        Key::VoiceAssistantOn
+                    } else {
+                        Key::VoiceAssistantOff
+                    };
+                    bot.send_message(msg.chat.id, t(lang, key)).await?;
+                }
+                Err(e) => {
+                    log::error!("设置语音助手模式错误: {:?}", e);
+                    bot.send_message(msg.chat.id, t(lang, Key::SaveFailed))
+                        .await?;
+                }
+            }
+        }
+        Command::ReplyLang(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let code = arg.trim();
+            let reply_lang = if code.eq_ignore_ascii_case("default") {
+                None
+            } else if code.eq_ignore_ascii_case("auto") {
+                Some("auto")
+            } else if commands::is_valid_language_code(code) {
+                Some(code)
+            } else {
+                bot.send_message(msg.chat.id, t(lang, Key::ReplyLangUsage))
+                    .await?;
+                return Ok(());
+            };
+
+            match models::Session::set_reply_lang(db_pool, msg.chat.id.0, reply_lang).await {
+                Ok(_) => {
+                    let text = match reply_lang {
+                        None => t(lang, Key::ReplyLangDefault)
+                            .replace("{lang}", &config::default_reply_language()),
+                        Some("auto") => t(lang, Key::ReplyLangAuto).to_string(),
+                        Some(code) => t(lang, Key::ReplyLangSet).replace("{lang}", code),
+                    };
+                    bot.send_message(msg.chat.id, text).await?;
+                }
+                Err(e) => {
+                    log::error!("设置回复语言错误: {:?}", e);
+                    bot.send_message(msg.chat.id, t(lang, Key::SaveFailed))
+                        .await?;
+                }
+            }
+        }
+        Command::Lang(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let code = arg.trim();
+            if code.is_empty() {
+                bot.send_message(
+                    msg.chat.id,
+                    t(lang, Key::LangCurrent).replace("{lang}", lang.code()),
+                )
+                .await?;
+                return Ok(());
+            }
+
+            let Some(new_lang) = i18n::Lang::from_code(code) else {
+                bot.send_message(
+                    msg.chat.id,
+                    t(lang, Key::LangUnsupported)
+                        .replace("{codes}", &i18n::SUPPORTED_CODES.join(", ")),
+                )
+                .await?;
+                return Ok(());
+            };
+
+            match models::Session::set_ui_lang(db_pool, msg.chat.id.0, new_lang.code()).await {
+                Ok(_) => {
+                    bot.send_message(msg.chat.id, t(new_lang, Key::LangSet))
+                        .await?;
+                }
+                Err(e) => {
+                    log::error!("设置界面语言错误: {:?}", e);
+                    bot.send_message(msg.chat.id, t(lang, Key::SaveFailed))
+                        .await?;
+                }
+            }
+        }
+        Command::Model(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let name = arg.trim();
+            let model = if name.eq_ignore_ascii_case("default") {
+                None
+            } else if let Some(model) = ALLOWED_MODELS
+                .iter()
+                .find(|model| model.eq_ignore_ascii_case(name))
+            {
+                Some(*model)
+            } else {
+                let text = t(lang, Key::ModelUsage).replace("{models}", &ALLOWED_MODELS.join(", "));
+                bot.send_message(msg.chat.id, text).await?;
+                return Ok(());
+            };
+
+            match models::Session::set_model(db_pool, msg.chat.id.0, model).await {
+                Ok(_) => {
+                    let text = match model {
+                        Some(model) => t(lang, Key::ModelSet).replace("{model}", model),
+                        None => t(lang, Key::ModelDefault).replace("{model}", DEFAULT_MODEL),
+                    };
+                    bot.send_message(msg.chat.id, text).await?;
+                }
+                Err(e) => {
+                    log::error!("设置模型错误: {:?}", e);
+                    bot.send_message(msg.chat.id, t(lang, Key::SaveFailed))
+                        .await?;
+                }
+            }
+        }
+        Command::MyModel(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let Some(from) = &msg.from else {
+                return Ok(());
+            };
+
+            let name = arg.trim();
+            let model = if name.eq_ignore_ascii_case("default") {
+                None
+            } else if let Some(model) = ALLOWED_MODELS
+                .iter()
+                .find(|model| model.eq_ignore_ascii_case(name))
+            {
+                Some(*model)
+            } else {
+                let text =
+                    t(lang, Key::MyModelUsage).replace("{models}", &ALLOWED_MODELS.join(", "));
+                bot.send_message(msg.chat.id, text).await?;
+                return Ok(());
+            };
+
+            match models::UserPreference::set(db_pool, from.id.0, model).await {
+                Ok(_) => {
+                    let text = match model {
+                        Some(model) => t(lang, Key::MyModelSet).replace("{model}", model),
+                        None => t(lang, Key::MyModelCleared).replace("{model}", DEFAULT_MODEL),
+                    };
+                    bot.send_message(msg.chat.id, text).await?;
+                }
+                Err(e) => {
+                    log::error!("设置个人默认模型错误: {:?}", e);
+                    bot.send_message(msg.chat.id, t(lang, Key::SaveFailed))
+                        .await?;
+                }
+            }
+        }
+        Command::MySettings => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let Some(from) = &msg.from else {
+                return Ok(());
+            };
+
+            let settings = async {
+                let preference = models::UserPreference::get(db_pool, from.id.0).await?;
+                let effective = resolve_model(db_pool, msg.chat.id.0, Some(from.id.0)).await?;
+                Ok::<_, Box<dyn Error + Send + Sync>>((preference, effective))
+            }
+            .await;
+
+            match settings {
+                Ok((preference, effective)) => {
+                    let model = match preference.model {
+                        Some(model) => model,
+                        None => t(lang, Key::ModelNotSet).replace("{model}", DEFAULT_MODEL),
+                    };
+                    let text = t(lang, Key::MySettings)
+                        .replace("{model}", &model)
+                        .replace("{effective}", &effective);
+                    bot.send_message(msg.chat.id, text).await?;
+                }
+                Err(e) => {
+                    log::error!("读取个人设置错误: {:?}", e);
+                    bot.send_message(msg.chat.id, t(lang, Key::ReadFailed))
+                        .await?;
+                }
+            }
+        }
+        Command::Temperature(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let value = arg.trim();
+            let temperature = if value.eq_ignore_ascii_case("default") {
+                None
+            } else {
+                match parse_temperature(value) {
+                    Some(temperature) => Some(temperature),
+                    None => {
+                        bot.send_message(msg.chat.id, t(lang, Key::TemperatureUsage))
                             .await?;
+                        return Ok(());
                     }
                 }
+            };
+
+            match models::Session::set_temperature(db_pool, msg.chat.id.0, temperature).await {
+                Ok(_) => {
+                    let text = match temperature {
+                        Some(temperature) => t(lang, Key::TemperatureSet)
+                            .replace("{temperature}", &temperature.to_string()),
+                        None => t(lang, Key::TemperatureDefault)
+                            .replace("{temperature}", &config::TEMPERATURE.to_string()),
+                    };
+                    bot.send_message(msg.chat.id, text).await?;
+                }
+                Err(e) => {
+                    log::error!("设置 temperature 错误: {:?}", e);
+                    bot.send_message(msg.chat.id, t(lang, Key::SaveFailed))
+                        .await?;
+                }
             }
         }
-        Command::ListAdmins => {
-            // 检查发送者是否是管理员
-            if let Some(from) = &msg.from {
-                match models::Admin::is_admin(db_pool, from.id.0).await {
-                    Ok(true) => {
-                        // 获取管理员列表
-                        match models::Admin::get_all_admins(db_pool).await {
-                            Ok(admins) => {
-                                let admin_list = admins
-                                    .iter()
-                                    .map(|admin| format!("ID: {}", admin.user_id))
-                                    .collect::<Vec<String>>()
-                                    .join("\n");
+        Command::Context(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let chat_id = msg.chat.id;
+            let value = arg.trim();
+            if value.is_empty() {
+                let text = match models::Session::get_history_limit(db_pool, chat_id.0).await {
+                    Ok(Some(limit)) => t(lang, Key::ContextChatSetting).replace(
+                        "{limit}",
+                        &config::effective_history_limit(Some(limit)).to_string(),
+                    ),
+                    Ok(None) => t(lang, Key::ContextDefaultSetting)
+                        .replace("{limit}", &config::history_limit().to_string()),
+                    Err(e) => {
+                        log::error!("读取历史消息条数设置错误: {:?}", e);
+                        t(lang, Key::ReadFailed).to_string()
+                    }
+                };
+                bot.send_message(chat_id, text).await?;
+                return Ok(());
+            }
+
+            let limit = if value.eq_ignore_ascii_case("default") {
+                None
+            } else {
+                match value.parse::<i64>() {
+                    // 超过上限时按上限保存，避免上下文过长
+                    Ok(limit) if limit >= 1 => Some(limit.min(config::MAX_HISTORY_LIMIT)),
+                    _ => {
+                        let text = t(lang, Key::ContextUsage)
+                            .replace("{max}", &config::MAX_HISTORY_LIMIT.to_string());
+                        bot.send_message(chat_id, text).await?;
+                        return Ok(());
+                    }
+                }
+            };
+
+            match models::Session::set_history_limit(db_pool, chat_id.0, limit).await {
+                Ok(_) => {
+                    let text = match limit {
+                        Some(limit) => {
+                            t(lang, Key::ContextSet).replace("{limit}", &limit.to_string())
+                        }
+                        None => t(lang, Key::ContextDefault)
+                            .replace("{limit}", &config::history_limit().to_string()),
+                    };
+                    bot.send_message(chat_id, text).await?;
+                }
+                Err(e) => {
+                    log::error!("设置历史消息条数错误: {:?}", e);
+                    bot.send_message(chat_id, t(lang, Key::SaveFailed)).await?;
+                }
+            }
+        }
+        Command::LastError => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let text = match last_errors.get(msg.chat.id.0) {
+                // 错误详情最后替换，避免其中的占位符被替换
+                Some(error) => t(lang, Key::LastError)
+                    .replace(
+                        "{time}",
+                        &error.occurred_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    )
+                    .replace("{trace_id}", &error.trace_id.to_string())
+                    .replace("{detail}", &error.message),
+                None => t(lang, Key::NoLastError).to_string(),
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        Command::Focus(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            match arg.trim().to_lowercase().as_str() {
+                "on" => {
+                    focus.set_enabled(msg.chat.id.0, true);
+                    let text = t(lang, Key::FocusOn)
+                        .replace("{seconds}", &focus::debounce_window().as_secs().to_string());
+                    bot.send_message(msg.chat.id, text).await?;
+                }
+                "off" => {
+                    focus.set_enabled(msg.chat.id.0, false);
+                    bot.send_message(msg.chat.id, t(lang, Key::FocusOff))
+                        .await?;
+                }
+                _ => {
+                    bot.send_message(msg.chat.id, t(lang, Key::FocusUsage))
+                        .await?;
+                }
+            }
+        }
+        Command::SetName(arg) => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let name = match parse_bot_name(&arg) {
+                Ok(name) => name,
+                Err(reason) => {
+                    let usage = t(lang, Key::SetNameUsage)
+                        .replace("{max}", &MAX_BOT_NAME_CHARS.to_string());
+                    bot.send_message(msg.chat.id, format!("{}\n{}", t(lang, reason), usage))
+                        .await?;
+                    return Ok(());
+                }
+            };
+
+            match models::Session::set_bot_name(db_pool, msg.chat.id.0, name.as_deref()).await {
+                Ok(_) => {
+                    let text = match &name {
+                        Some(name) => t(lang, Key::BotNameSet).replace("{name}", name),
+                        None => t(lang, Key::BotNameCleared).to_string(),
+                    };
+                    bot.send_message(msg.chat.id, text).await?;
+                }
+                Err(e) => {
+                    log::error!("设置助手名字错误: {:?}", e);
+                    bot.send_message(msg.chat.id, t(lang, Key::SaveFailed))
+                        .await?;
+                }
+            }
+        }
+        Command::Settings => {
+            // 检查用户是否在白名单中
+            if !check_whitelist(&bot, &msg, db_pool, access_cache).await {
+                return Ok(());
+            }
+
+            let chat_id = msg.chat.id.0;
+            let settings = async {
+                let reply_lang = models::Session::get_reply_lang(db_pool, chat_id).await?;
+                let show_timestamps =
+                    models::Session::get_show_timestamps(db_pool, chat_id).await?;
+                let voice_assistant =
+                    models::Session::get_voice_assistant(db_pool, chat_id).await?;
+                let model = models::Session::get_model(db_pool, chat_id).await?;
+                let temperature = models::Session::get_temperature(db_pool, chat_id).await?;
+                let history_limit = models::Session::get_history_limit(db_pool, chat_id).await?;
+                let bot_name = models::Session::get_bot_name(db_pool, chat_id).await?;
+                Ok::<_, Box<dyn Error + Send + Sync>>((
+                    reply_lang,
+                    show_timestamps,
+                    voice_assistant,
+                    model,
+                    temperature,
+                    history_limit,
+                    bot_name,
+                ))
+            }
+            .await;
+
+            match settings {
+                Ok((
+                    reply_lang,
+                    show_timestamps,
+                    voice_assistant,
+                    model,
+                    temperature,
+                    history_limit,
+                    bot_name,
+                )) => {
+                    let chat_setting =
+                        |value: String| t(lang, Key::ChatSetting).replace("{value}", &value);
+                    let default_setting =
+                        |value: String| t(lang, Key::DefaultSetting).replace("{value}", &value);
+                    let on_off = |enabled: bool| t(lang, if enabled { Key::On } else { Key::Off });
+
+                    let reply_lang = match reply_lang {
+                        Some(reply_lang) => chat_setting(reply_lang),
+                        None => default_setting(config::default_reply_language()),
+                    };
+                    let model = match model {
+                        Some(_) => chat_setting(chat_model(model, None)),
+                        None => t(lang, Key::SettingsModelNotSet).to_string(),
+                    };
+                    let temperature = match temperature {
+                        Some(temperature) => chat_setting(temperature.to_string()),
+                        None => default_setting(config::TEMPERATURE.to_string()),
+                    };
+                    let history_limit = match history_limit {
+                        Some(limit) => {
+                            chat_setting(config::effective_history_limit(Some(limit)).to_string())
+                        }
+                        None => default_setting(config::history_limit().to_string()),
+                    };
+                    let bot_name = bot_name.unwrap_or_else(|| t(lang, Key::NotSet).to_string());
+
+                    // 助手名字由用户设置，最后替换，避免其中的占位符被替换
+                    let text = t(lang, Key::Settings)
+                        .replace("{model}", &model)
+                        .replace("{temperature}", &temperature)
+                        .replace("{history_limit}", &history_limit)
+                        .replace("{reply_lang}", &reply_lang)
+                        .replace("{ui_lang}", lang.code())
+                        .replace("{timestamps}", on_off(show_timestamps))
+                        .replace("{focus}", on_off(focus.is_enabled(chat_id)))
+                        .replace("{voice_assistant}", on_off(voice_assistant))
+                        .replace("{bot_name}", &bot_name);
+                    bot.send_message(msg.chat.id, text).await?;
+                }
+                Err(e) => {
+                    log::error!("读取聊天设置错误: {:?}", e);
+                    bot.send_message(msg.chat.id, t(lang, Key::ReadFailed))
+                        .await?;
+                }
+            }
+        }
+        Command::AddUser(arg) => {
+            if !config::whitelist_enabled() {
+                bot.send_message(msg.chat.id, t(lang, Key::WhitelistDisabled))
+                    .await?;
+                return Ok(());
+            }
+
+            // 检查发送者是否是管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_admin(db_pool, from.id.0).await {
+                    Ok(true) => {
+                        // 解析用户ID、可选的用户名和备注
+                        match commands::parse_add_user_args(&arg) {
+                            Some((user_id, username, notes)) => {
+                                // 没有提供用户名时尝试从 Telegram 查询
+                                let username = match username {
+                                    Some(username) => Some(username.to_string()),
+                                    None => lookup_username(&bot, user_id).await,
+                                };
+
+                                // 已停用的用户直接重新启用，保留原来的添加记录并更新用户名和备注；否则添加到白名单
+                                let result = match models::WhitelistUser::reactivate(
+                                    db_pool,
+                                    user_id,
+                                    username.as_deref(),
+                                    notes,
+                                )
+                                .await
+                                {
+                                    Ok(true) => Ok(true),
+                                    Ok(false) => models::WhitelistUser::add_user(
+                                        db_pool,
+                                        user_id,
+                                        username.as_deref(),
+                                        from.id.0,
+                                        notes,
+                                    )
+                                    .await
+                                    .map(|_| false),
+                                    Err(e) => Err(e),
+                                };
+                                match result {
+                                    Ok(reactivated) => {
+                                        access_cache.invalidate(user_id);
+                                        let user = match username {
+                                            Some(username) => {
+                                                format!("{} (@{})", user_id, username)
+                                            }
+                                            None => user_id.to_string(),
+                                        };
+                                        let key = if reactivated {
+                                            Key::UserReactivated
+                                        } else {
+                                            Key::UserAdded
+                                        };
+                                        let text = t(lang, key).replace("{user}", &user);
+                                        bot.send_message(msg.chat.id, text).await?;
+                                    }
+                                    Err(e) => {
+                                        log::error!("添加白名单用户错误: {:?}", e);
+                                        bot.send_message(msg.chat.id, t(lang, Key::AddUserFailed))
+                                            .await?;
+                                    }
+                                }
+                            }
+                            None => {
+                                bot.send_message(msg.chat.id, t(lang, Key::AddUserUsage))
+                                    .await?;
+                            }
+                        }
+                    }
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::AddUserNotAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::AdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::RemoveUser(arg) => {
+            if !config::whitelist_enabled() {
+                bot.send_message(msg.chat.id, t(lang, Key::WhitelistDisabled))
+                    .await?;
+                return Ok(());
+            }
+
+            // 检查发送者是否是管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_admin(db_pool, from.id.0).await {
+                    Ok(true) => {
+                        // 解析用户ID和可选的 --purge
+                        match commands::parse_remove_user_args(&arg) {
+                            Some((user_id, true)) => {
+                                // 彻底删除会丢失添加记录，只允许超级管理员操作
+                                match models::Admin::is_super_admin(db_pool, from.id.0).await {
+                                    Ok(true) => {
+                                        match models::WhitelistUser::remove_user(db_pool, user_id)
+                                            .await
+                                        {
+                                            Ok(true) => {
+                                                access_cache.invalidate(user_id);
+                                                let text = t(lang, Key::UserPurged)
+                                                    .replace("{user}", &user_id.to_string());
+                                                bot.send_message(msg.chat.id, text).await?;
+                                            }
+                                            Ok(false) => {
+                                                let text = t(lang, Key::UserNotWhitelisted)
+                                                    .replace("{user}", &user_id.to_string());
+                                                bot.send_message(msg.chat.id, text).await?;
+                                            }
+                                            Err(e) => {
+                                                log::error!("删除白名单用户错误: {:?}", e);
+                                                bot.send_message(
+                                                    msg.chat.id,
+                                                    t(lang, Key::PurgeUserFailed),
+                                                )
+                                                .await?;
+                                            }
+                                        }
+                                    }
+                                    Ok(false) => {
+                                        bot.send_message(
+                                            msg.chat.id,
+                                            t(lang, Key::PurgeSuperAdminOnly),
+                                        )
+                                        .await?;
+                                    }
+                                    Err(e) => {
+                                        log::error!("检查超级管理员权限错误: {:?}", e);
+                                        bot.send_message(
+                                            msg.chat.id,
+                                            t(lang, Key::SuperAdminCheckFailed),
+                                        )
+                                        .await?;
+                                    }
+                                }
+                            }
+                            Some((user_id, false)) => {
+                                // 默认只停用，保留添加记录，之后可以用 /adduser 重新启用
+                                match models::WhitelistUser::deactivate(db_pool, user_id).await {
+                                    Ok(true) => {
+                                        access_cache.invalidate(user_id);
+                                        let text = t(lang, Key::UserDeactivated)
+                                            .replace("{user}", &user_id.to_string());
+                                        bot.send_message(msg.chat.id, text).await?;
+                                    }
+                                    Ok(false) => {
+                                        let text = t(lang, Key::UserNotActive)
+                                            .replace("{user}", &user_id.to_string());
+                                        bot.send_message(msg.chat.id, text).await?;
+                                    }
+                                    Err(e) => {
+                                        log::error!("停用白名单用户错误: {:?}", e);
+                                        bot.send_message(
+                                            msg.chat.id,
+                                            t(lang, Key::RemoveUserFailed),
+                                        )
+                                        .await?;
+                                    }
+                                }
+                            }
+                            None => {
+                                bot.send_message(msg.chat.id, t(lang, Key::RemoveUserUsage))
+                                    .await?;
+                            }
+                        }
+                    }
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::RemoveUserNotAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::AdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::ListUsers(arg) => {
+            if !config::whitelist_enabled() {
+                bot.send_message(msg.chat.id, t(lang, Key::WhitelistDisabled))
+                    .await?;
+                return Ok(());
+            }
+
+            // 检查发送者是否是管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_admin(db_pool, from.id.0).await {
+                    Ok(true) => {
+                        // 获取白名单用户列表，默认只列出启用的用户
+                        let active = if arg.trim().eq_ignore_ascii_case("all") {
+                            None
+                        } else {
+                            Some(true)
+                        };
+                        match models::WhitelistUser::get_all_users(db_pool, active).await {
+                            Ok(users) => {
+                                let user_list = users
+                                    .iter()
+                                    .map(|user| {
+                                        let name = user
+                                            .username
+                                            .as_ref()
+                                            .map(|username| format!(" (@{})", username))
+                                            .unwrap_or_default();
+                                        let status = if user.is_active {
+                                            ""
+                                        } else {
+                                            t(lang, Key::UserListDeactivated)
+                                        };
+                                        format!(
+                                            "ID: {}{}{}, {}: {:?}",
+                                            user.user_id,
+                                            name,
+                                            status,
+                                            t(lang, Key::UserListNotes),
+                                            user.notes
+                                        )
+                                    })
+                                    .collect::<Vec<String>>()
+                                    .join("\n");
+
+                                bot.send_message(
+                                    msg.chat.id,
+                                    format!("{}\n{}", t(lang, Key::UserListHeader), user_list),
+                                )
+                                .await?;
+                            }
+                            Err(e) => {
+                                log::error!("获取白名单用户列表错误: {:?}", e);
+                                bot.send_message(msg.chat.id, t(lang, Key::UserListFailed))
+                                    .await?;
+                            }
+                        }
+                    }
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::UserListNotAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::AdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::AddAdmin(arg) => {
+            // 检查发送者是否是超级管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_super_admin(db_pool, from.id.0).await {
+                    Ok(true) => {
+                        // 解析用户ID
+                        match arg.trim().parse::<u64>() {
+                            Ok(user_id) => {
+                                // 添加管理员
+                                match models::Admin::add_admin(db_pool, user_id, None, false).await
+                                {
+                                    Ok(_) => {
+                                        access_cache.invalidate(user_id);
+                                        let text = t(lang, Key::AdminAdded)
+                                            .replace("{user}", &user_id.to_string());
+                                        bot.send_message(msg.chat.id, text).await?;
+                                    }
+                                    Err(e) => {
+                                        log::error!("添加管理员错误: {:?}", e);
+                                        bot.send_message(msg.chat.id, t(lang, Key::AddAdminFailed))
+                                            .await?;
+                                    }
+                                }
+                            }
+                            Err(_) => {
+                                bot.send_message(msg.chat.id, t(lang, Key::AddAdminUsage))
+                                    .await?;
+                            }
+                        }
+                    }
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::AddAdminNotSuperAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查超级管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::SuperAdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::RemoveAdmin(arg) => {
+            // 检查发送者是否是超级管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_super_admin(db_pool, from.id.0).await {
+                    Ok(true) => match remove_admin(db_pool, lang, from.id.0, arg.trim()).await {
+                        Ok(text) => {
+                            access_cache.clear();
+                            bot.send_message(msg.chat.id, text).await?;
+                        }
+                        Err(e) => {
+                            log::error!("移除管理员错误: {:?}", e);
+                            bot.send_message(msg.chat.id, t(lang, Key::RemoveAdminFailed))
+                                .await?;
+                        }
+                    },
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::RemoveAdminNotSuperAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查超级管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::SuperAdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::ListAdmins => {
+            // 检查发送者是否是管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_admin(db_pool, from.id.0).await {
+                    Ok(true) => {
+                        // 获取管理员列表
+                        match models::Admin::get_all_admins(db_pool).await {
+                            Ok(admins) => {
+                                let admin_list = admins
+                                    .iter()
+                                    .map(|admin| format!("ID: {}", admin.user_id))
+                                    .collect::<Vec<String>>()
+                                    .join("\n");
+
+                                bot.send_message(
+                                    msg.chat.id,
+                                    format!("{}\n{}", t(lang, Key::AdminListHeader), admin_list),
+                                )
+                                .await?;
+                            }
+                            Err(e) => {
+                                log::error!("获取管理员列表错误: {:?}", e);
+                                bot.send_message(msg.chat.id, t(lang, Key::AdminListFailed))
+                                    .await?;
+                            }
+                        }
+                    }
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::AdminListNotAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::AdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::CheckAccess(arg) => {
+            // 检查发送者是否是超级管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_super_admin(db_pool, from.id.0).await {
+                    Ok(true) => match arg.trim().parse::<u64>() {
+                        Ok(user_id) => match access::evaluate_access(db_pool, user_id).await {
+                            Ok(report) => {
+                                bot.send_message(msg.chat.id, report.to_text()).await?;
+                            }
+                            Err(e) => {
+                                log::error!("检查用户访问权限错误: {:?}", e);
+                                bot.send_message(msg.chat.id, t(lang, Key::AccessCheckFailed))
+                                    .await?;
+                            }
+                        },
+                        Err(_) => {
+                            bot.send_message(msg.chat.id, t(lang, Key::CheckAccessUsage))
+                                .await?;
+                        }
+                    },
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::CheckAccessNotSuperAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查超级管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::SuperAdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::Transfer(arg) => {
+            // 检查发送者是否是超级管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_super_admin(db_pool, from.id.0).await {
+                    Ok(true) => {
+                        let text =
+                            match transfer_ownership(db_pool, lang, from.id.0, arg.trim()).await {
+                                Ok(text) => {
+                                    access_cache.clear();
+                                    text
+                                }
+                                Err(e) => {
+                                    log::error!("转让超级管理员错误: {:?}", e);
+                                    t(lang, Key::TransferFailed).to_string()
+                                }
+                            };
+                        bot.send_message(msg.chat.id, text).await?;
+                    }
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::TransferNotSuperAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查超级管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::SuperAdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::Refusals => {
+            // 检查发送者是否是管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_admin(db_pool, from.id.0).await {
+                    Ok(true) => match models::Refusal::get_recent(db_pool, 10).await {
+                        Ok(refusals) => {
+                            bot.send_message(msg.chat.id, format_refusals(lang, &refusals))
+                                .await?;
+                        }
+                        Err(e) => {
+                            log::error!("获取拒绝回答记录错误: {:?}", e);
+                            bot.send_message(msg.chat.id, t(lang, Key::RefusalsFailed))
+                                .await?;
+                        }
+                    },
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::RefusalsNotAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::AdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::Usage => {
+            // 检查发送者是否是管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_admin(db_pool, from.id.0).await {
+                    Ok(true) => match models::ChatTokenUsage::per_chat(db_pool, 30, 20).await {
+                        Ok(usage) => {
+                            bot.send_message(msg.chat.id, format_token_usage(lang, &usage, 30))
+                                .await?;
+                        }
+                        Err(e) => {
+                            log::error!("获取 token 用量错误: {:?}", e);
+                            bot.send_message(msg.chat.id, t(lang, Key::UsageFailed))
+                                .await?;
+                        }
+                    },
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::UsageNotAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::AdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::FinishReasons => {
+            // 检查发送者是否是管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_admin(db_pool, from.id.0).await {
+                    Ok(true) => match models::FinishReasonCount::get_all(db_pool).await {
+                        Ok(counts) => {
+                            bot.send_message(msg.chat.id, format_finish_reasons(lang, &counts))
+                                .await?;
+                        }
+                        Err(e) => {
+                            log::error!("获取 finish_reason 统计错误: {:?}", e);
+                            bot.send_message(msg.chat.id, t(lang, Key::StatsFailed))
+                                .await?;
+                        }
+                    },
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::StatsNotAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::AdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::Config => {
+            // 检查发送者是否是超级管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_super_admin(db_pool, from.id.0).await {
+                    Ok(true) => {
+                        bot.send_message(msg.chat.id, config::Config::from_env().to_text())
+                            .await?;
+                    }
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::ConfigNotSuperAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查超级管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::SuperAdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::DbReconnect => {
+            // 检查发送者是否是超级管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_super_admin(db_pool, from.id.0).await {
+                    Ok(true) => match db::reconnect(shared_db).await {
+                        Ok(_) => {
+                            access_cache.clear();
+                            bot.send_message(msg.chat.id, t(lang, Key::DbReconnected))
+                                .await?;
+                        }
+                        Err(e) => {
+                            log::error!("重新连接数据库错误: {:?}", e);
+                            bot.send_message(msg.chat.id, t(lang, Key::DbReconnectFailed))
+                                .await?;
+                        }
+                    },
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::DbReconnectNotSuperAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查超级管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::SuperAdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::MigrateDb(target_url) => {
+            // 检查发送者是否是超级管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_super_admin(db_pool, from.id.0).await {
+                    Ok(true) => {
+                        // 连接地址中包含密码，尽量删除这条命令消息
+                        if let Err(e) = bot.delete_message(msg.chat.id, msg.id).await {
+                            log::warn!("删除包含数据库地址的消息失败: {:?}", e);
+                        }
+
+                        let target_url = target_url.trim();
+                        if target_url.is_empty() {
+                            bot.send_message(msg.chat.id, t(lang, Key::MigrateUsage))
+                                .await?;
+                            return Ok(());
+                        }
+
+                        let status = bot
+                            .send_message(msg.chat.id, t(lang, Key::Migrating))
+                            .await?;
+                        let result = migrate::sqlite_to_postgres(db_pool, target_url, |progress| {
+                            let bot = bot.clone();
+                            let status_id = status.id;
+                            let chat_id = msg.chat.id;
+                            async move {
+                                let _ = bot.edit_message_text(chat_id, status_id, progress).await;
+                            }
+                        })
+                        .await;
+
+                        match result {
+                            Ok(report) => {
+                                bot.edit_message_text(msg.chat.id, status.id, report.to_text())
+                                    .await?;
+                            }
+                            Err(e) => {
+                                log::error!("迁移数据库错误: {:?}", e);
+                                bot.edit_message_text(
+                                    msg.chat.id,
+                                    status.id,
+                                    t(lang, Key::MigrateFailed).replace("{error}", &e.to_string()),
+                                )
+                                .await?;
+                            }
+                        }
+                    }
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::MigrateNotSuperAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查超级管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::SuperAdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::Broadcast(text) => {
+            // 检查发送者是否是超级管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_super_admin(db_pool, from.id.0).await {
+                    Ok(true) => {
+                        let text = text.trim();
+                        if text.is_empty() {
+                            bot.send_message(msg.chat.id, t(lang, Key::BroadcastUsage))
+                                .await?;
+                            return Ok(());
+                        }
+
+                        let status = bot
+                            .send_message(msg.chat.id, t(lang, Key::Broadcasting))
+                            .await?;
+                        let reply = match broadcast::broadcast(&bot, db_pool, from.id.0, text).await
+                        {
+                            Ok(report) => report.to_text(),
+                            Err(e) => {
+                                log::error!("发送通知错误: {:?}", e);
+                                t(lang, Key::BroadcastFailed).to_string()
+                            }
+                        };
+                        bot.edit_message_text(msg.chat.id, status.id, reply).await?;
+                    }
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::BroadcastNotSuperAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查超级管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::SuperAdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::Sql(sql) => {
+            // 检查发送者是否是超级管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_super_admin(db_pool, from.id.0).await {
+                    Ok(true) => {
+                        let sql = sql.trim();
+                        if sql.is_empty() {
+                            bot.send_message(msg.chat.id, t(lang, Key::SqlUsage))
+                                .await?;
+                            return Ok(());
+                        }
+
+                        // 被拒绝和执行失败的查询也记入审计日志，附带执行结果
+                        let result = db_pool.query_rows(sql, MAX_SQL_ROWS).await;
+                        let outcome = match &result {
+                            Ok(rows) => format!("成功，{} 行", rows.rows.len() + rows.omitted),
+                            Err(e) => format!("失败: {}", e),
+                        };
+                        let details = format!("{} -> {}", sql, outcome);
+                        if let Err(e) = models::AuditLog::record(
+                            db_pool,
+                            from.id.0,
+                            "sql",
+                            None,
+                            Some(&details),
+                        )
+                        .await
+                        {
+                            log::warn!("记录 /sql 审计日志失败: {:?}", e);
+                        }
+
+                        match result {
+                            Ok(result) => send_query_rows(&bot, msg.chat.id, &result, lang).await?,
+                            Err(e) => {
+                                log::warn!("执行 /sql 查询失败: {:?}", e);
+                                let text =
+                                    t(lang, Key::SqlFailed).replace("{error}", &e.to_string());
+                                bot.send_message(msg.chat.id, text).await?;
+                            }
+                        }
+                    }
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::SqlNotSuperAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查超级管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::SuperAdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+        Command::Analytics => {
+            // 检查发送者是否是超级管理员
+            if let Some(from) = &msg.from {
+                match models::Admin::is_super_admin(db_pool, from.id.0).await {
+                    Ok(true) => match analytics::Analytics::report(db_pool, 30).await {
+                        Ok(report) => {
+                            bot.send_message(msg.chat.id, report.to_text()).await?;
+                        }
+                        Err(e) => {
+                            log::error!("生成统计报告错误: {:?}", e);
+                            bot.send_message(msg.chat.id, t(lang, Key::AnalyticsFailed))
+                                .await?;
+                        }
+                    },
+                    Ok(false) => {
+                        bot.send_message(msg.chat.id, t(lang, Key::AnalyticsNotSuperAdmin))
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::error!("检查超级管理员权限错误: {:?}", e);
+                        bot.send_message(msg.chat.id, t(lang, Key::SuperAdminCheckFailed))
+                            .await?;
+                    }
+                }
+            }
+        }
+    };
+
+    Ok(())
+}
+
+// 机器人被加入群组时发送欢迎语，其他成员加入时不处理
+async fn handle_new_chat_members(
+    bot: Bot,
+    msg: Message,
+    bot_id: UserId,
+    db_pool: &db::DatabasePool,
+    configured_greeting: Option<&str>,
+) -> ResponseResult<()> {
+    let bot_added = msg
+        .new_chat_members()
+        .is_some_and(|members| members.iter().any(|member| member.id == bot_id));
+    if !bot_added {
+        return Ok(());
+    }
+
+    log::info!("机器人被加入群组: chat_id={}", msg.chat.id);
+    if let Some(greeting) = i18n::group_greeting(db_pool, msg.chat.id.0, configured_greeting).await
+    {
+        bot.send_message(msg.chat.id, greeting).await?;
+    }
+
+    Ok(())
+}
+
+// 格式化 /whoami 的回复
+fn format_whoami(
+    lang: Lang,
+    user: &teloxide::types::User,
+    chat_id: ChatId,
+    report: &access::AccessReport,
+) -> String {
+    let yes_no = |value: bool| t(lang, if value { Key::Yes } else { Key::No });
+    let username = user
+        .username
+        .as_deref()
+        .map(|name| format!("@{}", name))
+        .unwrap_or_else(|| t(lang, Key::NoUsername).to_string());
+    let status = if report.allowed {
+        Key::WhoamiAllowed
+    } else {
+        Key::WhoamiDenied
+    };
+
+    t(lang, Key::Whoami)
+        .replace("{user_id}", &user.id.to_string())
+        .replace("{username}", &username)
+        .replace("{chat_id}", &chat_id.to_string())
+        .replace("{whitelisted}", yes_no(report.whitelisted))
+        .replace("{admin}", yes_no(report.is_admin))
+        .replace("{super_admin}", yes_no(report.is_super_admin))
+        .replace("{status}", t(lang, status))
+}
+
+// 格式化各聊天的 token 用量
+fn format_token_usage(lang: Lang, usage: &[models::ChatTokenUsage], days: i64) -> String {
+    if usage.is_empty() {
+        return t(lang, Key::UsageEmpty).replace("{days}", &days.to_string());
+    }
+
+    let mut report = t(lang, Key::UsageHeader).replace("{days}", &days.to_string());
+    report.push('\n');
+    for entry in usage {
+        report.push('\n');
+        report.push_str(
+            &t(lang, Key::UsageRow)
+                .replace("{chat}", &entry.chat_id.to_string())
+                .replace("{prompt}", &entry.prompt_tokens.to_string())
+                .replace("{completion}", &entry.completion_tokens.to_string())
+                .replace(
+                    "{total}",
+                    &(entry.prompt_tokens + entry.completion_tokens).to_string(),
+                ),
+        );
+    }
+
+    let prompt_total: i64 = usage.iter().map(|entry| entry.prompt_tokens).sum();
+    let completion_total: i64 = usage.iter().map(|entry| entry.completion_tokens).sum();
+    report.push_str("\n\n");
+    report.push_str(
+        &t(lang, Key::UsageTotal)
+            .replace("{prompt}", &prompt_total.to_string())
+            .replace("{completion}", &completion_total.to_string())
+            .replace("{total}", &(prompt_total + completion_total).to_string()),
+    );
+    report
+}
+
+// 按模型格式化 finish_reason 统计，附带各原因所占比例
+fn format_finish_reasons(lang: Lang, counts: &[models::FinishReasonCount]) -> String {
+    if counts.is_empty() {
+        return t(lang, Key::FinishReasonsEmpty).to_string();
+    }
+
+    let mut report = format!("{}\n", t(lang, Key::FinishReasonsHeader));
+    let mut current_model: Option<&str> = None;
+    let mut total: i64 = 0;
+    for entry in counts {
+        // 结果按模型排序，遇到新模型时输出标题
+        if current_model != Some(entry.model.as_str()) {
+            current_model = Some(entry.model.as_str());
+            total = counts
+                .iter()
+                .filter(|other| other.model == entry.model)
+                .map(|other| other.count)
+                .sum();
+            let heading = t(lang, Key::FinishReasonsModel)
+                .replace("{model}", &entry.model)
+                .replace("{total}", &total.to_string());
+            report.push_str(&format!("\n{}\n", heading));
+        }
+
+        report.push_str(&format!(
+            "  {}: {} ({:.1}%)\n",
+            entry.finish_reason,
+            entry.count,
+            entry.count as f64 * 100.0 / total.max(1) as f64
+        ));
+    }
+
+    report
+}
+
+// 格式化拒绝回答记录，提问和回复只显示开头部分
+fn format_refusals(lang: Lang, refusals: &[models::Refusal]) -> String {
+    if refusals.is_empty() {
+        return t(lang, Key::RefusalsEmpty).to_string();
+    }
+
+    let preview = |text: &str| -> String {
+        let preview: String = text.chars().take(80).collect();
+        if preview.len() < text.len() {
+            format!("{}...", preview)
+        } else {
+            preview
+        }
+    };
+
+    let mut report = format!("{}\n", t(lang, Key::RefusalsHeader));
+    for refusal in refusals {
+        report.push_str(&format!(
+            "\n[{}] {} {} · {}\n{}: {}\n{}: {}\n",
+            refusal.created_at.format("%Y-%m-%d %H:%M"),
+            t(lang, Key::RefusalChat),
+            refusal.chat_id,
+            refusal.model,
+            t(lang, Key::RefusalPrompt),
+            preview(&refusal.prompt),
+            t(lang, Key::RefusalReply),
+            preview(&refusal.reply)
+        ));
+    }
+
+    report
+}
+
+// 处理被编辑的命令消息：不重新执行，只记录日志（可选提示用户）
+async fn handle_edited_command(
+    bot: Bot,
+    msg: Message,
+    db_pool: &db::DatabasePool,
+    notify: bool,
+) -> ResponseResult<()> {
+    log::info!(
+        "忽略被编辑的命令消息: chat_id={}, text={:?}",
+        msg.chat.id,
+        msg.text()
+    );
+
+    if notify {
+        let lang = i18n::chat_lang(db_pool, msg.chat.id.0).await;
+        bot.send_message(msg.chat.id, t(lang, Key::EditedCommandIgnored))
+            .await?;
+    }
+
+    Ok(())
+}
+
+// 处理被编辑的普通文本：被编辑的是会话中最新一轮的提问时更新保存的内容
+// 开启 REGENERATE_ON_EDIT 时删除原来的回复，按编辑后的内容重新回答
+async fn handle_edited_message(
+    bot: Bot,
+    msg: Message,
+    db_pool: &db::DatabasePool,
+    client: &OpenAiClient,
+    last_errors: &LastErrorStore,
+    in_flight: &InFlightChats,
+    bot_username: &str,
+) -> ResponseResult<()> {
+    let Some(text) = msg.text() else {
+        return Ok(());
+    };
+    let text = strip_mention(text, bot_username);
+    if text.is_empty() {
+        return Ok(());
+    }
+
+    // 与保存提问时的处理一致：去掉模型前缀，加上引用的内容
+    let text = match parse_model_override(&text) {
+        Some((_, text)) => text,
+        None => text.as_str(),
+    };
+    let quoted = quote::reply_quoted_context(db_pool, &msg).await;
+    let content = quote::with_quoted_context(quoted.as_deref(), text);
+
+    let chat_id = msg.chat.id;
+    let updated = match models::Session::find_or_create_by_chat_id(db_pool, chat_id.0).await {
+        Ok(session_id) => {
+            models::Message::update_content(db_pool, session_id, msg.id.0 as i64, &content).await
+        }
+        Err(e) => Err(e),
+    };
+    match updated {
+        Ok(true) => {
+            log::info!(
+                "已更新被编辑的消息: chat_id={}, message_id={}",
+                chat_id,
+                msg.id
+            );
+        }
+        // 不是最新一轮的提问，或者消息没有被保存（例如未同意保存消息）
+        Ok(false) => return Ok(()),
+        Err(e) => {
+            log::error!("更新被编辑的消息错误: {:?}", e);
+            return Ok(());
+        }
+    }
+
+    if config::regenerate_on_edit() {
+        let user_id = msg.from.as_ref().map(|user| user.id.0);
+        regenerate_last_reply(
+            &bot,
+            chat_id,
+            user_id,
+            msg.reply_to_message().map(|reply| reply.id),
+            db_pool,
+            client,
+            last_errors,
+            in_flight,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_text_message(
+    bot: Bot,
+    msg: Message,
+    db_pool: &db::DatabasePool,
+    client: &OpenAiClient,
+    last_errors: &LastErrorStore,
+    focus: &FocusStore,
+    in_flight: &InFlightChats,
+    bot_username: &str,
+) -> ResponseResult<()> {
+    // 处理普通文本消息
+    if let Some(text) = msg.text() {
+        if !text.starts_with('/') {
+            // 不是命令的普通文本，去掉对机器人的 @提及
+            let text = strip_mention(text, bot_username);
+            let text = text.as_str();
+            if text.is_empty() {
+                return Ok(());
+            }
+            let chat_id = msg.chat.id;
+            let user_id = msg.from.as_ref().map(|user| user.id.0);
+
+            // 新聊天第一次发消息时询问是否同意保存消息
+            ask_privacy_consent_once(&bot, chat_id, db_pool).await?;
+
+            // 专注模式下先缓冲，合并窗口内没有新消息时再一起发送
+            // 合并后的提问不带被回复的对话，回复某条消息时总是把引用的内容作为上下文
+            if focus.is_enabled(chat_id.0) {
+                let quoted = quote::quoted_context(&msg);
+                let text = quote::with_quoted_context(quoted.as_deref(), text);
+                let generation = focus.push(chat_id.0, &text);
+                let bot = bot.clone();
+                let db_pool = db_pool.clone();
+                let client = client.clone();
+                let last_errors = last_errors.clone();
+                let focus = focus.clone();
+                let in_flight = in_flight.clone();
+
+                tokio::spawn(async move {
+                    tokio::time::sleep(focus::debounce_window()).await;
+                    if let Some(combined) = focus.take_if_current(chat_id.0, generation) {
+                        // 合并后的提问对应多条 Telegram 消息，不记录消息ID
+                        if let Err(e) = reply_to_text(
+                            &bot,
+                            chat_id,
+                            user_id,
+                            &combined,
+                            None,
+                            None,
+                            None,
+                            &db_pool,
+                            &client,
+                            &last_errors,
+                            &in_flight,
+                        )
+                        .await
+                        {
+                            log::error!("专注模式发送回复错误: {:?}", e);
+                        }
+                    }
+                });
+
+                return Ok(());
+            }
+
+            // 回复某条消息时，把引用的片段（或整条被回复的消息）作为提问的上下文
+            let quoted = quote::reply_quoted_context(db_pool, &msg).await;
+
+            reply_to_text(
+                &bot,
+                chat_id,
+                user_id,
+                text,
+                Some(msg.id),
+                msg.reply_to_message().map(|reply| reply.id),
+                quoted.as_deref(),
+                db_pool,
+                client,
+                last_errors,
+                in_flight,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+// 私聊中所有消息都会处理；群组中只处理 @机器人 或回复机器人消息的文本
+fn addressed_to_bot(msg: &Message, bot_id: UserId, bot_username: &str) -> bool {
+    if !msg.chat.is_group() && !msg.chat.is_supergroup() {
+        return true;
+    }
+
+    let mentioned = msg.text().or(msg.caption()).is_some_and(|text| {
+        text.to_ascii_lowercase()
+            .contains(&format!("@{}", bot_username.to_ascii_lowercase()))
+    });
+    let replied_to_bot = msg
+        .reply_to_message()
+        .and_then(|reply| reply.from.as_ref())
+        .is_some_and(|user| user.id == bot_id);
+
+    mentioned || replied_to_bot
+}
+
+// 去掉文本中的 @机器人用户名（不区分大小写）
+fn strip_mention(text: &str, bot_username: &str) -> String {
+    let mention = format!("@{}", bot_username.to_ascii_lowercase());
+    let lower = text.to_ascii_lowercase();
+
+    // ASCII 小写转换不改变字节位置，可以直接用 lower 中的位置切分原文
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for (index, _) in lower.match_indices(&mention) {
+        result.push_str(&text[last..index]);
+        last = index + mention.len();
+    }
+    result.push_str(&text[last..]);
+
+    result.trim().to_string()
+}
+
+// 查询用户的 Telegram 用户名，只有和机器人对话过的用户才能查到
+async fn lookup_username(bot: &Bot, user_id: u64) -> Option<String> {
+    match bot.get_chat(ChatId(user_id as i64)).await {
+        Ok(chat) => chat.username().map(str::to_string),
+        Err(e) => {
+            log::debug!("无法查询用户 {} 的用户名: {:?}", user_id, e);
+            None
+        }
+    }
+}
+
+// 处理 /transfer：
+// "/transfer <用户ID>" 将目标用户设为超级管理员；
+// "/transfer confirm" 确认将自己降级为普通管理员（至少保留一位其他超级管理员）
+async fn transfer_ownership(
+    db_pool: &db::DatabasePool,
+    lang: Lang,
+    caller_id: u64,
+    arg: &str,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    if arg.eq_ignore_ascii_case("confirm") {
+        if models::Admin::count_super_admins(db_pool).await? <= 1 {
+            return Ok(t(lang, Key::TransferOnlySuperAdmin).to_string());
+        }
+
+        models::Admin::set_super_admin(db_pool, caller_id, false).await?;
+        models::AuditLog::record(db_pool, caller_id, "demote_self", Some(caller_id), None).await?;
+        log::info!("超级管理员 {} 已将自己降级为普通管理员", caller_id);
+        return Ok(t(lang, Key::TransferDemoted).to_string());
+    }
+
+    let target_id = match arg.parse::<u64>() {
+        Ok(id) => id,
+        Err(_) => return Ok(t(lang, Key::TransferUsage).to_string()),
+    };
+
+    if target_id == caller_id {
+        return Ok(t(lang, Key::TransferAlreadySuperAdmin).to_string());
+    }
+
+    models::Admin::set_super_admin(db_pool, target_id, true).await?;
+    models::AuditLog::record(
+        db_pool,
+        caller_id,
+        "transfer_super_admin",
+        Some(target_id),
+        None,
+    )
+    .await?;
+    log::info!(
+        "超级管理员 {} 已将超级管理员身份授予 {}",
+        caller_id,
+        target_id
+    );
+
+    Ok(t(lang, Key::TransferDone).replace("{user}", &target_id.to_string()))
+}
+
+// 处理 /removeadmin：
+// "/removeadmin <用户ID>" 移除管理员；移除自己需要 "/removeadmin <用户ID> confirm"
+// 不允许移除最后一位超级管理员
+async fn remove_admin(
+    db_pool: &db::DatabasePool,
+    lang: Lang,
+    caller_id: u64,
+    arg: &str,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let mut parts = arg.split_whitespace();
+    let target_id = match parts.next().map(str::parse::<u64>) {
+        Some(Ok(id)) => id,
+        _ => return Ok(t(lang, Key::RemoveAdminUsage).to_string()),
+    };
+    let confirmed = parts
+        .next()
+        .is_some_and(|flag| flag.eq_ignore_ascii_case("confirm"));
+
+    if target_id == caller_id && !confirmed {
+        return Ok(t(lang, Key::RemoveAdminConfirmSelf).replace("{user}", &caller_id.to_string()));
+    }
+
+    if models::Admin::is_super_admin(db_pool, target_id).await?
+        && models::Admin::count_super_admins(db_pool).await? <= 1
+    {
+        return Ok(t(lang, Key::RemoveAdminLastSuperAdmin).to_string());
+    }
+
+    if !models::Admin::remove_admin(db_pool, target_id).await? {
+        return Ok(t(lang, Key::NotAnAdmin).replace("{user}", &target_id.to_string()));
+    }
+
+    models::AuditLog::record(db_pool, caller_id, "remove_admin", Some(target_id), None).await?;
+    log::info!("超级管理员 {} 已移除管理员 {}", caller_id, target_id);
+
+    Ok(t(lang, Key::AdminRemoved).replace("{user}", &target_id.to_string()))
+}
+
+// 将 /sql 的结果格式化为按列对齐的表格，放在代码块中发送
+// 表格过长时继续减少显示的行数；Telegram 拒绝格式时改为纯文本发送
+async fn send_query_rows(
+    bot: &Bot,
+    chat_id: ChatId,
+    result: &db::QueryRows,
+    lang: i18n::Lang,
+) -> ResponseResult<()> {
+    if result.rows.is_empty() {
+        bot.send_message(chat_id, t(lang, Key::SqlNoRows)).await?;
+        return Ok(());
+    }
+
+    let table = result.to_table();
+    let note = table.note(lang);
+    let text = format!("```\n{}\n```\n{}", table.text, note);
+    match bot
+        .send_message(chat_id, markdown::to_markdown_v2(&text))
+        .parse_mode(ParseMode::MarkdownV2)
+        .await
+    {
+        Ok(_) => {}
+        Err(RequestError::Api(e)) => {
+            log::warn!("MarkdownV2 格式的查询结果发送失败，改为纯文本发送: {}", e);
+            bot.send_message(chat_id, format!("{}\n\n{}", table.text, note))
+                .await?;
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+// 检查用户是否超出 USER_HOURLY_LIMIT，超出时提示还需等待的时间并返回 false
+// 管理员不受限制；数据库出错时记录日志并放行
+async fn check_rate_limit(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: Option<u64>,
+    db_pool: &db::DatabasePool,
+) -> bool {
+    let (Some(limit), Some(user_id)) = (config::user_hourly_limit(), user_id) else {
+        return true;
+    };
+
+    let result = async {
+        if models::Admin::is_admin(db_pool, user_id).await? {
+            return Ok(rate_limit::RateLimitDecision::Allowed);
+        }
+        rate_limit::RateLimiter::check_and_increment(
+            db_pool,
+            user_id,
+            limit,
+            std::time::Duration::from_secs(3600),
+        )
+        .await
+    }
+    .await;
+
+    match result {
+        Ok(rate_limit::RateLimitDecision::Allowed) => true,
+        Ok(rate_limit::RateLimitDecision::Limited { reset_in }) => {
+            let minutes = reset_in.as_secs().div_ceil(60).max(1);
+            let lang = i18n::chat_lang(db_pool, chat_id.0).await;
+            let text = t(lang, Key::RateLimited)
+                .replace("{limit}", &limit.to_string())
+                .replace("{minutes}", &minutes.to_string());
+            let _ = bot.send_message(chat_id, text).await;
+            false
+        }
+        Err(e) => {
+            log::error!("检查请求频率限制错误: {:?}", e);
+            true
+        }
+    }
+}
+
+// 开启 PRIVACY_CONSENT 时，对还没有询问过的聊天发送一次同意请求
+async fn ask_privacy_consent_once(
+    bot: &Bot,
+    chat_id: ChatId,
+    db_pool: &db::DatabasePool,
+) -> ResponseResult<()> {
+    if !config::privacy_consent_required() {
+        return Ok(());
+    }
+
+    match models::Session::get_privacy_consent(db_pool, chat_id.0).await {
+        Ok(None) => {
+            if let Err(e) =
+                models::Session::set_privacy_consent(db_pool, chat_id.0, privacy::PENDING).await
+            {
+                log::error!("保存隐私同意状态错误: {:?}", e);
+                return Ok(());
+            }
+            let lang = i18n::chat_lang(db_pool, chat_id.0).await;
+            let prompt = config::privacy_consent_prompt()
+                .unwrap_or_else(|| t(lang, Key::PrivacyPrompt).to_string());
+            bot.send_message(chat_id, prompt)
+                .reply_markup(privacy::consent_keyboard(lang))
+                .await?;
+        }
+        Ok(Some(_)) => {}
+        Err(e) => log::error!("读取隐私同意状态错误: {:?}", e),
+    }
+    Ok(())
+}
+
+// 处理隐私同意按钮：同意状态作用于整个聊天，点击者必须有权使用机器人，在群组中还必须是群组管理员
+async fn handle_privacy_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    db_pool: &db::DatabasePool,
+    access_cache: &AccessCache,
+) -> ResponseResult<()> {
+    let (Some(data), Some(message)) = (q.data.as_deref(), q.message.as_ref()) else {
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+    let lang = i18n::chat_lang(db_pool, chat_id.0).await;
+
+    let denied = match access_cache.evaluate(db_pool, q.from.id.0).await {
+        Ok(report) if !report.allowed => Some(t(lang, Key::NotWhitelisted)),
+        Ok(_) if message.chat().is_group() || message.chat().is_supergroup() => {
+            match bot.get_chat_member(chat_id, q.from.id).await {
+                Ok(member) if member.is_privileged() => None,
+                Ok(_) => Some(t(lang, Key::PrivacyGroupAdminOnly)),
+                Err(e) => {
+                    log::error!("查询群组成员权限错误: {:?}", e);
+                    Some(t(lang, Key::AdminCheckFailed))
+                }
+            }
+        }
+        Ok(_) => None,
+        Err(e) => {
+            log::error!("检查白名单错误: {:?}", e);
+            Some(t(lang, Key::WhitelistCheckFailed))
+        }
+    };
+    if let Some(text) = denied {
+        bot.answer_callback_query(q.id.clone())
+            .text(text)
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
+
+    let result = match data {
+        privacy::CALLBACK_GRANT => {
+            models::Session::set_privacy_consent(db_pool, chat_id.0, privacy::GRANTED)
+                .await
+                .map(|_| t(lang, Key::PrivacyGranted))
+        }
+        privacy::CALLBACK_DECLINE => {
+            models::Session::set_privacy_consent(db_pool, chat_id.0, privacy::DECLINED)
+                .await
+                .map(|_| t(lang, Key::PrivacyDeclined))
+        }
+        // 撤回同意时只删除已保存的消息，聊天的其他设置保留
+        privacy::CALLBACK_REVOKE => privacy::revoke(db_pool, chat_id.0)
+            .await
+            .map(|_| t(lang, Key::PrivacyRevoked)),
+        _ => return Ok(()),
+    };
+
+    bot.answer_callback_query(q.id.clone()).await?;
+    match result {
+        Ok(text) => {
+            bot.edit_message_text(chat_id, message.id(), text).await?;
+        }
+        Err(e) => {
+            log::error!("更新隐私同意状态错误: {:?}", e);
+            bot.send_message(chat_id, t(lang, Key::SaveFailed)).await?;
+        }
+    }
+    Ok(())
+}
+
+// 处理语音回复下方的按钮：重新转录并回答、仅转录、朗读回复
+async fn handle_voice_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    db_pool: &db::DatabasePool,
+    client: &OpenAiClient,
+    last_errors: &LastErrorStore,
+    in_flight: &InFlightChats,
+    access_cache: &AccessCache,
+) -> ResponseResult<()> {
+    let (Some(data), Some(message)) = (q.data.as_deref(), q.message.as_ref()) else {
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+    bot.answer_callback_query(q.id.clone()).await?;
+    let lang = i18n::chat_lang(db_pool, chat_id.0).await;
+
+    // 按钮会调用 OpenAI，同样需要检查点击者是否在白名单中
+    if config::whitelist_enabled() {
+        match access_cache.evaluate(db_pool, q.from.id.0).await {
+            Ok(report) if report.allowed => {}
+            Ok(_) => {
+                bot.send_message(chat_id, t(lang, Key::NotWhitelisted))
+                    .await?;
+                return Ok(());
+            }
+            Err(e) => {
+                log::error!("检查白名单错误: {:?}", e);
+                return Ok(());
+            }
+        }
+    }
+
+    // 超过 48 小时的消息无法再读取内容
+    let Some(reply) = message.regular_message() else {
+        bot.send_message(chat_id, t(lang, Key::ButtonExpired))
+            .await?;
+        return Ok(());
+    };
+
+    match data {
+        voice_actions::CALLBACK_SPEAK => {
+            // 只朗读按钮所在的这条消息，去掉附加的页脚
+            let text = reply.text().unwrap_or_default();
+            let text = match config::reply_footer() {
+                Some(footer) => text.strip_suffix(footer.as_str()).unwrap_or(text),
+                None => text,
+            };
+
+            // 朗读同样调用 OpenAI：上一条消息还在处理或超出每小时请求上限时不再生成
+            let Some(_in_flight) = acquire_in_flight(&bot, chat_id, db_pool, in_flight).await?
+            else {
+                return Ok(());
+            };
+            if !check_rate_limit(&bot, chat_id, Some(q.from.id.0), db_pool).await {
+                return Ok(());
+            }
+            send_speech(&bot, chat_id, text.trim_end(), client, last_errors, lang).await?;
+        }
+        voice_actions::CALLBACK_RETRANSCRIBE | voice_actions::CALLBACK_TRANSCRIBE_ONLY => {
+            let Some(voice_msg) = reply
+                .reply_to_message()
+                .filter(|m| audio::AudioFile::from_message(m).is_some())
+            else {
+                bot.send_message(chat_id, t(lang, Key::VoiceSourceMissing))
+                    .await?;
+                return Ok(());
+            };
+
+            let transcribe_only = data == voice_actions::CALLBACK_TRANSCRIBE_ONLY;
+            if let Err(err) = handle_voice_message(
+                bot.clone(),
+                voice_msg.clone(),
+                client,
+                db_pool,
+                last_errors,
+                in_flight,
+                transcribe_only,
+            )
+            .await
+            {
+                let trace_id = last_errors.record(chat_id.0, &err.to_string());
+                log::error!("[{}] 语音处理错误: {:?}", trace_id, err);
+                bot.send_message(chat_id, t(lang, Key::VoiceFailed)).await?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// 将文本发送给 GPT 并回复到聊天
+#[allow(clippy::too_many_arguments)]
+async fn reply_to_text(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: Option<u64>,
+    text: &str,
+    tg_message_id: Option<MessageId>,
+    reply_to: Option<MessageId>,
+    quoted: Option<&str>,
+    db_pool: &db::DatabasePool,
+    client: &OpenAiClient,
+    last_errors: &LastErrorStore,
+    in_flight: &InFlightChats,
+) -> ResponseResult<()> {
+    // 同一聊天的上一条消息还在处理时不再发送
+    let Some(_in_flight) = acquire_in_flight(bot, chat_id, db_pool, in_flight).await? else {
+        return Ok(());
+    };
+
+    // 超出每小时请求上限时不再调用 GPT
+    if !check_rate_limit(bot, chat_id, user_id, db_pool).await {
+        return Ok(());
+    }
+
+    // 解析单条消息的模型覆盖前缀，例如 "@gpt-4o: 解释一下"
+    let (model, text) = match parse_model_override(text) {
+        Some((model, text)) => (Some(model), text),
+        None => (None, text),
+    };
+    let text = quote::with_quoted_context(quoted, text);
+
+    send_chat_reply(
+        bot,
+        chat_id,
+        user_id,
+        &text,
+        tg_message_id,
+        reply_to,
+        None,
+        model,
+        db_pool,
+        client,
+        last_errors,
+    )
+    .await
+}
+
+// 出错时给用户的提示：开启 VERBOSE_ERRORS 时管理员还能看到具体的错误信息（如 OpenAI 状态码和错误消息）
+// 普通用户始终只看到通用提示
+async fn error_reply(
+    db_pool: &db::DatabasePool,
+    lang: i18n::Lang,
+    user_id: Option<u64>,
+    e: &(dyn Error + Send + Sync + 'static),
+) -> String {
+    let message = error::user_message(lang, e);
+    let Some(user_id) = user_id.filter(|_| config::verbose_errors()) else {
+        return message.to_string();
+    };
+
+    match models::Admin::is_admin(db_pool, user_id).await {
+        Ok(true) => t(lang, Key::ErrorDetail)
+            .replace("{message}", message)
+            .replace("{detail}", &e.to_string()),
+        Ok(false) => message.to_string(),
+        Err(admin_error) => {
+            log::warn!("检查管理员权限错误: {:?}", admin_error);
+            message.to_string()
+        }
+    }
+}
+
+// 标记聊天为处理中；上一条消息还在处理时提示用户并返回 None
+async fn acquire_in_flight(
+    bot: &Bot,
+    chat_id: ChatId,
+    db_pool: &db::DatabasePool,
+    in_flight: &InFlightChats,
+) -> ResponseResult<Option<in_flight::InFlightGuard>> {
+    let guard = in_flight.try_acquire(chat_id.0);
+    if guard.is_none() {
+        let lang = i18n::chat_lang(db_pool, chat_id.0).await;
+        bot.send_message(chat_id, t(lang, Key::Busy)).await?;
+    }
+    Ok(guard)
+}
+
+// 发送给 GPT 并回复：显示"思考中"，成功时发送并保存回复，失败时提示错误
+#[allow(clippy::too_many_arguments)]
+async fn send_chat_reply(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: Option<u64>,
+    text: &str,
+    tg_message_id: Option<MessageId>,
+    reply_to: Option<MessageId>,
+    image: Option<&str>,
+    model: Option<&str>,
+    db_pool: &db::DatabasePool,
+    client: &OpenAiClient,
+    last_errors: &LastErrorStore,
+) -> ResponseResult<()> {
+    // 显示"正在思考"的提示，等待回复期间同时显示"正在输入"
+    let lang = i18n::chat_lang(db_pool, chat_id.0).await;
+    let thinking_message = thinking::send_thinking(bot, chat_id, lang).await?;
+    let typing = typing::TypingIndicator::start(bot.clone(), chat_id);
+
+    // 处理消息并获取回复
+    let source = if image.is_some() { "photo" } else { "text" };
+    let result = process_chat_message(
+        db_pool,
+        chat_id.0,
+        user_id,
+        text,
+        image,
+        client,
+        model,
+        source,
+        tg_message_id,
+        reply_to,
+    )
+    .await;
+    drop(typing);
+
+    match result {
+        Ok(reply) => {
+            last_errors.clear(chat_id.0);
+
+            // 删除"思考中"的消息
+            thinking::delete_thinking(bot, chat_id, thinking_message.as_ref()).await;
+
+            // 先发送AI回复，再保存到数据库
+            let sent = send_reply(bot, chat_id, &reply.text(lang), None, None).await?;
+            reply.persist(db_pool, Some(sent.id)).await;
+        }
+        Err(e) => {
+            let trace_id = last_errors.record(chat_id.0, &e.to_string());
+            log::error!("[{}] GPT处理错误: {:?}", trace_id, e);
+            let text = error_reply(db_pool, lang, user_id, e.as_ref()).await;
+            thinking::show_in_thinking(bot, chat_id, thinking_message.as_ref(), text).await?;
+        }
+    }
+    Ok(())
+}
+
+// 解析 /setname 的参数：default 表示清除；名字不能为空、不能换行，最多 MAX_BOT_NAME_CHARS 个字符
+// 名字无效时返回说明原因的文字键
+fn parse_bot_name(arg: &str) -> Result<Option<String>, Key> {
+    let name = arg.trim();
+    if name.is_empty() {
+        return Err(Key::BotNameEmpty);
+    }
+    if name.eq_ignore_ascii_case("default") {
+        return Ok(None);
+    }
+    if name.chars().count() > MAX_BOT_NAME_CHARS {
+        return Err(Key::BotNameTooLong);
+    }
+    if name.chars().any(char::is_control) {
+        return Err(Key::BotNameInvalid);
+    }
+    Ok(Some(name.to_string()))
+}
+
+// 解析 temperature，超出 [0.0, 2.0] 或无法解析时返回 None（不做截断）
+fn parse_temperature(value: &str) -> Option<f64> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|temperature| (0.0..=2.0).contains(temperature))
+}
+
+// 依次使用聊天选择的模型、用户默认模型，都未设置（或不在允许列表中）时使用默认模型
+fn chat_model(chat: Option<String>, user: Option<String>) -> String {
+    chat.into_iter()
+        .chain(user)
+        .find(|model| ALLOWED_MODELS.contains(&model.as_str()))
+        .unwrap_or_else(|| DEFAULT_MODEL.to_string())
+}
+
+// 读取聊天和用户的模型设置，得到本次实际使用的模型
+async fn resolve_model(
+    db_pool: &db::DatabasePool,
+    chat_id: i64,
+    user_id: Option<u64>,
+) -> Result<String, AppError> {
+    let chat = models::Session::get_model(db_pool, chat_id).await?;
+    let user = match user_id {
+        Some(user_id) => models::UserPreference::get(db_pool, user_id).await?.model,
+        None => None,
+    };
+    Ok(chat_model(chat, user))
+}
+
+// 解析消息开头的 "@模型名:" 前缀
+// 仅当模型在允许列表中且前缀后仍有内容时返回 (模型, 去掉前缀后的文本)
+fn parse_model_override(text: &str) -> Option<(&'static str, &str)> {
+    let rest = text.strip_prefix('@')?;
+    let (name, content) = rest.split_once(':')?;
+    let model = ALLOWED_MODELS
+        .iter()
+        .find(|model| model.eq_ignore_ascii_case(name.trim()))?;
+
+    let content = content.trim_start();
+    if content.is_empty() {
+        return None;
+    }
+
+    Some((model, content))
+}
+
+// image 为图片的 data URL，只随本次请求发送给模型，历史中只保存 message（如 "[图片] 说明文字"）
+#[allow(clippy::too_many_arguments)]
+async fn process_chat_message(
+    db_pool: &db::DatabasePool,
+    chat_id: i64,
+    user_id: Option<u64>,
+    message: &str,
+    image: Option<&str>,
+    client: &OpenAiClient,
+    model_override: Option<&str>,
+    source: &str,
+    tg_message_id: Option<MessageId>,
+    reply_to: Option<MessageId>,
+) -> Result<ChatReply, Box<dyn Error + Send + Sync>> {
+    // 开启 PRIVACY_CONSENT 时，未同意的聊天不保存消息，也不加载历史
+    let store_history = if config::privacy_consent_required() {
+        degrade_on_db_error(
+            models::Session::get_privacy_consent(db_pool, chat_id).await,
+            "读取隐私同意状态",
+        )?
+        .flatten()
+        .as_deref()
+            == Some(privacy::GRANTED)
+    } else {
+        true
+    };
+
+    // 查找或创建会话
+    let session_id = if store_history {
+        degrade_on_db_error(
+            models::Session::find_or_create_by_chat_id(db_pool, chat_id).await,
+            "查找会话",
+        )?
+    } else {
+        None
+    };
+
+    // 保存用户消息
+    if let Some(session_id) = session_id {
+        let user_meta = models::MessageMeta {
+            source: Some(source),
+            tg_message_id: tg_message_id.map(|id| id.0 as i64),
+            ..Default::default()
+        };
+        degrade_on_db_error(
+            models::Message::create_with_meta(db_pool, session_id, "user", message, &user_meta)
+                .await,
+            "保存用户消息",
+        )?;
+    }
+
+    // 获取历史消息，条数上限依次使用 /context 的设置、HISTORY_LIMIT 和默认值
+    let history = match session_id {
+        Some(session_id) => {
+            let chat_limit = degrade_on_db_error(
+                models::Session::get_history_limit(db_pool, chat_id).await,
+                "读取历史消息条数设置",
+            )?
+            .flatten();
+            degrade_on_db_error(
+                models::Message::get_messages_within_token_budget(
+                    db_pool,
+                    session_id,
+                    config::history_token_budget(),
+                    config::effective_history_limit(chat_limit),
+                )
+                .await,
+                "加载历史消息",
+            )?
+        }
+        None => None,
+    };
+
+    // 回复了机器人较早的回答时，把那一轮对话放在历史最前面，即使它已经不在最近的历史中
+    let history = match (history, session_id, reply_to) {
+        (Some(mut history), Some(session_id), Some(reply_to)) => {
+            let exchange = degrade_on_db_error(
+                models::Message::get_exchange_by_tg_id(db_pool, session_id, reply_to.0 as i64)
+                    .await,
+                "查找被回复的对话",
+            )?
+            .unwrap_or_default();
+            let in_window = exchange.last().is_some_and(|reply| {
+                history
+                    .iter()
+                    .any(|msg| msg.role == reply.role && msg.content == reply.content)
+            });
+            if !in_window {
+                history.splice(0..0, exchange);
+            }
+            Some(history)
+        }
+        (history, _, _) => history,
+    };
+
+    // 构建 GPT 请求，历史加载失败时只发送当前这条消息
+    let mut messages: Vec<serde_json::Value> = match history {
+        Some(history) => history
+            .iter()
+            .map(|msg| {
+                serde_json::json!({
+                    "role": msg.role,
+                    "content": msg.content
+                })
+            })
+            .collect(),
+        None => vec![serde_json::json!({
+            "role": "user",
+            "content": message
+        })],
+    };
+
+    // 带图片时，把最后一条（即本次的）用户消息换成文字和图片两部分
+    if let Some(image) = image {
+        if messages.last().is_some_and(|last| last["role"] == "user") {
+            messages.pop();
+        }
+        messages.push(serde_json::json!({
+            "role": "user",
+            "content": [
+                { "type": "text", "text": message },
+                { "type": "image_url", "image_url": { "url": image } }
+            ]
+        }));
+    }
+
+    // 固定回复语言时，在最前面加入系统指令
+    let reply_lang = degrade_on_db_error(
+        models::Session::get_reply_lang(db_pool, chat_id).await,
+        "读取回复语言",
+    )?
+    .flatten()
+    .unwrap_or_else(config::default_reply_language);
+    let mut all_messages = Vec::new();
+
+    // CHAT_PERSONAS 中为该聊天配置的人设和 /setname 设置的助手名字，合并为一条系统指令
+    let bot_name = degrade_on_db_error(
+        models::Session::get_bot_name(db_pool, chat_id).await,
+        "读取助手名字",
+    )?
+    .flatten();
+    let identity: Vec<String> = [
+        persona::prompt_for_chat(chat_id),
+        bot_name.map(|name| format!("你的名字是 {}。", name)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !identity.is_empty() {
+        all_messages.push(serde_json::json!({
+            "role": "system",
+            "content": identity.join("\n\n")
+        }));
+    }
+
+    if !reply_lang.eq_ignore_ascii_case("auto") {
+        all_messages.push(serde_json::json!({
+            "role": "system",
+            "content": format!(
+                "无论用户使用何种语言提问，你都必须只使用语言代码为 {} 的语言回复。",
+                reply_lang
+            )
+        }));
+    }
+    all_messages.extend(messages);
+
+    // 单条消息指定的模型优先，其次是 /model 为本聊天选择的模型，再次是 /mymodel 设置的用户默认模型
+    let mut model = match model_override {
+        Some(model) => model.to_string(),
+        None => degrade_on_db_error(
+            resolve_model(db_pool, chat_id, user_id).await,
+            "读取模型设置",
+        )?
+        .unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+    };
+
+    // 上下文超出模型上限时，按 ON_CONTEXT_OVERFLOW 的设置处理
+    if context::exceeds_limit(&all_messages, &model) {
+        let estimated = context::estimate_messages_tokens(&all_messages);
+        match context::overflow_policy() {
+            context::OverflowPolicy::Error => {
+                return Err(format!(
+                    "对话上下文过长（约 {} tokens），超出模型 {} 的上限，请使用 /clear 清除历史后重试",
+                    estimated, model
+                )
+                .into());
+            }
+            context::OverflowPolicy::Upgrade => {
+                let fallback = context::fallback_model();
+                if context::exceeds_limit(&all_messages, &fallback) {
+                    return Err(format!(
+                        "对话上下文过长（约 {} tokens），备用模型 {} 也无法容纳",
+                        estimated, fallback
+                    )
+                    .into());
+                }
+                log::info!(
+                    "上下文约 {} tokens，超出 {} 的上限，切换到 {}",
+                    estimated,
+                    model,
+                    fallback
+                );
+                model = fallback;
+            }
+            context::OverflowPolicy::Summarize => {
+                log::info!(
+                    "上下文约 {} tokens，超出 {} 的上限，总结较早的历史消息",
+                    estimated,
+                    model
+                );
+                all_messages = summarize_older_messages(client, &model, all_messages).await?;
+                if context::exceeds_limit(&all_messages, &model) {
+                    return Err("总结后对话上下文仍然过长，请使用 /clear 清除历史后重试".into());
+                }
+            }
+        }
+    }
+    let model = model.as_str();
+
+    // 本聊天通过 /temperature 设置的值，未设置时使用默认值
+    let temperature = degrade_on_db_error(
+        models::Session::get_temperature(db_pool, chat_id).await,
+        "读取 temperature 设置",
+    )?
+    .flatten()
+    .unwrap_or(config::TEMPERATURE);
+
+    // 调用 GPT API；开启 ENABLE_TOOLS 时模型可以先调用工具，拿到结果后再回答
+    let started_at = std::time::Instant::now();
+    let provider = providers::for_model(model, &client.keys);
+    let tools = config::tools_enabled().then(tools::definitions);
+    let mut usage: Option<openai::Usage> = None;
+    let mut round = 0;
+    let choice = loop {
+        let mut body = serde_json::json!({
+            "model": model,
+            "messages": all_messages,
+            "temperature": temperature
+        });
+        if let Some(max_tokens) = config::max_tokens() {
+            body["max_tokens"] = serde_json::json!(max_tokens);
+        }
+        // 达到轮数上限后不再提供工具，让模型直接回答
+        if let Some(tools) = tools.as_ref().filter(|_| round < tools::MAX_TOOL_ROUNDS) {
+            body["tools"] = serde_json::json!(tools);
+        }
 
-                                bot.send_message(
-                                    msg.chat.id,
-                                    format!("管理员列表:\n{}", admin_list),
-                                )
-                                .await?;
-                            }
-                            Err(e) => {
-                                log::error!("获取管理员列表错误: {:?}", e);
-                                bot.send_message(msg.chat.id, "获取管理员列表时发生错误")
-                                    .await?;
-                            }
+        let completion = client
+            .timed("chat", async {
+                let response =
+                    openai::openai_request_with_retry(provider.api_keys.as_ref(), |key| {
+                        let mut request = client.http.post(provider.chat_completions_url());
+                        if provider.is_default {
+                            request = client.scoped(request);
                         }
-                    }
-                    Ok(false) => {
-                        bot.send_message(msg.chat.id, "⚠️ 您没有管理员权限，无法查看管理员列表")
-                            .await?;
-                    }
-                    Err(e) => {
-                        log::error!("检查管理员权限错误: {:?}", e);
-                        bot.send_message(msg.chat.id, "检查管理员权限时发生错误")
-                            .await?;
-                    }
+                        if let Some(key) = key {
+                            request = request.bearer_auth(key);
+                        }
+                        request.json(&body)
+                    })
+                    .await?;
+                openai::read_chat_completion(response).await
+            })
+            .await?;
+
+        // 处理 GPT 响应，多轮请求的用量累加
+        if let Some(round_usage) = completion.usage {
+            log::debug!(
+                "模型 {} 用量: 提示 {} tokens，回复 {} tokens，共 {} tokens",
+                model,
+                round_usage.prompt_tokens,
+                round_usage.completion_tokens,
+                round_usage.total_tokens
+            );
+            usage = Some(usage.unwrap_or_default() + round_usage);
+        }
+        let Some(choice) = completion.choices.into_iter().next() else {
+            return Err("GPT 响应中没有回复内容".into());
+        };
+        if choice.message.tool_calls.is_empty() {
+            break choice;
+        }
+
+        // 把模型的工具调用和执行结果加入对话，再请求一次
+        round += 1;
+        all_messages.push(serde_json::json!({
+            "role": "assistant",
+            "content": choice.message.text(),
+            "tool_calls": choice.message.tool_calls.iter().map(|call| serde_json::json!({
+                "id": call.id,
+                "type": "function",
+                "function": {
+                    "name": call.function.name,
+                    "arguments": call.function.arguments
                 }
-            }
+            })).collect::<Vec<_>>()
+        }));
+        for call in &choice.message.tool_calls {
+            let result = tools::execute(&call.function.name, &call.function.arguments);
+            log::info!(
+                "聊天 {} 调用工具 {}({})，结果: {}",
+                chat_id,
+                call.function.name,
+                call.function.arguments,
+                result
+            );
+            all_messages.push(serde_json::json!({
+                "role": "tool",
+                "tool_call_id": call.id,
+                "content": result
+            }));
         }
     };
 
-    Ok(())
+    // 统计 finish_reason，失败时不影响回复
+    if let Some(finish_reason) = choice.finish_reason.as_deref() {
+        if let Err(e) = models::FinishReasonCount::increment(db_pool, model, finish_reason).await {
+            log::warn!("记录 finish_reason 失败: {:?}", e);
+        }
+    }
+
+    let Some(content) = choice.message.text() else {
+        return Err("无法解析 GPT 响应".into());
+    };
+
+    // 记录拒绝回答的情况，失败时不影响回复
+    if config::detect_refusals() && refusal::is_refusal(&content) {
+        log::info!("检测到模型 {} 拒绝回答，聊天 {}", model, chat_id);
+        // 未同意保存消息的聊天不保存提问和回复的内容
+        if let Err(e) =
+            refusal::record(db_pool, chat_id, model, message, &content, store_history).await
+        {
+            log::warn!("记录拒绝回答失败: {:?}", e);
+        }
+    }
+
+    // 每条处理完成的消息记录一条结构化日志，LOG_FORMAT=json 时各字段单独输出
+    let latency_ms = started_at.elapsed().as_millis() as i64;
+    tracing::info!(chat_id, user_id, latency_ms, model, source, "消息处理完成");
+
+    // AI 回复由调用方发送给用户后再保存
+    Ok(ChatReply {
+        content,
+        truncated: choice.finish_reason.as_deref() == Some("length"),
+        usage,
+        session_id,
+        model: model.to_string(),
+        latency_ms,
+    })
 }
 
-async fn handle_text_message(
-    bot: Bot,
-    msg: Message,
+// 删除最近一次的 AI 回复，对同一个提问重新生成回答（/regenerate 和编辑提问后使用）
+// 上一条消息还在处理或超出每小时请求上限时不删除原回复
+// reply_to 是编辑后的提问所回复的消息，用于带上被回复的那一轮对话
+#[allow(clippy::too_many_arguments)]
+async fn regenerate_last_reply(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: Option<u64>,
+    reply_to: Option<MessageId>,
     db_pool: &db::DatabasePool,
-    openai_token: &str,
+    client: &OpenAiClient,
+    last_errors: &LastErrorStore,
+    in_flight: &InFlightChats,
 ) -> ResponseResult<()> {
-    // 处理普通文本消息
-    if let Some(text) = msg.text() {
-        if !text.starts_with('/') {
-            // 不是命令的普通文本
-            // 显示"正在思考"的提示
-            let chat_id = msg.chat.id;
-            let thinking_message = bot.send_message(chat_id, "🤔 思考中...").await?;
+    let Some(_in_flight) = acquire_in_flight(bot, chat_id, db_pool, in_flight).await? else {
+        return Ok(());
+    };
+    if !check_rate_limit(bot, chat_id, user_id, db_pool).await {
+        return Ok(());
+    }
+
+    match take_last_exchange(db_pool, chat_id.0).await {
+        Ok(Some((question, tg_message_id))) => {
+            send_chat_reply(
+                bot,
+                chat_id,
+                user_id,
+                &question,
+                tg_message_id,
+                reply_to,
+                None,
+                None,
+                db_pool,
+                client,
+                last_errors,
+            )
+            .await?;
+        }
+        Ok(None) => {
+            let lang = i18n::chat_lang(db_pool, chat_id.0).await;
+            bot.send_message(chat_id, t(lang, Key::NothingToRegenerate))
+                .await?;
+        }
+        Err(e) => {
+            log::error!("读取最近一次对话错误: {:?}", e);
+            let lang = i18n::chat_lang(db_pool, chat_id.0).await;
+            bot.send_message(chat_id, t(lang, Key::RegenerateFailed))
+                .await?;
+        }
+    }
+    Ok(())
+}
 
-            // 处理消息并获取回复
-            match process_chat_message(db_pool, chat_id.0, text, openai_token).await {
-                Ok(response) => {
-                    // 删除"思考中"的消息
-                    bot.delete_message(chat_id, thinking_message.id).await?;
+// 取出最近一轮对话的提问用于重新生成：删除最新的 AI 回复和对应的用户消息
+// 用户消息会在重新提问时再次保存，没有可以重新生成的提问时返回 None
+async fn take_last_exchange(
+    db_pool: &db::DatabasePool,
+    chat_id: i64,
+) -> Result<Option<(String, Option<MessageId>)>, Box<dyn Error + Send + Sync>> {
+    let session_id = models::Session::find_or_create_by_chat_id(db_pool, chat_id).await?;
+    let Some((message_id, content, tg_message_id)) =
+        models::Message::get_last_user_message(db_pool, session_id).await?
+    else {
+        return Ok(None);
+    };
 
-                    // 发送AI回复
-                    bot.send_message(chat_id, response).await?;
-                }
-                Err(e) => {
-                    log::error!("GPT处理错误: {:?}", e);
-                    bot.edit_message_text(
-                        chat_id,
-                        thinking_message.id,
-                        "处理消息时发生错误，请稍后再试。",
-                    )
-                    .await?;
-                }
+    models::Message::delete_last_assistant(db_pool, session_id).await?;
+    models::Message::delete(db_pool, message_id).await?;
+    // 重新保存提问时保留原来的 Telegram 消息ID，之后编辑这条消息仍然能找到
+    Ok(Some((
+        content,
+        tg_message_id.map(|id| MessageId(id as i32)),
+    )))
+}
+
+// 发送AI回复，超长时拆分为多条，并在末尾附加 REPLY_FOOTER（页脚不会保存到历史）
+// reply_to 和 keyboard 只作用于最后一条，返回最后发送的一条消息
+async fn send_reply(
+    bot: &Bot,
+    chat_id: ChatId,
+    content: &str,
+    reply_to: Option<MessageId>,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> ResponseResult<Message> {
+    let footer = config::reply_footer();
+    let mut chunks = reply::split_for_telegram(content, footer.as_deref());
+    let last = chunks.pop().unwrap_or_default();
+
+    for chunk in chunks {
+        send_reply_chunk(bot, chat_id, &chunk, None, None).await?;
+    }
+
+    send_reply_chunk(bot, chat_id, &last, reply_to, keyboard).await
+}
+
+// 发送回复中的一条消息；REPLY_PARSE_MODE=MarkdownV2 时按格式发送，
+// Telegram 拒绝（格式无法解析、转义后超长等）时改为纯文本重新发送
+async fn send_reply_chunk(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    reply_to: Option<MessageId>,
+    keyboard: Option<InlineKeyboardMarkup>,
+) -> ResponseResult<Message> {
+    if config::markdown_replies() {
+        let mut request = bot
+            .send_message(chat_id, markdown::to_markdown_v2(text))
+            .parse_mode(ParseMode::MarkdownV2);
+        if let Some(reply_to) = reply_to {
+            request = request.reply_parameters(ReplyParameters::new(reply_to));
+        }
+        if let Some(keyboard) = keyboard.clone() {
+            request = request.reply_markup(keyboard);
+        }
+        match request.await {
+            Ok(message) => return Ok(message),
+            Err(RequestError::Api(e)) => {
+                log::warn!("MarkdownV2 格式的回复发送失败，改为纯文本发送: {}", e);
             }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let mut request = bot.send_message(chat_id, text);
+    if let Some(reply_to) = reply_to {
+        request = request.reply_parameters(ReplyParameters::new(reply_to));
+    }
+    if let Some(keyboard) = keyboard {
+        request = request.reply_markup(keyboard);
+    }
+    request.await
+}
+
+// 将文本合成为语音发送，失败时提示用户查看文字回复
+async fn send_speech(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: &str,
+    client: &OpenAiClient,
+    last_errors: &LastErrorStore,
+    lang: i18n::Lang,
+) -> ResponseResult<()> {
+    match client
+        .timed("speech", synthesize_speech(text, client))
+        .await
+    {
+        Ok(audio) => {
+            bot.send_voice(chat_id, InputFile::memory(audio).file_name("reply.ogg"))
+                .await?;
+        }
+        Err(e) => {
+            let trace_id = last_errors.record(chat_id.0, &e.to_string());
+            log::error!("[{}] 语音合成错误: {:?}", trace_id, e);
+            bot.send_message(chat_id, t(lang, Key::SpeechFailed))
+                .await?;
         }
     }
     Ok(())
 }
 
-async fn process_chat_message(
+// GPT 的回复，发送给用户之后再调用 persist 保存到数据库
+struct ChatReply {
+    content: String,
+    // 达到 max_tokens 上限被截断（finish_reason 为 length）
+    truncated: bool,
+    // 本次请求的 token 用量，接口没有返回时为 None
+    usage: Option<openai::Usage>,
+    // 会话加载失败并降级时为 None，此时不保存
+    session_id: Option<i64>,
+    model: String,
+    latency_ms: i64,
+}
+
+impl ChatReply {
+    // 发送给用户的文本，被截断时附加提示（提示不会保存到历史）
+    fn text(&self, lang: i18n::Lang) -> String {
+        if self.truncated {
+            format!("{}\n\n{}", self.content, t(lang, Key::ReplyTruncated))
+        } else {
+            self.content.clone()
+        }
+    }
+
+    // 保存 AI 回复；回复已经发送给用户，保存失败只记录日志
+    async fn persist(&self, db_pool: &db::DatabasePool, tg_message_id: Option<MessageId>) {
+        let Some(session_id) = self.session_id else {
+            return;
+        };
+
+        let assistant_meta = models::MessageMeta {
+            model: Some(&self.model),
+            latency_ms: Some(self.latency_ms),
+            tg_message_id: tg_message_id.map(|id| id.0 as i64),
+            ..Default::default()
+        };
+        if let Err(e) = models::Message::create_with_usage(
+            db_pool,
+            session_id,
+            "assistant",
+            &self.content,
+            &assistant_meta,
+            self.usage.as_ref(),
+        )
+        .await
+        {
+            log::error!("保存 AI 回复失败（回复已发送给用户）: {:?}", e);
+        }
+    }
+}
+
+// 数据库操作失败时的处理：设置 DEGRADE_ON_DB_ERROR=true 时记录警告并返回 None，
+// 由调用方降级为不带历史的单轮请求；否则直接返回错误
+fn degrade_on_db_error<T>(
+    result: Result<T, AppError>,
+    action: &str,
+) -> Result<Option<T>, AppError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if config::degrade_on_db_error() => {
+            log::warn!("{}失败，降级为无历史的单轮请求: {:?}", action, e);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// 将较早的历史消息总结为一条系统消息，保留开头的系统指令和最近几条消息原文
+async fn summarize_older_messages(
+    client: &OpenAiClient,
+    model: &str,
+    messages: Vec<Value>,
+) -> Result<Vec<Value>, Box<dyn Error + Send + Sync>> {
+    let (system, conversation): (Vec<Value>, Vec<Value>) = messages
+        .into_iter()
+        .partition(|message| message["role"] == "system");
+
+    if conversation.len() <= context::KEEP_RECENT_MESSAGES {
+        // 没有可以总结的历史
+        return Ok(system.into_iter().chain(conversation).collect());
+    }
+
+    let split_at = conversation.len() - context::KEEP_RECENT_MESSAGES;
+    let transcript = conversation[..split_at]
+        .iter()
+        .map(|message| {
+            format!(
+                "{}: {}",
+                message["role"].as_str().unwrap_or_default(),
+                message["content"].as_str().unwrap_or_default()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let summary = summarize_text(client, model, &transcript).await?;
+
+    let mut result = system;
+    result.push(serde_json::json!({
+        "role": "system",
+        "content": format!("以下是之前对话的摘要：\n{}", summary)
+    }));
+    result.extend(conversation.into_iter().skip(split_at));
+    Ok(result)
+}
+
+// 手动压缩对话：保留最近几条消息，其余总结为一条保存下来的摘要
+// 摘要使用聊天当前的模型，返回被压缩的消息条数和大约节省的 token 数，没有可压缩的消息时返回 None
+async fn compress_history(
     db_pool: &db::DatabasePool,
     chat_id: i64,
-    message: &str,
-    api_key: &str,
-) -> Result<String, Box<dyn Error + Send + Sync>> {
-    // 查找或创建会话
+    client: &OpenAiClient,
+    model: &str,
+) -> Result<Option<(usize, usize)>, Box<dyn Error + Send + Sync>> {
     let session_id = models::Session::find_or_create_by_chat_id(db_pool, chat_id).await?;
+    let messages = models::Message::get_session_messages(db_pool, session_id).await?;
 
-    // 保存用户消息
-    models::Message::create(db_pool, session_id, "user", message).await?;
+    if messages.len() <= context::KEEP_RECENT_MESSAGES + 1 {
+        return Ok(None);
+    }
+
+    let split_at = messages.len() - context::KEEP_RECENT_MESSAGES;
+    let older = &messages[..split_at];
+    let transcript = older
+        .iter()
+        .map(|(_, message)| format!("{}: {}", message.role, message.content))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let summary = summarize_text(client, model, &transcript).await?;
+    let summary = format!("以下是之前对话的摘要：\n{}", summary);
+
+    let ids: Vec<i64> = older.iter().map(|(id, _)| *id).collect();
+    models::Message::collapse_into_summary(db_pool, session_id, &ids, &summary).await?;
+
+    let saved_tokens =
+        context::estimate_tokens(&transcript).saturating_sub(context::estimate_tokens(&summary));
+    Ok(Some((older.len(), saved_tokens)))
+}
+
+// 聊天中保存的消息数
+async fn count_session_messages(
+    db_pool: &db::DatabasePool,
+    chat_id: i64,
+) -> Result<usize, AppError> {
+    let session_id = models::Session::find_or_create_by_chat_id(db_pool, chat_id).await?;
+    Ok(models::Message::get_session_messages(db_pool, session_id)
+        .await?
+        .len())
+}
 
-    // 获取历史消息
-    let history = models::Message::get_recent_messages(db_pool, session_id, 10).await?;
+// /summary confirm：将整段对话总结为一条摘要并替换全部历史
+// 返回被替换的消息数和摘要内容，少于两条消息时返回 None
+async fn summarize_history(
+    db_pool: &db::DatabasePool,
+    chat_id: i64,
+    client: &OpenAiClient,
+    model: &str,
+) -> Result<Option<(u64, String)>, Box<dyn Error + Send + Sync>> {
+    let session_id = models::Session::find_or_create_by_chat_id(db_pool, chat_id).await?;
+    let messages = models::Message::get_session_messages(db_pool, session_id).await?;
+    if messages.len() < 2 {
+        return Ok(None);
+    }
 
-    // 构建 GPT 请求
-    let messages: Vec<serde_json::Value> = history
+    let lines = messages
         .iter()
-        .map(|msg| {
-            serde_json::json!({
-                "role": msg.role,
-                "content": msg.content
-            })
-        })
-        .collect();
-
-    // 添加当前消息
-    let all_messages = messages;
-
-    // 调用 GPT API
-    let client = reqwest::Client::builder().build()?;
-    let response = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(api_key)
-        .json(&serde_json::json!({
-            "model": "gpt-4o-mini",
-            "messages": all_messages,
-            "temperature": 0.7
-        }))
-        .send()
-        .await?;
+        .map(|(_, message)| format!("{}: {}", message.role, message.content))
+        .collect::<Vec<String>>();
+    let summary = summarize_in_chunks(client, model, lines).await?;
 
-    // 处理 GPT 响应
-    if response.status().is_success() {
-        let json: Value = response.json().await?;
-        if let Some(content) = json["choices"][0]["message"]["content"].as_str() {
-            // 保存 AI 回复
-            models::Message::create(db_pool, session_id, "assistant", content).await?;
-            Ok(content.to_string())
-        } else {
-            Err("无法解析 GPT 响应".into())
+    let replaced = models::Message::replace_history_with_summary(
+        db_pool,
+        session_id,
+        &format!("以下是之前对话的摘要：\n{}", summary),
+    )
+    .await?;
+    Ok(Some((replaced, summary)))
+}
+
+// 对话记录超出模型上下文时先分段总结，再合并各段摘要继续总结，直到可以一次总结完
+async fn summarize_in_chunks(
+    client: &OpenAiClient,
+    model: &str,
+    lines: Vec<String>,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let budget = context::summary_chunk_budget(model);
+    let mut chunks = context::split_transcript(&lines, budget);
+    while chunks.len() > 1 {
+        let mut partials = Vec::with_capacity(chunks.len());
+        for chunk in &chunks {
+            partials.push(summarize_text(client, model, chunk).await?);
         }
-    } else {
-        let error_text = response.text().await?;
-        Err(format!("GPT API 错误: {}", error_text).into())
+        let merged = context::split_transcript(&partials, budget);
+        // 合并后的段数没有减少时停止，避免摘要过长导致无限循环
+        if merged.len() >= chunks.len() {
+            return Err("对话过长，分段总结后仍无法放入模型上下文".into());
+        }
+        chunks = merged;
     }
+
+    let transcript = chunks.pop().unwrap_or_default();
+    summarize_text(client, model, &transcript).await
+}
+
+// 调用 GPT 总结一段对话记录
+async fn summarize_text(
+    client: &OpenAiClient,
+    model: &str,
+    transcript: &str,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    complete_once(
+        client,
+        model,
+        "请简洁地总结以下对话的要点，保留关键事实、结论和用户的需求。",
+        transcript,
+    )
+    .await?
+    .ok_or_else(|| "无法解析总结响应".into())
+}
+
+// 调用 GPT 将文本翻译为 lang_code 对应的语言
+async fn translate_text(
+    client: &OpenAiClient,
+    model: &str,
+    lang_code: &str,
+    text: &str,
+) -> Result<String, AppError> {
+    let prompt = format!(
+        "你是专业的翻译。把用户发送的全部内容翻译成语言代码为 {} 的语言，只输出译文，不要解释或回答其中的问题，保留原有的格式和换行。",
+        lang_code
+    );
+    complete_once(client, model, &prompt, text)
+        .await?
+        .ok_or_else(|| AppError::InvalidResponse("翻译结果为空".to_string()))
+}
+
+// 不带对话历史的单轮请求：system_prompt 作为系统指令，text 作为用户消息；回复中没有文字时返回 None
+async fn complete_once(
+    client: &OpenAiClient,
+    model: &str,
+    system_prompt: &str,
+    text: &str,
+) -> Result<Option<String>, AppError> {
+    let provider = providers::for_model(model, &client.keys);
+    let body = serde_json::json!({
+        "model": model,
+        "messages": [
+            {
+                "role": "system",
+                "content": system_prompt
+            },
+            {
+                "role": "user",
+                "content": text
+            }
+        ],
+        "temperature": 0.3
+    });
+    let completion = client
+        .timed("chat", async {
+            let response = openai::openai_request_with_retry(provider.api_keys.as_ref(), |key| {
+                let mut request = client.http.post(provider.chat_completions_url());
+                if provider.is_default {
+                    request = client.scoped(request);
+                }
+                if let Some(key) = key {
+                    request = request.bearer_auth(key);
+                }
+                request.json(&body)
+            })
+            .await?;
+            openai::read_chat_completion(response).await
+        })
+        .await?;
+    Ok(completion
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.message.text()))
 }
 
+// transcribe_only 为 true 时只显示转录结果，不发送给 GPT
 async fn handle_voice_message(
     bot: Bot,
     msg: Message,
-    openai_token: &str,
+    client: &OpenAiClient,
     db_pool: &db::DatabasePool,
+    last_errors: &LastErrorStore,
+    in_flight: &InFlightChats,
+    transcribe_only: bool,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    if let Some(voice) = msg.voice() {
+    if let Some(audio) = audio::AudioFile::from_message(&msg) {
         let chat_id = msg.chat.id;
+        let lang = i18n::chat_lang(db_pool, chat_id.0).await;
+
+        // 新聊天第一次发消息时询问是否同意保存消息
+        ask_privacy_consent_once(&bot, chat_id, db_pool).await?;
+
+        // 超过大小上限的文件不下载，转录接口也会拒绝过大的文件
+        let max_audio_bytes = config::max_audio_bytes();
+        if u64::from(audio.size) > max_audio_bytes {
+            let text = t(lang, Key::FileTooLarge)
+                .replace("{size}", &config::format_megabytes(audio.size.into()))
+                .replace("{max}", &config::format_megabytes(max_audio_bytes));
+            bot.send_message(chat_id, text).await?;
+            return Ok(());
+        }
+
+        // 同一聊天的上一条消息还在处理时不再转录
+        let Some(_in_flight) = acquire_in_flight(&bot, chat_id, db_pool, in_flight).await? else {
+            return Ok(());
+        };
+
+        // 超出每小时请求上限时不再转录和回复
+        let user_id = msg.from.as_ref().map(|user| user.id.0);
+        if !check_rate_limit(&bot, chat_id, user_id, db_pool).await {
+            return Ok(());
+        }
 
         // 发送"处理中"信息
         let processing_msg = bot
-            .send_message(chat_id, "正在处理您的语音消息，请稍候...")
+            .send_message(chat_id, t(lang, Key::ProcessingVoice))
             .await?;
 
-        // 获取语音文件
-        let file_id = &voice.file.id;
-        let file = bot.get_file(file_id).await?;
+        // 获取并下载语音文件，遇到 Telegram 限流时会自动退避重试
+        let voice_data = match fetch_file(&bot, &audio.file_id, &processing_msg).await {
+            Ok(data) => data,
+            Err(e) => {
+                let trace_id = last_errors.record(chat_id.0, &e.to_string());
+                log::error!("[{}] 获取语音文件失败: {:?}", trace_id, e);
+                bot.edit_message_text(chat_id, processing_msg.id, t(lang, Key::VoiceFetchFailed))
+                    .await?;
+                return Ok(());
+            }
+        };
 
-        // 下载语音文件到内存
-        let voice_data = download_voice(&bot, &file).await?;
+        // 是否需要带时间戳的分段转录
+        let show_timestamps = models::Session::get_show_timestamps(db_pool, chat_id.0)
+            .await
+            .unwrap_or_else(|e| {
+                log::error!("读取时间戳设置错误: {:?}", e);
+                false
+            });
 
         // 发送到OpenAI进行转录
-        match transcribe_audio(&voice_data, openai_token).await {
-            Ok(text) => {
-                // 显示转录结果
-                bot.edit_message_text(chat_id, processing_msg.id, format!("语音内容: {}", text))
+        let transcription = client.timed(
+            "transcription",
+            transcribe_audio(&voice_data, &audio, client, show_timestamps),
+        );
+        match transcription.await {
+            Ok(transcription) => {
+                let text = transcription.text;
+
+                // 静音或噪音可能转录出空文本，不再显示和发送给 GPT，也不会保存空消息
+                if text.trim().is_empty() {
+                    log::info!("聊天 {} 的语音转录结果为空", chat_id);
+                    last_errors.clear(chat_id.0);
+                    bot.edit_message_text(
+                        chat_id,
+                        processing_msg.id,
+                        t(lang, Key::EmptyTranscription),
+                    )
                     .await?;
+                    return Ok(());
+                }
+
+                // 显示转录结果
+                let display = if show_timestamps && !transcription.segments.is_empty() {
+                    format!("\n{}", format_segments(&transcription.segments))
+                } else {
+                    text.clone()
+                };
+                bot.edit_message_text(
+                    chat_id,
+                    processing_msg.id,
+                    t(lang, Key::VoiceTranscript).replace("{text}", &display),
+                )
+                .await?;
+
+                if transcribe_only {
+                    last_errors.clear(chat_id.0);
+                    return Ok(());
+                }
+
+                // 显示"正在思考"的提示，等待回复期间同时显示"正在输入"
+                let thinking_message = thinking::send_thinking(&bot, chat_id, lang).await?;
+                let typing = typing::TypingIndicator::start(bot.clone(), chat_id);
 
-                // 将转录内容保存到数据库
-                let session_id =
-                    models::Session::find_or_create_by_chat_id(db_pool, chat_id.0).await?;
-                models::Message::create(db_pool, session_id, "user", &text).await?;
+                // 处理消息并获取回复（转录内容会在其中保存到数据库）
+                let result = process_chat_message(
+                    db_pool,
+                    chat_id.0,
+                    user_id,
+                    &text,
+                    None,
+                    client,
+                    None,
+                    "voice",
+                    Some(msg.id),
+                    msg.reply_to_message().map(|reply| reply.id),
+                )
+                .await;
+                drop(typing);
 
-                // 显示"正在思考"的提示
-                let thinking_message = bot.send_message(chat_id, "🤔 思考中...").await?;
+                match result {
+                    Ok(reply) => {
+                        last_errors.clear(chat_id.0);
 
-                // 处理消息并获取回复
-                match process_chat_message(db_pool, chat_id.0, &text, openai_token).await {
-                    Ok(response) => {
                         // 删除"思考中"的消息
-                        bot.delete_message(chat_id, thinking_message.id).await?;
+                        thinking::delete_thinking(&bot, chat_id, thinking_message.as_ref()).await;
 
-                        // 发送AI回复
-                        bot.send_message(chat_id, response).await?;
-                    }
-                    Err(e) => {
-                        log::error!("GPT处理错误: {:?}", e);
-                        bot.edit_message_text(
+                        // 先发送AI回复（文字版本始终保留），再保存到数据库
+                        // 回复挂在原语音消息下，按钮回调时通过回复关系找到语音文件
+                        let sent = send_reply(
+                            &bot,
                             chat_id,
-                            thinking_message.id,
-                            "处理消息时发生错误，请稍后再试。",
+                            &reply.text(lang),
+                            Some(msg.id),
+                            Some(voice_actions::keyboard()),
                         )
                         .await?;
+                        reply.persist(db_pool, Some(sent.id)).await;
+
+                        // 语音助手模式下再将回复合成为语音发送
+                        let voice_assistant =
+                            models::Session::get_voice_assistant(db_pool, chat_id.0)
+                                .await
+                                .unwrap_or_else(|e| {
+                                    log::error!("读取语音助手设置错误: {:?}", e);
+                                    false
+                                });
+                        if voice_assistant {
+                            send_speech(&bot, chat_id, &reply.content, client, last_errors, lang)
+                                .await?;
+                        }
+                    }
+                    Err(e) => {
+                        let trace_id = last_errors.record(chat_id.0, &e.to_string());
+                        log::error!("[{}] GPT处理错误: {:?}", trace_id, e);
+                        let text = error_reply(db_pool, lang, user_id, e.as_ref()).await;
+                        thinking::show_in_thinking(&bot, chat_id, thinking_message.as_ref(), text)
+                            .await?;
                     }
                 }
             }
             Err(e) => {
-                bot.edit_message_text(chat_id, processing_msg.id, format!("处理语音时出错: {}", e))
+                let trace_id = last_errors.record(chat_id.0, &e.to_string());
+                log::error!("[{}] 语音转录错误: {:?}", trace_id, e);
+                let text = error_reply(db_pool, lang, user_id, &e).await;
+                bot.edit_message_text(chat_id, processing_msg.id, text)
                     .await?;
             }
         }
@@ -626,48 +4350,224 @@ async fn handle_voice_message(
     Ok(())
 }
 
+// 处理图片消息：下载最大尺寸的图片，连同说明文字发送给支持图片的模型
+// 历史中只保存 "[图片]" 和说明文字，图片本身不保存
+#[allow(clippy::too_many_arguments)]
+async fn handle_photo_message(
+    bot: Bot,
+    msg: Message,
+    db_pool: &db::DatabasePool,
+    client: &OpenAiClient,
+    last_errors: &LastErrorStore,
+    in_flight: &InFlightChats,
+    bot_username: &str,
+) -> ResponseResult<()> {
+    let Some(photo) = msg
+        .photo()
+        .and_then(|sizes| sizes.iter().max_by_key(|size| size.width * size.height))
+    else {
+        return Ok(());
+    };
+    let chat_id = msg.chat.id;
+    let user_id = msg.from.as_ref().map(|user| user.id.0);
+
+    // 新聊天第一次发消息时询问是否同意保存消息
+    ask_privacy_consent_once(&bot, chat_id, db_pool).await?;
+
+    // 同一聊天的上一条消息还在处理时不再处理图片
+    let Some(_in_flight) = acquire_in_flight(&bot, chat_id, db_pool, in_flight).await? else {
+        return Ok(());
+    };
+
+    // 超出每小时请求上限时不再调用 GPT
+    if !check_rate_limit(&bot, chat_id, user_id, db_pool).await {
+        return Ok(());
+    }
+
+    let lang = i18n::chat_lang(db_pool, chat_id.0).await;
+    let processing_msg = bot
+        .send_message(chat_id, t(lang, Key::ProcessingPhoto))
+        .await?;
+    let image = match fetch_file(&bot, &photo.file.id, &processing_msg).await {
+        Ok(data) => data,
+        Err(e) => {
+            let trace_id = last_errors.record(chat_id.0, &e.to_string());
+            log::error!("[{}] 获取图片失败: {:?}", trace_id, e);
+            bot.edit_message_text(chat_id, processing_msg.id, t(lang, Key::PhotoFetchFailed))
+                .await?;
+            return Ok(());
+        }
+    };
+    bot.delete_message(chat_id, processing_msg.id).await?;
+
+    // Telegram 的图片消息统一为 JPEG 格式
+    let image_url = format!("data:image/jpeg;base64,{}", BASE64.encode(&image));
+    let caption = msg
+        .caption()
+        .map(|caption| strip_mention(caption, bot_username))
+        .filter(|caption| !caption.is_empty());
+    let text = match caption {
+        Some(caption) => format!("[图片] {}", caption),
+        None => "[图片]".to_string(),
+    };
+
+    send_chat_reply(
+        &bot,
+        chat_id,
+        user_id,
+        &text,
+        Some(msg.id),
+        msg.reply_to_message().map(|reply| reply.id),
+        Some(&image_url),
+        Some(&config::vision_model()),
+        db_pool,
+        client,
+        last_errors,
+    )
+    .await
+}
+
+/// 获取文件信息并下载到内存，两个步骤都会在限流时重试
+///
+/// 开启 SHOW_RETRY_STATUS 时，重试等待期间会把占位消息更新为重试提示
+async fn fetch_file(bot: &Bot, file_id: &str, placeholder: &Message) -> Result<Vec<u8>, AppError> {
+    let show_status = config::show_retry_status();
+    let on_retry = |_, _| async move {
+        if show_status {
+            let _ = bot
+                .edit_message_text(
+                    placeholder.chat.id,
+                    placeholder.id,
+                    retry::RETRY_STATUS_TEXT,
+                )
+                .await;
+        }
+    };
+
+    let file = retry::retry_with_progress(
+        retry::DEFAULT_MAX_RETRIES,
+        retry::DEFAULT_BASE_DELAY,
+        || bot.get_file(file_id).send(),
+        retry::classify_request_error,
+        on_retry,
+    )
+    .await?;
+
+    download_file(bot, &file, on_retry).await
+}
+
 /// 将文件下载到内存而不是保存为文件
-async fn download_voice(bot: &Bot, file: &TgFile) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
-    // 创建内存缓冲区
-    let mut buffer = Vec::new();
+async fn download_file<P, PFut>(bot: &Bot, file: &TgFile, on_retry: P) -> Result<Vec<u8>, AppError>
+where
+    P: FnMut(u32, std::time::Duration) -> PFut,
+    PFut: std::future::Future<Output = ()>,
+{
+    let buffer = retry::retry_with_progress(
+        retry::DEFAULT_MAX_RETRIES,
+        retry::DEFAULT_BASE_DELAY,
+        move || async move {
+            // 每次尝试都使用新的内存缓冲区
+            let mut buffer = Vec::new();
+
+            // 下载文件到内存
+            bot.download_file(&file.path, &mut buffer).await?;
 
-    // 下载文件到内存
-    bot.download_file(&file.path, &mut buffer).await?;
+            Ok(buffer)
+        },
+        retry::classify_download_error,
+        on_retry,
+    )
+    .await?;
 
     Ok(buffer)
 }
 
 /// 从内存数据中转录音频
+///
+/// `audio` 提供文件名和 MIME 类型，转录接口据此识别音频格式（mp3、m4a、wav 等）。
+/// `with_segments` 为 true 时请求 verbose_json 格式，返回结果中包含带时间的分段
 async fn transcribe_audio(
     audio_data: &[u8],
-    api_key: &str,
-) -> Result<String, Box<dyn Error + Send + Sync>> {
-    // 创建multipart表单
-    let part = Part::bytes(audio_data.to_vec())
-        .file_name("audio.oga")
-        .mime_str("audio/ogg")?;
-
-    let form = Form::new().part("file", part).text("model", "whisper-1");
-
-    // 发送请求到OpenAI
-    let client = reqwest::Client::new();
-    let response = client
-        .post("https://api.openai.com/v1/audio/transcriptions")
-        .bearer_auth(api_key)
-        .multipart(form)
-        .send()
-        .await?;
+    audio: &audio::AudioFile,
+    client: &OpenAiClient,
+    with_segments: bool,
+) -> Result<OpenAIResponse, AppError> {
+    // 发送请求到OpenAI，multipart 表单无法复用，每次重试重新创建
+    let response = openai::openai_request_with_retry(Some(&client.keys), |key| {
+        let part = Part::bytes(audio_data.to_vec())
+            .file_name(audio.file_name.clone())
+            .mime_str(&audio.mime_type)
+            .expect("MIME 类型来自 Telegram 文件信息或内置列表");
+
+        let mut form = Form::new().part("file", part).text("model", "whisper-1");
+        if with_segments {
+            form = form.text("response_format", "verbose_json");
+        }
+
+        client
+            .scoped(
+                client
+                    .http
+                    .post(providers::default_endpoint("audio/transcriptions")),
+            )
+            .bearer_auth(key.unwrap_or_default())
+            .multipart(form)
+    })
+    .await?;
 
     // 处理响应
     if response.status().is_success() {
-        let json: Value = response.json().await?;
-        if let Some(text) = json["text"].as_str() {
-            Ok(text.to_string())
-        } else {
-            Err("无法获取文字内容".into())
+        match response.json::<OpenAIResponse>().await {
+            Ok(json) => Ok(json),
+            Err(_) => Err(AppError::Transcription("无法获取文字内容".to_string())),
         }
     } else {
-        let error_text = response.text().await?;
-        Err(format!("API错误: {}", error_text).into())
+        Err(openai::api_error(response).await)
+    }
+}
+
+// 将文本合成为语音（OGG/Opus 格式，可直接作为 Telegram 语音消息发送）
+async fn synthesize_speech(text: &str, client: &OpenAiClient) -> Result<Vec<u8>, AppError> {
+    let voice = config::tts_voice();
+
+    let body = serde_json::json!({
+        "model": "tts-1",
+        "input": text,
+        "voice": voice,
+        "response_format": "opus"
+    });
+    let response = openai::openai_request_with_retry(Some(&client.keys), |key| {
+        client
+            .scoped(
+                client
+                    .http
+                    .post(providers::default_endpoint("audio/speech")),
+            )
+            .bearer_auth(key.unwrap_or_default())
+            .json(&body)
+    })
+    .await?;
+
+    if response.status().is_success() {
+        Ok(response.bytes().await?.to_vec())
+    } else {
+        Err(openai::api_error(response).await)
     }
 }
+
+/// 将转录分段格式化为每行带 [mm:ss] 前缀的文本
+fn format_segments(segments: &[TranscriptionSegment]) -> String {
+    segments
+        .iter()
+        .map(|segment| {
+            let seconds = segment.start.max(0.0) as u64;
+            format!(
+                "[{:02}:{:02}] {}",
+                seconds / 60,
+                seconds % 60,
+                segment.text.trim()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
@@ -0,0 +1,152 @@
+// 将模型输出的常见 Markdown 转换为 Telegram MarkdownV2
+// 支持代码块、行内代码、**粗体**、*斜体*、链接和标题（显示为粗体），其余特殊字符全部转义
+// 不成对的 ` 和 * 等按普通字符转义；Telegram 仍然拒绝时由调用方改为纯文本发送
+
+// MarkdownV2 普通文本中需要转义的字符
+const SPECIAL_CHARS: &str = "_*[]()~`>#+-=|{}.!\\";
+
+pub fn to_markdown_v2(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() * 2);
+    let mut rest = text;
+
+    // 先处理 ``` 代码块，代码块之外的部分按行转换
+    while let Some(start) = rest.find("```") {
+        let Some(length) = rest[start + 3..].find("```") else {
+            break;
+        };
+        let end = start + 3 + length;
+
+        convert_lines(&rest[..start], &mut out);
+        out.push_str("```");
+        out.push_str(&escape_code(&rest[start + 3..end]));
+        out.push_str("```");
+        rest = &rest[end + 3..];
+    }
+    convert_lines(rest, &mut out);
+
+    out
+}
+
+// 逐行转换，# 开头的标题显示为粗体
+fn convert_lines(text: &str, out: &mut String) {
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            out.push('\n');
+        }
+
+        let heading = line.trim_start_matches('#');
+        if heading.len() < line.len() && line.len() - heading.len() <= 6 && heading.starts_with(' ')
+        {
+            let title = heading.trim().replace("**", "");
+            if !title.is_empty() {
+                out.push('*');
+                out.push_str(&escape_text(&title));
+                out.push('*');
+                continue;
+            }
+        }
+
+        convert_inline(line, out);
+    }
+}
+
+// 转换一行中的行内格式
+fn convert_inline(line: &str, out: &mut String) {
+    let mut rest = line;
+
+    while let Some(ch) = rest.chars().next() {
+        if ch == '`' {
+            if let Some((code, next)) = enclosed(&rest[1..], "`") {
+                out.push('`');
+                out.push_str(&escape_code(code));
+                out.push('`');
+                rest = next;
+                continue;
+            }
+        } else if let Some(after) = rest.strip_prefix("**") {
+            if let Some((bold, next)) = enclosed(after, "**") {
+                out.push('*');
+                out.push_str(&escape_text(bold));
+                out.push('*');
+                rest = next;
+                continue;
+            }
+        } else if ch == '*' {
+            // 列表项的 "* " 不是斜体，开头和结尾紧挨文字时才按斜体处理
+            if let Some((italic, next)) = enclosed(&rest[1..], "*") {
+                if !italic.starts_with(' ') && !italic.ends_with(' ') {
+                    out.push('_');
+                    out.push_str(&escape_text(italic));
+                    out.push('_');
+                    rest = next;
+                    continue;
+                }
+            }
+        } else if ch == '[' {
+            if let Some((label, url, next)) = link(rest) {
+                out.push('[');
+                out.push_str(&escape_text(label));
+                out.push_str("](");
+                out.push_str(&escape_url(url));
+                out.push(')');
+                rest = next;
+                continue;
+            }
+        }
+
+        push_escaped(ch, out);
+        rest = &rest[ch.len_utf8()..];
+    }
+}
+
+// 查找结束标记，返回其中非空的内容和结束标记之后的文本
+fn enclosed<'a>(text: &'a str, marker: &str) -> Option<(&'a str, &'a str)> {
+    let end = text.find(marker)?;
+    if end == 0 {
+        return None;
+    }
+    Some((&text[..end], &text[end + marker.len()..]))
+}
+
+// 解析 [文字](地址) 形式的链接
+fn link(text: &str) -> Option<(&str, &str, &str)> {
+    let close = text.find("](")?;
+    let label = &text[1..close];
+    let after = &text[close + 2..];
+    let end = after.find(')')?;
+    let url = &after[..end];
+    if label.is_empty()
+        || label.contains('[')
+        || url.is_empty()
+        || url.contains(char::is_whitespace)
+    {
+        return None;
+    }
+    Some((label, url, &after[end + 1..]))
+}
+
+fn push_escaped(ch: char, out: &mut String) {
+    if SPECIAL_CHARS.contains(ch) {
+        out.push('\\');
+    }
+    out.push(ch);
+}
+
+// 转义普通文本
+fn escape_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        push_escaped(ch, &mut out);
+    }
+    out
+}
+
+// 代码中只需要转义 ` 和 \
+fn escape_code(code: &str) -> String {
+    code.replace('\\', "\\\\").replace('`', "\\`")
+}
+
+// 链接地址中只需要转义 ) 和 \
+fn escape_url(url: &str) -> String {
+    url.replace('\\', "\\\\").replace(')', "\\)")
+}
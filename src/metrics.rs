@@ -0,0 +1,79 @@
+use prometheus_client::encoding::text::encode;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::Registry;
+use std::sync::Arc;
+use std::time::Duration;
+
+// 请求种类：chat、transcription、speech
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct KindLabels {
+    kind: &'static str,
+}
+
+// 请求种类和结果（success / error）
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct OutcomeLabels {
+    kind: &'static str,
+    outcome: &'static str,
+}
+
+// 耗时分桶：0.25 秒到约 128 秒，按 2 倍递增
+fn duration_histogram() -> Histogram {
+    Histogram::new(exponential_buckets(0.25, 2.0, 10))
+}
+
+// OpenAI 请求的耗时和结果统计，以 Prometheus 文本格式通过 /metrics 输出
+// 克隆后共用同一份统计
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+    durations: Family<KindLabels, Histogram, fn() -> Histogram>,
+    requests: Family<OutcomeLabels, Counter>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let durations: Family<KindLabels, Histogram, fn() -> Histogram> =
+            Family::new_with_constructor(duration_histogram);
+        let requests = Family::<OutcomeLabels, Counter>::default();
+
+        let mut registry = Registry::default();
+        registry.register(
+            "openai_request_duration_seconds",
+            "OpenAI 请求耗时（包括重试和读取响应）",
+            durations.clone(),
+        );
+        registry.register("openai_requests", "OpenAI 请求次数", requests.clone());
+
+        Metrics {
+            registry: Arc::new(registry),
+            durations,
+            requests,
+        }
+    }
+}
+
+impl Metrics {
+    // 记录一次请求的耗时和结果
+    pub fn observe(&self, kind: &'static str, success: bool, elapsed: Duration) {
+        self.durations
+            .get_or_create(&KindLabels { kind })
+            .observe(elapsed.as_secs_f64());
+        let outcome = if success { "success" } else { "error" };
+        self.requests
+            .get_or_create(&OutcomeLabels { kind, outcome })
+            .inc();
+    }
+
+    // 输出 Prometheus / OpenMetrics 文本格式
+    pub fn encode(&self) -> String {
+        let mut text = String::new();
+        if let Err(e) = encode(&mut text, &self.registry) {
+            log::error!("输出指标失败: {:?}", e);
+        }
+        text
+    }
+}
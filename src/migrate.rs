@@ -0,0 +1,288 @@
+use crate::db::{self, DatabasePool};
+use chrono::NaiveDateTime;
+use sqlx::{Pool, Postgres, Row, Sqlite};
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+
+// 每个事务写入的行数
+const BATCH_SIZE: i64 = 500;
+
+// 迁移结果，记录各表写入目标数据库的行数
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    pub sessions: u64,
+    pub messages: u64,
+    pub skipped_messages: u64,
+    pub whitelist_users: u64,
+    pub admins: u64,
+    pub user_preferences: u64,
+}
+
+impl MigrationReport {
+    // 格式化为便于查看的文本
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "✅ 迁移完成\n\n会话: {}\n消息: {}\n白名单用户: {}\n管理员: {}\n用户偏好: {}",
+            self.sessions, self.messages, self.whitelist_users, self.admins, self.user_preferences
+        );
+        if self.skipped_messages > 0 {
+            text.push_str(&format!(
+                "\n\n跳过了 {} 条找不到所属会话的消息",
+                self.skipped_messages
+            ));
+        }
+        text.push_str("\n\n请将 DATABASE_URL 改为新的 PostgreSQL 地址后重启机器人。");
+        text
+    }
+}
+
+// 将当前 SQLite 数据库中的会话、消息、白名单、管理员和用户偏好复制到 PostgreSQL
+// 目标库的表结构由 db::connect 创建，会话和消息的 id 由目标库重新分配
+// 每批数据在一个事务中写入，每完成一批调用 on_progress 报告进度
+pub async fn sqlite_to_postgres<F, Fut>(
+    source: &DatabasePool,
+    target_url: &str,
+    mut on_progress: F,
+) -> Result<MigrationReport, Box<dyn Error + Send + Sync>>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let DatabasePool::Sqlite(source) = source else {
+        return Err("当前使用的不是 SQLite 数据库，无需迁移".into());
+    };
+    if !target_url.starts_with("postgres:") {
+        return Err("目标地址必须是 postgres:// 开头的 PostgreSQL 连接地址".into());
+    }
+
+    let DatabasePool::Postgres(target) = db::connect(target_url).await? else {
+        return Err("目标地址不是 PostgreSQL 数据库".into());
+    };
+
+    // 只迁移到空库，避免重复执行时消息被复制两次
+    let existing: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM messages")
+        .fetch_one(&target)
+        .await?;
+    if existing > 0 {
+        return Err(format!(
+            "目标数据库中已有 {} 条消息，为避免重复请迁移到空数据库",
+            existing
+        )
+        .into());
+    }
+
+    let mut report = MigrationReport::default();
+
+    let session_ids = copy_sessions(source, &target, &mut report).await?;
+    on_progress(format!("已迁移 {} 个会话", report.sessions)).await;
+
+    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM messages")
+        .fetch_one(source)
+        .await?;
+    let mut last_id = 0;
+    loop {
+        let rows = sqlx::query(
+            "SELECT id, session_id, role, content, timestamp, source, model, latency_ms,
+                    prompt_tokens, completion_tokens, tg_message_id
+             FROM messages WHERE id > ? ORDER BY id LIMIT ?",
+        )
+        .bind(last_id)
+        .bind(BATCH_SIZE)
+        .fetch_all(source)
+        .await?;
+        let Some(last) = rows.last() else {
+            break;
+        };
+        last_id = last.try_get("id")?;
+
+        let mut tx = target.begin().await?;
+        for row in &rows {
+            let session_id: i64 = row.try_get("session_id")?;
+            let Some(new_session_id) = session_ids.get(&session_id) else {
+                report.skipped_messages += 1;
+                continue;
+            };
+
+            sqlx::query(
+                "INSERT INTO messages (session_id, role, content, timestamp, source, model, latency_ms,
+                                       prompt_tokens, completion_tokens, tg_message_id)
+                 VALUES ($1, $2, $3, COALESCE($4, CURRENT_TIMESTAMP), $5, $6, $7, $8, $9, $10)",
+            )
+            .bind(new_session_id)
+            .bind(row.try_get::<String, _>("role")?)
+            .bind(row.try_get::<String, _>("content")?)
+            .bind(row.try_get::<Option<NaiveDateTime>, _>("timestamp")?)
+            .bind(row.try_get::<Option<String>, _>("source")?)
+            .bind(row.try_get::<Option<String>, _>("model")?)
+            .bind(row.try_get::<Option<i64>, _>("latency_ms")?)
+            .bind(row.try_get::<Option<i64>, _>("prompt_tokens")?)
+            .bind(row.try_get::<Option<i64>, _>("completion_tokens")?)
+            .bind(row.try_get::<Option<i64>, _>("tg_message_id")?)
+            .execute(&mut *tx)
+            .await?;
+            report.messages += 1;
+        }
+        tx.commit().await?;
+
+        on_progress(format!(
+            "已迁移消息 {}/{}",
+            report.messages + report.skipped_messages,
+            total
+        ))
+        .await;
+    }
+
+    copy_whitelist_users(source, &target, &mut report).await?;
+    copy_admins(source, &target, &mut report).await?;
+    copy_user_preferences(source, &target, &mut report).await?;
+
+    log::info!("SQLite 数据已迁移到 PostgreSQL: {:?}", report);
+    Ok(report)
+}
+
+// 复制会话及其设置，返回旧 id 到新 id 的映射
+async fn copy_sessions(
+    source: &Pool<Sqlite>,
+    target: &Pool<Postgres>,
+    report: &mut MigrationReport,
+) -> Result<HashMap<i64, i64>, Box<dyn Error + Send + Sync>> {
+    let rows = sqlx::query(
+        "SELECT id, chat_id, created_at, updated_at, show_timestamps, reply_lang,
+                voice_assistant, privacy_consent, model, temperature, ui_lang,
+                history_limit, bot_name
+         FROM sessions ORDER BY id",
+    )
+    .fetch_all(source)
+    .await?;
+
+    let mut session_ids = HashMap::new();
+    for batch in rows.chunks(BATCH_SIZE as usize) {
+        let mut tx = target.begin().await?;
+        for row in batch {
+            // 目标库中已有同一聊天的会话时沿用该会话
+            let new_id: i64 = sqlx::query_scalar(
+                "INSERT INTO sessions (chat_id, created_at, updated_at, show_timestamps, reply_lang,
+                                       voice_assistant, privacy_consent, model, temperature, ui_lang,
+                                       history_limit, bot_name)
+                 VALUES ($1, COALESCE($2, CURRENT_TIMESTAMP), COALESCE($3, CURRENT_TIMESTAMP),
+                         COALESCE($4, FALSE), $5, COALESCE($6, FALSE), $7, $8, $9, $10, $11, $12)
+                 ON CONFLICT (chat_id) DO UPDATE SET chat_id = EXCLUDED.chat_id
+                 RETURNING id",
+            )
+            .bind(row.try_get::<i64, _>("chat_id")?)
+            .bind(row.try_get::<Option<NaiveDateTime>, _>("created_at")?)
+            .bind(row.try_get::<Option<NaiveDateTime>, _>("updated_at")?)
+            .bind(row.try_get::<Option<bool>, _>("show_timestamps")?)
+            .bind(row.try_get::<Option<String>, _>("reply_lang")?)
+            .bind(row.try_get::<Option<bool>, _>("voice_assistant")?)
+            .bind(row.try_get::<Option<String>, _>("privacy_consent")?)
+            .bind(row.try_get::<Option<String>, _>("model")?)
+            .bind(row.try_get::<Option<f64>, _>("temperature")?)
+            .bind(row.try_get::<Option<String>, _>("ui_lang")?)
+            .bind(row.try_get::<Option<i64>, _>("history_limit")?)
+            .bind(row.try_get::<Option<String>, _>("bot_name")?)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            session_ids.insert(row.try_get("id")?, new_id);
+            report.sessions += 1;
+        }
+        tx.commit().await?;
+    }
+
+    Ok(session_ids)
+}
+
+// 复制白名单，目标库中已存在的用户保持不变
+async fn copy_whitelist_users(
+    source: &Pool<Sqlite>,
+    target: &Pool<Postgres>,
+    report: &mut MigrationReport,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let rows = sqlx::query(
+        "SELECT user_id, username, added_by, added_at, notes, is_active FROM whitelist_users ORDER BY id",
+    )
+    .fetch_all(source)
+    .await?;
+
+    let mut tx = target.begin().await?;
+    for row in &rows {
+        let result = sqlx::query(
+            "INSERT INTO whitelist_users (user_id, username, added_by, added_at, notes, is_active)
+             VALUES ($1, $2, $3, COALESCE($4, CURRENT_TIMESTAMP), $5, $6)
+             ON CONFLICT (user_id) DO NOTHING",
+        )
+        .bind(row.try_get::<i64, _>("user_id")?)
+        .bind(row.try_get::<Option<String>, _>("username")?)
+        .bind(row.try_get::<i64, _>("added_by")?)
+        .bind(row.try_get::<Option<NaiveDateTime>, _>("added_at")?)
+        .bind(row.try_get::<Option<String>, _>("notes")?)
+        .bind(row.try_get::<bool, _>("is_active")?)
+        .execute(&mut *tx)
+        .await?;
+        report.whitelist_users += result.rows_affected();
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+// 复制管理员，ADMIN_USER_IDS 中的超级管理员在连接目标库时已经添加
+async fn copy_admins(
+    source: &Pool<Sqlite>,
+    target: &Pool<Postgres>,
+    report: &mut MigrationReport,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let rows = sqlx::query("SELECT user_id, username, is_super, added_at FROM admins ORDER BY id")
+        .fetch_all(source)
+        .await?;
+
+    let mut tx = target.begin().await?;
+    for row in &rows {
+        let result = sqlx::query(
+            "INSERT INTO admins (user_id, username, is_super, added_at)
+             VALUES ($1, $2, COALESCE($3, FALSE), COALESCE($4, CURRENT_TIMESTAMP))
+             ON CONFLICT (user_id) DO NOTHING",
+        )
+        .bind(row.try_get::<i64, _>("user_id")?)
+        .bind(row.try_get::<Option<String>, _>("username")?)
+        .bind(row.try_get::<Option<bool>, _>("is_super")?)
+        .bind(row.try_get::<Option<NaiveDateTime>, _>("added_at")?)
+        .execute(&mut *tx)
+        .await?;
+        report.admins += result.rows_affected();
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+// 复制用户偏好
+async fn copy_user_preferences(
+    source: &Pool<Sqlite>,
+    target: &Pool<Postgres>,
+    report: &mut MigrationReport,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let rows = sqlx::query("SELECT user_id, model, updated_at FROM user_preferences")
+        .fetch_all(source)
+        .await?;
+
+    let mut tx = target.begin().await?;
+    for row in &rows {
+        let result = sqlx::query(
+            "INSERT INTO user_preferences (user_id, model, updated_at)
+             VALUES ($1, $2, COALESCE($3, CURRENT_TIMESTAMP))
+             ON CONFLICT (user_id) DO NOTHING",
+        )
+        .bind(row.try_get::<i64, _>("user_id")?)
+        .bind(row.try_get::<Option<String>, _>("model")?)
+        .bind(row.try_get::<Option<NaiveDateTime>, _>("updated_at")?)
+        .execute(&mut *tx)
+        .await?;
+        report.user_preferences += result.rows_affected();
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -1,13 +1,16 @@
+use crate::context;
 use crate::db::DatabasePool;
+use crate::error::AppError;
+use crate::openai::Usage;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use sqlx::{Error as SqlxError, Row};
-use std::error::Error;
+use sqlx::Row;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Session {
-    pub id: i32,
-    pub chat_id: u64,
+    pub id: i64,
+    // 群组和频道的聊天ID是负数，必须使用有符号类型
+    pub chat_id: i64,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
 }
@@ -18,6 +21,18 @@ pub struct ChatMessage {
     pub content: String,
 }
 
+// 导出的聊天记录，id 只用于分页读取
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedMessage {
+    #[serde(skip)]
+    pub id: i64,
+    pub role: String,
+    pub content: String,
+    pub timestamp: Option<NaiveDateTime>,
+    // 对应的 Telegram 消息ID，添加该字段之前保存的消息没有
+    pub tg_message_id: Option<i64>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WhitelistUser {
     pub id: i32,
@@ -26,6 +41,8 @@ pub struct WhitelistUser {
     pub added_by: u64,
     pub added_at: NaiveDateTime,
     pub notes: Option<String>,
+    // 被 /removeuser 停用的用户保留记录，但不能再使用机器人
+    pub is_active: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -37,230 +54,1238 @@ pub struct Admin {
     pub added_at: NaiveDateTime,
 }
 
+// 审计日志，记录管理员的敏感操作
+pub struct AuditLog;
+
+// 模型拒绝回答的记录，供管理员查看
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Refusal {
+    pub chat_id: i64,
+    pub model: String,
+    pub prompt: String,
+    pub reply: String,
+    pub created_at: NaiveDateTime,
+}
+
+// 用户级偏好，对该用户的所有聊天生效
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UserPreference {
+    // 用户默认模型，优先级低于聊天通过 /model 选择的模型
+    pub model: Option<String>,
+}
+
+// 单个聊天在统计期间内的 token 用量
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatTokenUsage {
+    pub chat_id: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FinishReasonCount {
+    pub model: String,
+    pub finish_reason: String,
+    pub count: i64,
+}
+
 impl Session {
     // 查找或创建会话
+    // 使用 UPSERT 保证并发创建同一聊天的会话时只会有一条记录
     pub async fn find_or_create_by_chat_id(
         pool: &DatabasePool,
         chat_id: i64,
-    ) -> Result<i32, Box<dyn Error + Send + Sync>> {
+    ) -> Result<i64, AppError> {
         match pool {
             DatabasePool::Sqlite(db) => {
-                // 尝试查找现有会话
-                let session = sqlx::query("SELECT id FROM sessions WHERE chat_id = ?")
-                    .bind(chat_id)
-                    .fetch_optional(db)
-                    .await?;
+                let id: i64 = sqlx::query_scalar(
+                    "INSERT INTO sessions (chat_id) VALUES (?)
+                     ON CONFLICT (chat_id) DO UPDATE SET updated_at = datetime('now','localtime')
+                     RETURNING id",
+                )
+                .bind(chat_id)
+                .fetch_one(db)
+                .await?;
 
-                if let Some(row) = session {
-                    let id: i32 = row.get(0);
+                Ok(id)
+            }
+            DatabasePool::Postgres(db) => {
+                let id: i64 = sqlx::query_scalar(
+                    "INSERT INTO sessions (chat_id) VALUES ($1)
+                     ON CONFLICT (chat_id) DO UPDATE SET updated_at = CURRENT_TIMESTAMP
+                     RETURNING id",
+                )
+                .bind(chat_id)
+                .fetch_one(db)
+                .await?;
 
-                    // 更新最后活动时间
-                    sqlx::query(
-                        "UPDATE sessions SET updated_at = datetime('now','localtime') WHERE id = ?",
-                    )
-                    .bind(id)
-                    .execute(db)
-                    .await?;
+                Ok(id)
+            }
+        }
+    }
+
+    // 按聊天ID读取会话，不存在时返回 None
+    pub async fn get_by_chat_id(
+        pool: &DatabasePool,
+        chat_id: i64,
+    ) -> Result<Option<Session>, AppError> {
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                let session = sqlx::query(
+                    "SELECT id, chat_id, created_at, updated_at FROM sessions WHERE chat_id = ?",
+                )
+                .bind(chat_id)
+                .map(|row: sqlx::sqlite::SqliteRow| Session {
+                    id: row.get(0),
+                    chat_id: row.get(1),
+                    created_at: row.get(2),
+                    updated_at: row.get(3),
+                })
+                .fetch_optional(db)
+                .await?;
+
+                Ok(session)
+            }
+            DatabasePool::Postgres(db) => {
+                let session = sqlx::query(
+                    "SELECT id, chat_id, created_at, updated_at FROM sessions WHERE chat_id = $1",
+                )
+                .bind(chat_id)
+                .map(|row: sqlx::postgres::PgRow| Session {
+                    id: row.get(0),
+                    chat_id: row.get(1),
+                    created_at: row.get(2),
+                    updated_at: row.get(3),
+                })
+                .fetch_optional(db)
+                .await?;
+
+                Ok(session)
+            }
+        }
+    }
+
+    // 获取聊天是否显示语音转录时间戳
+    pub async fn get_show_timestamps(pool: &DatabasePool, chat_id: i64) -> Result<bool, AppError> {
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                let value: Option<Option<i64>> =
+                    sqlx::query_scalar("SELECT show_timestamps FROM sessions WHERE chat_id = ?")
+                        .bind(chat_id)
+                        .fetch_optional(db)
+                        .await?;
 
-                    Ok(id)
-                } else {
-                    // 创建新会话
-                    let result = sqlx::query("INSERT INTO sessions (chat_id) VALUES (?)")
+                Ok(value.flatten().unwrap_or(0) != 0)
+            }
+            DatabasePool::Postgres(db) => {
+                let value: Option<Option<bool>> =
+                    sqlx::query_scalar("SELECT show_timestamps FROM sessions WHERE chat_id = $1")
                         .bind(chat_id)
-                        .execute(db)
+                        .fetch_optional(db)
                         .await?;
 
-                    Ok(result.last_insert_rowid() as i32)
-                }
+                Ok(value.flatten().unwrap_or(false))
+            }
+        }
+    }
+
+    // 设置聊天是否显示语音转录时间戳
+    pub async fn set_show_timestamps(
+        pool: &DatabasePool,
+        chat_id: i64,
+        enabled: bool,
+    ) -> Result<(), AppError> {
+        // 确保会话存在
+        Self::find_or_create_by_chat_id(pool, chat_id).await?;
+
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query("UPDATE sessions SET show_timestamps = ? WHERE chat_id = ?")
+                    .bind(enabled as i32)
+                    .bind(chat_id)
+                    .execute(db)
+                    .await?;
             }
             DatabasePool::Postgres(db) => {
-                // 尝试查找现有会话
-                let session = sqlx::query("SELECT id FROM sessions WHERE chat_id = $1")
+                sqlx::query("UPDATE sessions SET show_timestamps = $1 WHERE chat_id = $2")
+                    .bind(enabled)
                     .bind(chat_id)
-                    .fetch_optional(db)
+                    .execute(db)
                     .await?;
+            }
+        }
 
-                if let Some(row) = session {
-                    let id: i32 = row.get(0);
+        Ok(())
+    }
 
-                    // 更新最后活动时间
-                    sqlx::query("UPDATE sessions SET updated_at = CURRENT_TIMESTAMP WHERE id = $1")
-                        .bind(id)
-                        .execute(db)
+    // 获取聊天是否开启语音助手模式（语音提问时用语音回复）
+    pub async fn get_voice_assistant(pool: &DatabasePool, chat_id: i64) -> Result<bool, AppError> {
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                let value: Option<Option<i64>> =
+                    sqlx::query_scalar("SELECT voice_assistant FROM sessions WHERE chat_id = ?")
+                        .bind(chat_id)
+                        .fetch_optional(db)
                         .await?;
 
-                    Ok(id)
-                } else {
-                    // 创建新会话
-                    let row =
-                        sqlx::query("INSERT INTO sessions (chat_id) VALUES ($1) RETURNING id")
-                            .bind(chat_id)
-                            .fetch_one(db)
-                            .await?;
+                Ok(value.flatten().unwrap_or(0) != 0)
+            }
+            DatabasePool::Postgres(db) => {
+                let value: Option<Option<bool>> =
+                    sqlx::query_scalar("SELECT voice_assistant FROM sessions WHERE chat_id = $1")
+                        .bind(chat_id)
+                        .fetch_optional(db)
+                        .await?;
 
-                    Ok(row.get(0))
-                }
+                Ok(value.flatten().unwrap_or(false))
             }
         }
     }
 
-    // 清除聊天历史
-    pub async fn clear_history_by_chat_id(
+    // 设置聊天是否开启语音助手模式
+    pub async fn set_voice_assistant(
         pool: &DatabasePool,
         chat_id: i64,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        enabled: bool,
+    ) -> Result<(), AppError> {
+        // 确保会话存在
+        Self::find_or_create_by_chat_id(pool, chat_id).await?;
+
         match pool {
             DatabasePool::Sqlite(db) => {
-                // 获取所有相关会话
-                let sessions = sqlx::query("SELECT id FROM sessions WHERE chat_id = ?")
-                    .bind(chat_id as i64)
-                    .fetch_all(db)
+                sqlx::query("UPDATE sessions SET voice_assistant = ? WHERE chat_id = ?")
+                    .bind(enabled as i32)
+                    .bind(chat_id)
+                    .execute(db)
+                    .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query("UPDATE sessions SET voice_assistant = $1 WHERE chat_id = $2")
+                    .bind(enabled)
+                    .bind(chat_id)
+                    .execute(db)
                     .await?;
+            }
+        }
 
-                // 删除所有相关消息
-                for row in &sessions {
-                    let id: i32 = row.get(0);
-                    sqlx::query("DELETE FROM messages WHERE session_id = ?")
-                        .bind(id)
-                        .execute(db)
-                        .await?;
-                }
+        Ok(())
+    }
+
+    // 获取聊天的隐私同意状态（None 表示还没有询问过）
+    pub async fn get_privacy_consent(
+        pool: &DatabasePool,
+        chat_id: i64,
+    ) -> Result<Option<String>, AppError> {
+        let value: Option<Option<String>> = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_scalar("SELECT privacy_consent FROM sessions WHERE chat_id = ?")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_scalar("SELECT privacy_consent FROM sessions WHERE chat_id = $1")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
+            }
+        };
+
+        Ok(value.flatten())
+    }
+
+    // 设置聊天的隐私同意状态
+    pub async fn set_privacy_consent(
+        pool: &DatabasePool,
+        chat_id: i64,
+        consent: &str,
+    ) -> Result<(), AppError> {
+        // 确保会话存在
+        Self::find_or_create_by_chat_id(pool, chat_id).await?;
 
-                // 删除所有相关会话
-                sqlx::query("DELETE FROM sessions WHERE chat_id = ?")
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query("UPDATE sessions SET privacy_consent = ? WHERE chat_id = ?")
+                    .bind(consent)
                     .bind(chat_id)
                     .execute(db)
                     .await?;
-
-                Ok(())
             }
             DatabasePool::Postgres(db) => {
-                // 获取所有相关会话
-                let sessions = sqlx::query("SELECT id FROM sessions WHERE chat_id = $1")
+                sqlx::query("UPDATE sessions SET privacy_consent = $1 WHERE chat_id = $2")
+                    .bind(consent)
                     .bind(chat_id)
-                    .fetch_all(db)
+                    .execute(db)
                     .await?;
+            }
+        }
 
-                // 删除所有相关消息
-                for row in &sessions {
-                    let id: i32 = row.get(0);
-                    sqlx::query("DELETE FROM messages WHERE session_id = $1")
-                        .bind(id)
-                        .execute(db)
-                        .await?;
-                }
+        Ok(())
+    }
+
+    // 获取聊天的回复语言设置（None 表示使用全局默认）
+    pub async fn get_reply_lang(
+        pool: &DatabasePool,
+        chat_id: i64,
+    ) -> Result<Option<String>, AppError> {
+        let value: Option<Option<String>> = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_scalar("SELECT reply_lang FROM sessions WHERE chat_id = ?")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_scalar("SELECT reply_lang FROM sessions WHERE chat_id = $1")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
+            }
+        };
+
+        Ok(value.flatten())
+    }
+
+    // 设置聊天的回复语言（None 表示恢复全局默认）
+    pub async fn set_reply_lang(
+        pool: &DatabasePool,
+        chat_id: i64,
+        lang: Option<&str>,
+    ) -> Result<(), AppError> {
+        // 确保会话存在
+        Self::find_or_create_by_chat_id(pool, chat_id).await?;
 
-                // 删除所有相关会话
-                sqlx::query("DELETE FROM sessions WHERE chat_id = $1")
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query("UPDATE sessions SET reply_lang = ? WHERE chat_id = ?")
+                    .bind(lang)
+                    .bind(chat_id)
+                    .execute(db)
+                    .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query("UPDATE sessions SET reply_lang = $1 WHERE chat_id = $2")
+                    .bind(lang)
                     .bind(chat_id)
                     .execute(db)
                     .await?;
-
-                Ok(())
             }
         }
+
+        Ok(())
     }
-}
 
-pub struct Message;
+    // 获取聊天的界面语言设置（None 表示使用全局默认）
+    pub async fn get_ui_lang(
+        pool: &DatabasePool,
+        chat_id: i64,
+    ) -> Result<Option<String>, AppError> {
+        let value: Option<Option<String>> = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_scalar("SELECT ui_lang FROM sessions WHERE chat_id = ?")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_scalar("SELECT ui_lang FROM sessions WHERE chat_id = $1")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
+            }
+        };
 
-impl Message {
-    // 创建新消息
-    pub async fn create(
+        Ok(value.flatten())
+    }
+
+    // 设置聊天的界面语言
+    pub async fn set_ui_lang(
         pool: &DatabasePool,
-        session_id: i32,
-        role: &str,
-        content: &str,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        chat_id: i64,
+        lang: &str,
+    ) -> Result<(), AppError> {
+        // 确保会话存在
+        Self::find_or_create_by_chat_id(pool, chat_id).await?;
+
         match pool {
             DatabasePool::Sqlite(db) => {
-                sqlx::query("INSERT INTO messages (session_id, role, content) VALUES (?, ?, ?)")
-                    .bind(session_id)
-                    .bind(role)
-                    .bind(content)
+                sqlx::query("UPDATE sessions SET ui_lang = ? WHERE chat_id = ?")
+                    .bind(lang)
+                    .bind(chat_id)
                     .execute(db)
                     .await?;
-
-                Ok(())
             }
             DatabasePool::Postgres(db) => {
-                sqlx::query("INSERT INTO messages (session_id, role, content) VALUES ($1, $2, $3)")
-                    .bind(session_id)
-                    .bind(role)
-                    .bind(content)
+                sqlx::query("UPDATE sessions SET ui_lang = $1 WHERE chat_id = $2")
+                    .bind(lang)
+                    .bind(chat_id)
                     .execute(db)
                     .await?;
+            }
+        }
 
-                Ok(())
+        Ok(())
+    }
+
+    // 获取聊天选择的模型（None 表示使用默认模型）
+    pub async fn get_model(pool: &DatabasePool, chat_id: i64) -> Result<Option<String>, AppError> {
+        let value: Option<Option<String>> = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_scalar("SELECT model FROM sessions WHERE chat_id = ?")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_scalar("SELECT model FROM sessions WHERE chat_id = $1")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
+            }
+        };
+
+        Ok(value.flatten())
+    }
+
+    // 设置聊天使用的模型（None 表示恢复默认模型）
+    pub async fn set_model(
+        pool: &DatabasePool,
+        chat_id: i64,
+        model: Option<&str>,
+    ) -> Result<(), AppError> {
+        // 确保会话存在
+        Self::find_or_create_by_chat_id(pool, chat_id).await?;
+
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query("UPDATE sessions SET model = ? WHERE chat_id = ?")
+                    .bind(model)
+                    .bind(chat_id)
+                    .execute(db)
+                    .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query("UPDATE sessions SET model = $1 WHERE chat_id = $2")
+                    .bind(model)
+                    .bind(chat_id)
+                    .execute(db)
+                    .await?;
             }
         }
+
+        Ok(())
     }
 
-    // 获取最近消息
-    pub async fn get_recent_messages(
+    // 获取聊天设置的 temperature（None 表示使用默认值）
+    pub async fn get_temperature(
         pool: &DatabasePool,
-        session_id: i32,
-        limit: i64,
-    ) -> Result<Vec<ChatMessage>, Box<dyn Error + Send + Sync>> {
-        let messages = match pool {
+        chat_id: i64,
+    ) -> Result<Option<f64>, AppError> {
+        let value: Option<Option<f64>> = match pool {
             DatabasePool::Sqlite(db) => {
-                sqlx::query_as::<_, (String, String)>(
-                    "SELECT role, content FROM messages 
-                     WHERE session_id = ? 
-                     ORDER BY timestamp ASC 
-                     LIMIT ?",
-                )
-                .bind(session_id)
-                .bind(limit)
-                .fetch_all(db)
-                .await?
+                sqlx::query_scalar("SELECT temperature FROM sessions WHERE chat_id = ?")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
             }
             DatabasePool::Postgres(db) => {
-                sqlx::query_as::<_, (String, String)>(
-                    "SELECT role, content FROM messages 
-                     WHERE session_id = $1 
-                     ORDER BY timestamp ASC 
-                     LIMIT $2",
-                )
-                .bind(session_id)
-                .bind(limit)
-                .fetch_all(db)
-                .await?
+                sqlx::query_scalar("SELECT temperature FROM sessions WHERE chat_id = $1")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
             }
         };
 
-        let mut chat_messages = Vec::new();
-        for (role, content) in messages {
-            chat_messages.push(ChatMessage { role, content });
-        }
+        Ok(value.flatten())
+    }
 
-        Ok(chat_messages)
+    // 设置聊天的 temperature（None 表示恢复默认值）
+    pub async fn set_temperature(
+        pool: &DatabasePool,
+        chat_id: i64,
+        temperature: Option<f64>,
+    ) -> Result<(), AppError> {
+        // 确保会话存在
+        Self::find_or_create_by_chat_id(pool, chat_id).await?;
+
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query("UPDATE sessions SET temperature = ? WHERE chat_id = ?")
+                    .bind(temperature)
+                    .bind(chat_id)
+                    .execute(db)
+                    .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query("UPDATE sessions SET temperature = $1 WHERE chat_id = $2")
+                    .bind(temperature)
+                    .bind(chat_id)
+                    .execute(db)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // 获取聊天设置的历史消息条数上限（None 表示使用 HISTORY_LIMIT）
+    pub async fn get_history_limit(
+        pool: &DatabasePool,
+        chat_id: i64,
+    ) -> Result<Option<i64>, AppError> {
+        let value: Option<Option<i64>> = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_scalar("SELECT history_limit FROM sessions WHERE chat_id = ?")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_scalar("SELECT history_limit FROM sessions WHERE chat_id = $1")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
+            }
+        };
+
+        Ok(value.flatten())
+    }
+
+    // 设置聊天的历史消息条数上限（None 表示恢复默认值）
+    pub async fn set_history_limit(
+        pool: &DatabasePool,
+        chat_id: i64,
+        limit: Option<i64>,
+    ) -> Result<(), AppError> {
+        // 确保会话存在
+        Self::find_or_create_by_chat_id(pool, chat_id).await?;
+
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query("UPDATE sessions SET history_limit = ? WHERE chat_id = ?")
+                    .bind(limit)
+                    .bind(chat_id)
+                    .execute(db)
+                    .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query("UPDATE sessions SET history_limit = $1 WHERE chat_id = $2")
+                    .bind(limit)
+                    .bind(chat_id)
+                    .execute(db)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // 获取聊天为助手设置的名字（None 表示未设置）
+    pub async fn get_bot_name(
+        pool: &DatabasePool,
+        chat_id: i64,
+    ) -> Result<Option<String>, AppError> {
+        let value: Option<Option<String>> = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_scalar("SELECT bot_name FROM sessions WHERE chat_id = ?")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_scalar("SELECT bot_name FROM sessions WHERE chat_id = $1")
+                    .bind(chat_id)
+                    .fetch_optional(db)
+                    .await?
+            }
+        };
+
+        Ok(value.flatten())
+    }
+
+    // 设置聊天的助手名字（None 表示清除）
+    pub async fn set_bot_name(
+        pool: &DatabasePool,
+        chat_id: i64,
+        name: Option<&str>,
+    ) -> Result<(), AppError> {
+        // 确保会话存在
+        Self::find_or_create_by_chat_id(pool, chat_id).await?;
+
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query("UPDATE sessions SET bot_name = ? WHERE chat_id = ?")
+                    .bind(name)
+                    .bind(chat_id)
+                    .execute(db)
+                    .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query("UPDATE sessions SET bot_name = $1 WHERE chat_id = $2")
+                    .bind(name)
+                    .bind(chat_id)
+                    .execute(db)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // 获取所有有会话记录的聊天ID，供 /broadcast 使用
+    pub async fn get_all_chat_ids(pool: &DatabasePool) -> Result<Vec<i64>, AppError> {
+        let chat_ids = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_scalar("SELECT DISTINCT chat_id FROM sessions ORDER BY chat_id")
+                    .fetch_all(db)
+                    .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_scalar("SELECT DISTINCT chat_id FROM sessions ORDER BY chat_id")
+                    .fetch_all(db)
+                    .await?
+            }
+        };
+
+        Ok(chat_ids)
+    }
+
+    // 清除聊天历史：只删除聊天的消息，保留会话本身和保存在会话上的各项设置
+    // 一条语句完成删除，失败时不会留下删除了一半的历史
+    pub async fn clear_history_by_chat_id(
+        pool: &DatabasePool,
+        chat_id: i64,
+    ) -> Result<(), AppError> {
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query(
+                    "DELETE FROM messages WHERE session_id IN (SELECT id FROM sessions WHERE chat_id = ?)",
+                )
+                .bind(chat_id)
+                .execute(db)
+                .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query(
+                    "DELETE FROM messages WHERE session_id IN (SELECT id FROM sessions WHERE chat_id = $1)",
+                )
+                .bind(chat_id)
+                .execute(db)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Message;
+
+// 消息的统计信息：用户消息记录来源和对应的 Telegram 消息ID，AI 回复记录模型和耗时
+#[derive(Debug, Default)]
+pub struct MessageMeta<'a> {
+    pub source: Option<&'a str>,
+    pub model: Option<&'a str>,
+    pub latency_ms: Option<i64>,
+    pub tg_message_id: Option<i64>,
+}
+
+impl Message {
+    // 创建新消息
+    pub async fn create(
+        pool: &DatabasePool,
+        session_id: i64,
+        role: &str,
+        content: &str,
+    ) -> Result<(), AppError> {
+        Self::create_with_tg_id(pool, session_id, role, content, None).await
+    }
+
+    // 创建新消息并记录对应的 Telegram 消息ID（用户消息为用户发送的消息，AI 回复为机器人发出的消息）
+    pub async fn create_with_tg_id(
+        pool: &DatabasePool,
+        session_id: i64,
+        role: &str,
+        content: &str,
+        tg_message_id: Option<i64>,
+    ) -> Result<(), AppError> {
+        let meta = MessageMeta {
+            tg_message_id,
+            ..Default::default()
+        };
+        Self::create_with_meta(pool, session_id, role, content, &meta).await
+    }
+
+    // 创建带统计信息的新消息
+    pub async fn create_with_meta(
+        pool: &DatabasePool,
+        session_id: i64,
+        role: &str,
+        content: &str,
+        meta: &MessageMeta<'_>,
+    ) -> Result<(), AppError> {
+        Self::create_with_usage(pool, session_id, role, content, meta, None).await
+    }
+
+    // 创建消息并记录本次请求的 token 用量（只有 AI 回复带有用量）
+    pub async fn create_with_usage(
+        pool: &DatabasePool,
+        session_id: i64,
+        role: &str,
+        content: &str,
+        meta: &MessageMeta<'_>,
+        usage: Option<&Usage>,
+    ) -> Result<(), AppError> {
+        let prompt_tokens = usage.map(|usage| usage.prompt_tokens);
+        let completion_tokens = usage.map(|usage| usage.completion_tokens);
+
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query(
+                    "INSERT INTO messages (session_id, role, content, source, model, latency_ms, prompt_tokens, completion_tokens, tg_message_id)
+                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                )
+                .bind(session_id)
+                .bind(role)
+                .bind(content)
+                .bind(meta.source)
+                .bind(meta.model)
+                .bind(meta.latency_ms)
+                .bind(prompt_tokens)
+                .bind(completion_tokens)
+                .bind(meta.tg_message_id)
+                .execute(db)
+                .await?;
+
+                Ok(())
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query(
+                    "INSERT INTO messages (session_id, role, content, source, model, latency_ms, prompt_tokens, completion_tokens, tg_message_id)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                )
+                .bind(session_id)
+                .bind(role)
+                .bind(content)
+                .bind(meta.source)
+                .bind(meta.model)
+                .bind(meta.latency_ms)
+                .bind(prompt_tokens)
+                .bind(completion_tokens)
+                .bind(meta.tg_message_id)
+                .execute(db)
+                .await?;
+
+                Ok(())
+            }
+        }
+    }
+
+    // 按 id 顺序分页读取聊天的全部消息：返回 id 大于 after_id 的最多 limit 条
+    // 导出时逐页读取，避免一次把整个历史加载到内存
+    pub async fn get_all_messages_by_chat_id(
+        pool: &DatabasePool,
+        chat_id: i64,
+        after_id: i64,
+        limit: i64,
+    ) -> Result<Vec<ExportedMessage>, AppError> {
+        let rows = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_as::<_, (i64, String, String, Option<NaiveDateTime>, Option<i64>)>(
+                    "SELECT m.id, m.role, m.content, m.timestamp, m.tg_message_id
+                     FROM messages m JOIN sessions s ON s.id = m.session_id
+                     WHERE s.chat_id = ? AND m.id > ?
+                     ORDER BY m.id ASC
+                     LIMIT ?",
+                )
+                .bind(chat_id)
+                .bind(after_id)
+                .bind(limit)
+                .fetch_all(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_as::<_, (i64, String, String, Option<NaiveDateTime>, Option<i64>)>(
+                    "SELECT m.id, m.role, m.content, m.timestamp, m.tg_message_id
+                     FROM messages m JOIN sessions s ON s.id = m.session_id
+                     WHERE s.chat_id = $1 AND m.id > $2
+                     ORDER BY m.id ASC
+                     LIMIT $3",
+                )
+                .bind(chat_id)
+                .bind(after_id)
+                .bind(limit)
+                .fetch_all(db)
+                .await?
+            }
+        };
+
+        Ok(rows
+            .into_iter()
+            .map(
+                |(id, role, content, timestamp, tg_message_id)| ExportedMessage {
+                    id,
+                    role,
+                    content,
+                    timestamp,
+                    tg_message_id,
+                },
+            )
+            .collect())
+    }
+
+    // 删除会话中最新的一条消息，仅当它是 AI 回复时删除，返回是否删除
+    // 上一次回复失败没有保存时，不会误删更早一轮的回复
+    pub async fn delete_last_assistant(
+        pool: &DatabasePool,
+        session_id: i64,
+    ) -> Result<bool, AppError> {
+        let result = match pool {
+            DatabasePool::Sqlite(db) => sqlx::query(
+                "DELETE FROM messages
+                     WHERE id = (SELECT MAX(id) FROM messages WHERE session_id = ?)
+                       AND role = 'assistant'",
+            )
+            .bind(session_id)
+            .execute(db)
+            .await?
+            .rows_affected(),
+            DatabasePool::Postgres(db) => sqlx::query(
+                "DELETE FROM messages
+                     WHERE id = (SELECT MAX(id) FROM messages WHERE session_id = $1)
+                       AND role = 'assistant'",
+            )
+            .bind(session_id)
+            .execute(db)
+            .await?
+            .rows_affected(),
+        };
+
+        Ok(result > 0)
+    }
+
+    // 获取会话中最新的一条用户消息，返回消息 id、内容和对应的 Telegram 消息ID
+    pub async fn get_last_user_message(
+        pool: &DatabasePool,
+        session_id: i64,
+    ) -> Result<Option<(i64, String, Option<i64>)>, AppError> {
+        let message = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_as::<_, (i64, String, Option<i64>)>(
+                    "SELECT id, content, tg_message_id FROM messages
+                     WHERE session_id = ? AND role = 'user'
+                     ORDER BY id DESC
+                     LIMIT 1",
+                )
+                .bind(session_id)
+                .fetch_optional(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_as::<_, (i64, String, Option<i64>)>(
+                    "SELECT id, content, tg_message_id FROM messages
+                     WHERE session_id = $1 AND role = 'user'
+                     ORDER BY id DESC
+                     LIMIT 1",
+                )
+                .bind(session_id)
+                .fetch_optional(db)
+                .await?
+            }
+        };
+
+        Ok(message)
+    }
+
+    // 用户编辑了 Telegram 消息后更新保存的内容，只更新会话中最新的一条用户消息
+    // 被编辑的不是最新一轮的提问时不做修改，返回是否更新
+    pub async fn update_content(
+        pool: &DatabasePool,
+        session_id: i64,
+        tg_message_id: i64,
+        content: &str,
+    ) -> Result<bool, AppError> {
+        let result = match pool {
+            DatabasePool::Sqlite(db) => sqlx::query(
+                "UPDATE messages SET content = ?
+                     WHERE id = (SELECT MAX(id) FROM messages WHERE session_id = ? AND role = 'user')
+                       AND tg_message_id = ?",
+            )
+            .bind(content)
+            .bind(session_id)
+            .bind(tg_message_id)
+            .execute(db)
+            .await?
+            .rows_affected(),
+            DatabasePool::Postgres(db) => sqlx::query(
+                "UPDATE messages SET content = $1
+                     WHERE id = (SELECT MAX(id) FROM messages WHERE session_id = $2 AND role = 'user')
+                       AND tg_message_id = $3",
+            )
+            .bind(content)
+            .bind(session_id)
+            .bind(tg_message_id)
+            .execute(db)
+            .await?
+            .rows_affected(),
+        };
+
+        Ok(result > 0)
+    }
+
+    // 删除会话中最新的 n 条消息，消息不足 n 条时全部删除，返回实际删除的条数
+    pub async fn delete_last_n(
+        pool: &DatabasePool,
+        session_id: i64,
+        n: i64,
+    ) -> Result<u64, AppError> {
+        let deleted = match pool {
+            DatabasePool::Sqlite(db) => sqlx::query(
+                "DELETE FROM messages WHERE id IN (
+                     SELECT id FROM messages WHERE session_id = ? ORDER BY id DESC LIMIT ?
+                 )",
+            )
+            .bind(session_id)
+            .bind(n)
+            .execute(db)
+            .await?
+            .rows_affected(),
+            DatabasePool::Postgres(db) => sqlx::query(
+                "DELETE FROM messages WHERE id IN (
+                     SELECT id FROM messages WHERE session_id = $1 ORDER BY id DESC LIMIT $2
+                 )",
+            )
+            .bind(session_id)
+            .bind(n)
+            .execute(db)
+            .await?
+            .rows_affected(),
+        };
+
+        Ok(deleted)
+    }
+
+    // 删除指定的消息
+    pub async fn delete(pool: &DatabasePool, id: i64) -> Result<(), AppError> {
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query("DELETE FROM messages WHERE id = ?")
+                    .bind(id)
+                    .execute(db)
+                    .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query("DELETE FROM messages WHERE id = $1")
+                    .bind(id)
+                    .execute(db)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    // 获取最近的 limit 条消息（按时间顺序）
+    pub async fn get_recent_messages(
+        pool: &DatabasePool,
+        session_id: i64,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let messages = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_as::<_, (String, String)>(
+                    "SELECT role, content FROM messages
+                     WHERE session_id = ?
+                     ORDER BY id DESC
+                     LIMIT ?",
+                )
+                .bind(session_id)
+                .bind(limit)
+                .fetch_all(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_as::<_, (String, String)>(
+                    "SELECT role, content FROM messages
+                     WHERE session_id = $1
+                     ORDER BY id DESC
+                     LIMIT $2",
+                )
+                .bind(session_id)
+                .bind(limit)
+                .fetch_all(db)
+                .await?
+            }
+        };
+
+        // 查询按最新在前排序，返回前恢复为时间顺序
+        let mut chat_messages = Vec::new();
+        for (role, content) in messages.into_iter().rev() {
+            chat_messages.push(ChatMessage { role, content });
+        }
+
+        Ok(chat_messages)
+    }
+
+    // 获取不超过 token 预算的最近消息（按时间顺序），最多查询 max_messages 条
+    // 最新的一条消息总是保留，即使它本身就超出预算
+    pub async fn get_messages_within_token_budget(
+        pool: &DatabasePool,
+        session_id: i64,
+        token_budget: usize,
+        max_messages: i64,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let mut messages = Self::get_recent_messages(pool, session_id, max_messages).await?;
+
+        let mut used = 0;
+        let mut keep = 0;
+        for message in messages.iter().rev() {
+            let tokens = context::estimate_message_tokens(&message.content);
+            if keep > 0 && used + tokens > token_budget {
+                break;
+            }
+            used += tokens;
+            keep += 1;
+        }
+
+        Ok(messages.split_off(messages.len() - keep))
+    }
+
+    // 按机器人回复的 Telegram 消息ID 查找那一轮对话：该回复和它之前最近的一条用户消息（按时间顺序）
+    // 找不到对应的回复时返回空列表
+    pub async fn get_exchange_by_tg_id(
+        pool: &DatabasePool,
+        session_id: i64,
+        tg_message_id: i64,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let reply: Option<(i64, String)> = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_as(
+                    "SELECT id, content FROM messages
+                     WHERE session_id = ? AND role = 'assistant' AND tg_message_id = ?
+                     ORDER BY id DESC
+                     LIMIT 1",
+                )
+                .bind(session_id)
+                .bind(tg_message_id)
+                .fetch_optional(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_as(
+                    "SELECT id, content FROM messages
+                     WHERE session_id = $1 AND role = 'assistant' AND tg_message_id = $2
+                     ORDER BY id DESC
+                     LIMIT 1",
+                )
+                .bind(session_id)
+                .bind(tg_message_id)
+                .fetch_optional(db)
+                .await?
+            }
+        };
+        let Some((reply_id, reply)) = reply else {
+            return Ok(Vec::new());
+        };
+
+        let question: Option<String> = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_scalar(
+                    "SELECT content FROM messages
+                     WHERE session_id = ? AND role = 'user' AND id < ?
+                     ORDER BY id DESC
+                     LIMIT 1",
+                )
+                .bind(session_id)
+                .bind(reply_id)
+                .fetch_optional(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_scalar(
+                    "SELECT content FROM messages
+                     WHERE session_id = $1 AND role = 'user' AND id < $2
+                     ORDER BY id DESC
+                     LIMIT 1",
+                )
+                .bind(session_id)
+                .bind(reply_id)
+                .fetch_optional(db)
+                .await?
+            }
+        };
+
+        let mut exchange = Vec::new();
+        if let Some(question) = question {
+            exchange.push(ChatMessage {
+                role: "user".to_string(),
+                content: question,
+            });
+        }
+        exchange.push(ChatMessage {
+            role: "assistant".to_string(),
+            content: reply,
+        });
+        Ok(exchange)
+    }
+
+    // 获取会话的全部消息（按时间顺序），同时返回消息 id
+    pub async fn get_session_messages(
+        pool: &DatabasePool,
+        session_id: i64,
+    ) -> Result<Vec<(i64, ChatMessage)>, AppError> {
+        let rows = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_as::<_, (i64, String, String)>(
+                    "SELECT id, role, content FROM messages
+                     WHERE session_id = ?
+                     ORDER BY timestamp ASC, id ASC",
+                )
+                .bind(session_id)
+                .fetch_all(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_as::<_, (i64, String, String)>(
+                    "SELECT id, role, content FROM messages
+                     WHERE session_id = $1
+                     ORDER BY timestamp ASC, id ASC",
+                )
+                .bind(session_id)
+                .fetch_all(db)
+                .await?
+            }
+        };
+
+        Ok(rows
+            .into_iter()
+            .map(|(id, role, content)| (id, ChatMessage { role, content }))
+            .collect())
+    }
+
+    // 用一条摘要替换会话的全部历史：在同一个事务中删除所有消息并插入摘要，返回删除的消息数
+    pub async fn replace_history_with_summary(
+        pool: &DatabasePool,
+        session_id: i64,
+        summary: &str,
+    ) -> Result<u64, AppError> {
+        let deleted = match pool {
+            DatabasePool::Sqlite(db) => {
+                let mut tx = db.begin().await?;
+                let deleted = sqlx::query("DELETE FROM messages WHERE session_id = ?")
+                    .bind(session_id)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+                sqlx::query(
+                    "INSERT INTO messages (session_id, role, content, source) VALUES (?, 'system', ?, 'summary')",
+                )
+                .bind(session_id)
+                .bind(summary)
+                .execute(&mut *tx)
+                .await?;
+                tx.commit().await?;
+                deleted
+            }
+            DatabasePool::Postgres(db) => {
+                let mut tx = db.begin().await?;
+                let deleted = sqlx::query("DELETE FROM messages WHERE session_id = $1")
+                    .bind(session_id)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+                sqlx::query(
+                    "INSERT INTO messages (session_id, role, content, source) VALUES ($1, 'system', $2, 'summary')",
+                )
+                .bind(session_id)
+                .bind(summary)
+                .execute(&mut *tx)
+                .await?;
+                tx.commit().await?;
+                deleted
+            }
+        };
+
+        Ok(deleted)
+    }
+
+    // 将一段消息压缩为一条摘要：第一条消息改写为摘要（保留原时间，排在较新的消息之前），其余删除
+    pub async fn collapse_into_summary(
+        pool: &DatabasePool,
+        session_id: i64,
+        ids: &[i64],
+        summary: &str,
+    ) -> Result<(), AppError> {
+        let Some((first_id, rest)) = ids.split_first() else {
+            return Ok(());
+        };
+
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                let mut tx = db.begin().await?;
+                sqlx::query(
+                    "UPDATE messages SET role = 'system', content = ? WHERE id = ? AND session_id = ?",
+                )
+                .bind(summary)
+                .bind(first_id)
+                .bind(session_id)
+                .execute(&mut *tx)
+                .await?;
+                for id in rest {
+                    sqlx::query("DELETE FROM messages WHERE id = ? AND session_id = ?")
+                        .bind(id)
+                        .bind(session_id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                tx.commit().await?;
+            }
+            DatabasePool::Postgres(db) => {
+                let mut tx = db.begin().await?;
+                sqlx::query(
+                    "UPDATE messages SET role = 'system', content = $1 WHERE id = $2 AND session_id = $3",
+                )
+                .bind(summary)
+                .bind(first_id)
+                .bind(session_id)
+                .execute(&mut *tx)
+                .await?;
+                for id in rest {
+                    sqlx::query("DELETE FROM messages WHERE id = $1 AND session_id = $2")
+                        .bind(id)
+                        .bind(session_id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                tx.commit().await?;
+            }
+        }
+
+        Ok(())
     }
 }
 
 impl WhitelistUser {
-    // 检查用户是否在白名单中
-    pub async fn is_user_whitelisted(
-        pool: &DatabasePool,
-        user_id: u64,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    // 检查用户是否在白名单中，已停用的用户不算
+    pub async fn is_user_whitelisted(pool: &DatabasePool, user_id: u64) -> Result<bool, AppError> {
         match pool {
             DatabasePool::Sqlite(db) => {
-                let result =
-                    sqlx::query("SELECT COUNT(*) as count FROM whitelist_users WHERE user_id = ?")
-                        .bind(user_id as i64)
-                        .fetch_one(db)
-                        .await?;
+                let result = sqlx::query(
+                    "SELECT COUNT(*) as count FROM whitelist_users WHERE user_id = ? AND is_active = 1",
+                )
+                .bind(user_id as i64)
+                .fetch_one(db)
+                .await?;
 
                 let count: u64 = result.get(0);
                 Ok(count > 0)
             }
             DatabasePool::Postgres(db) => {
-                let result =
-                    sqlx::query("SELECT COUNT(*) as count FROM whitelist_users WHERE user_id = $1")
-                        .bind(user_id as i64)
-                        .fetch_one(db)
-                        .await?;
+                let result = sqlx::query(
+                    "SELECT COUNT(*) as count FROM whitelist_users WHERE user_id = $1 AND is_active = TRUE",
+                )
+                .bind(user_id as i64)
+                .fetch_one(db)
+                .await?;
 
                 let count: i64 = result.get(0);
                 Ok(count > 0)
@@ -275,7 +1300,7 @@ impl WhitelistUser {
         username: Option<&str>,
         added_by: u64,
         notes: Option<&str>,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    ) -> Result<(), AppError> {
         match pool {
             DatabasePool::Sqlite(db) => {
                 sqlx::query(
@@ -306,11 +1331,74 @@ impl WhitelistUser {
         }
     }
 
-    // 从白名单移除用户
-    pub async fn remove_user(
+    // 停用白名单用户，保留记录；用户不存在或已停用时返回 false
+    pub async fn deactivate(pool: &DatabasePool, user_id: u64) -> Result<bool, AppError> {
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                let result = sqlx::query(
+                    "UPDATE whitelist_users SET is_active = 0 WHERE user_id = ? AND is_active = 1",
+                )
+                .bind(user_id as i64)
+                .execute(db)
+                .await?;
+
+                Ok(result.rows_affected() > 0)
+            }
+            DatabasePool::Postgres(db) => {
+                let result = sqlx::query(
+                    "UPDATE whitelist_users SET is_active = FALSE WHERE user_id = $1 AND is_active = TRUE",
+                )
+                .bind(user_id as i64)
+                .execute(db)
+                .await?;
+
+                Ok(result.rows_affected() > 0)
+            }
+        }
+    }
+
+    // 重新启用已停用的白名单用户，提供了用户名或备注时一并更新，未提供的保留原来的值
+    // 用户不存在或本来就是启用状态时返回 false
+    pub async fn reactivate(
         pool: &DatabasePool,
         user_id: u64,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        username: Option<&str>,
+        notes: Option<&str>,
+    ) -> Result<bool, AppError> {
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                let result = sqlx::query(
+                    "UPDATE whitelist_users
+                     SET is_active = 1, username = COALESCE(?, username), notes = COALESCE(?, notes)
+                     WHERE user_id = ? AND is_active = 0",
+                )
+                .bind(username)
+                .bind(notes)
+                .bind(user_id as i64)
+                .execute(db)
+                .await?;
+
+                Ok(result.rows_affected() > 0)
+            }
+            DatabasePool::Postgres(db) => {
+                let result = sqlx::query(
+                    "UPDATE whitelist_users
+                     SET is_active = TRUE, username = COALESCE($1, username), notes = COALESCE($2, notes)
+                     WHERE user_id = $3 AND is_active = FALSE",
+                )
+                .bind(username)
+                .bind(notes)
+                .bind(user_id as i64)
+                .execute(db)
+                .await?;
+
+                Ok(result.rows_affected() > 0)
+            }
+        }
+    }
+
+    // 从白名单彻底删除用户（包括已停用的用户），不保留记录
+    pub async fn remove_user(pool: &DatabasePool, user_id: u64) -> Result<bool, AppError> {
         match pool {
             DatabasePool::Sqlite(db) => {
                 let result = sqlx::query("DELETE FROM whitelist_users WHERE user_id = ?")
@@ -331,15 +1419,19 @@ impl WhitelistUser {
         }
     }
 
-    // 获取所有白名单用户
+    // 获取白名单用户，active 为 None 时返回全部用户，否则只返回启用或停用的用户
     pub async fn get_all_users(
         pool: &DatabasePool,
-    ) -> Result<Vec<WhitelistUser>, Box<dyn Error + Send + Sync>> {
+        active: Option<bool>,
+    ) -> Result<Vec<WhitelistUser>, AppError> {
         match pool {
             DatabasePool::Sqlite(db) => {
                 let rows: Vec<WhitelistUser> = sqlx::query(
-                    "SELECT id, user_id, username, added_by, added_at, notes FROM whitelist_users ORDER BY added_at DESC"
+                    "SELECT id, user_id, username, added_by, added_at, notes, is_active FROM whitelist_users
+                     WHERE ? IS NULL OR is_active = ? ORDER BY added_at DESC"
                 )
+                .bind(active)
+                .bind(active)
                 .map(|row: sqlx::sqlite::SqliteRow| {
                     WhitelistUser {
                         id: row.get(0),
@@ -348,6 +1440,7 @@ impl WhitelistUser {
                         added_by: row.get::<i64, _>(3) as u64,
                         added_at: row.get(4),
                         notes: row.get(5),
+                        is_active: row.get(6),
                     }
                 })
                 .fetch_all(db)
@@ -357,8 +1450,10 @@ impl WhitelistUser {
             }
             DatabasePool::Postgres(db) => {
                 let rows: Vec<WhitelistUser> = sqlx::query(
-                    "SELECT id, user_id, username, added_by, added_at, notes FROM whitelist_users ORDER BY added_at DESC"
+                    "SELECT id, user_id, username, added_by, added_at, notes, is_active FROM whitelist_users
+                     WHERE $1::BOOLEAN IS NULL OR is_active = $1 ORDER BY added_at DESC"
                 )
+                .bind(active)
                 .map(|row: sqlx::postgres::PgRow| {
                     WhitelistUser {
                         id: row.get(0),
@@ -367,6 +1462,7 @@ impl WhitelistUser {
                         added_by: row.get::<i64, _>(3) as u64,
                         added_at: row.get(4),
                         notes: row.get(5),
+                        is_active: row.get(6),
                     }
                 })
                 .fetch_all(db)
@@ -380,10 +1476,7 @@ impl WhitelistUser {
 
 impl Admin {
     // 检查用户是否是管理员
-    pub async fn is_admin(
-        pool: &DatabasePool,
-        user_id: u64,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    pub async fn is_admin(pool: &DatabasePool, user_id: u64) -> Result<bool, AppError> {
         match pool {
             DatabasePool::Sqlite(db) => {
                 let result = sqlx::query("SELECT COUNT(*) as count FROM admins WHERE user_id = ?")
@@ -407,10 +1500,7 @@ impl Admin {
     }
 
     // 检查用户是否是超级管理员
-    pub async fn is_super_admin(
-        pool: &DatabasePool,
-        user_id: u64,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    pub async fn is_super_admin(pool: &DatabasePool, user_id: u64) -> Result<bool, AppError> {
         match pool {
             DatabasePool::Sqlite(db) => {
                 let result = sqlx::query(
@@ -443,7 +1533,7 @@ impl Admin {
         user_id: u64,
         username: Option<&str>,
         is_super: bool,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    ) -> Result<(), AppError> {
         match pool {
             DatabasePool::Sqlite(db) => {
                 sqlx::query(
@@ -472,10 +1562,76 @@ impl Admin {
         }
     }
 
-    // 获取所有管理员
-    pub async fn get_all_admins(
+    // 设置用户的超级管理员身份，用户还不是管理员时会先添加
+    pub async fn set_super_admin(
         pool: &DatabasePool,
-    ) -> Result<Vec<Admin>, Box<dyn Error + Send + Sync>> {
+        user_id: u64,
+        is_super: bool,
+    ) -> Result<(), AppError> {
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query(
+                    "INSERT INTO admins (user_id, is_super) VALUES (?, ?)
+                     ON CONFLICT (user_id) DO UPDATE SET is_super = excluded.is_super",
+                )
+                .bind(user_id as i64)
+                .bind(is_super as i32)
+                .execute(db)
+                .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query(
+                    "INSERT INTO admins (user_id, is_super) VALUES ($1, $2)
+                     ON CONFLICT (user_id) DO UPDATE SET is_super = EXCLUDED.is_super",
+                )
+                .bind(user_id as i64)
+                .bind(is_super)
+                .execute(db)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // 移除管理员（包括超级管理员），返回是否删除了记录
+    pub async fn remove_admin(pool: &DatabasePool, user_id: u64) -> Result<bool, AppError> {
+        let result = match pool {
+            DatabasePool::Sqlite(db) => sqlx::query("DELETE FROM admins WHERE user_id = ?")
+                .bind(user_id as i64)
+                .execute(db)
+                .await?
+                .rows_affected(),
+            DatabasePool::Postgres(db) => sqlx::query("DELETE FROM admins WHERE user_id = $1")
+                .bind(user_id as i64)
+                .execute(db)
+                .await?
+                .rows_affected(),
+        };
+
+        Ok(result > 0)
+    }
+
+    // 统计超级管理员数量
+    pub async fn count_super_admins(pool: &DatabasePool) -> Result<i64, AppError> {
+        let count: i64 = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_scalar("SELECT COUNT(*) FROM admins WHERE is_super = 1")
+                    .fetch_one(db)
+                    .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_scalar("SELECT COUNT(*) FROM admins WHERE is_super = TRUE")
+                    .fetch_one(db)
+                    .await?
+            }
+        };
+
+        Ok(count)
+    }
+
+    // 获取所有管理员
+    pub async fn get_all_admins(pool: &DatabasePool) -> Result<Vec<Admin>, AppError> {
         match pool {
             DatabasePool::Sqlite(db) => {
                 let rows: Vec<Admin> = sqlx::query(
@@ -516,3 +1672,295 @@ impl Admin {
         }
     }
 }
+
+impl AuditLog {
+    // 记录一条审计日志
+    pub async fn record(
+        pool: &DatabasePool,
+        actor_id: u64,
+        action: &str,
+        target_id: Option<u64>,
+        details: Option<&str>,
+    ) -> Result<(), AppError> {
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query(
+                    "INSERT INTO audit_log (actor_id, action, target_id, details) VALUES (?, ?, ?, ?)",
+                )
+                .bind(actor_id as i64)
+                .bind(action)
+                .bind(target_id.map(|id| id as i64))
+                .bind(details)
+                .execute(db)
+                .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query(
+                    "INSERT INTO audit_log (actor_id, action, target_id, details) VALUES ($1, $2, $3, $4)",
+                )
+                .bind(actor_id as i64)
+                .bind(action)
+                .bind(target_id.map(|id| id as i64))
+                .bind(details)
+                .execute(db)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl UserPreference {
+    // 获取用户偏好，没有记录时返回默认值
+    pub async fn get(pool: &DatabasePool, user_id: u64) -> Result<UserPreference, AppError> {
+        let model: Option<Option<String>> = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_scalar("SELECT model FROM user_preferences WHERE user_id = ?")
+                    .bind(user_id as i64)
+                    .fetch_optional(db)
+                    .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_scalar("SELECT model FROM user_preferences WHERE user_id = $1")
+                    .bind(user_id as i64)
+                    .fetch_optional(db)
+                    .await?
+            }
+        };
+
+        Ok(UserPreference {
+            model: model.flatten(),
+        })
+    }
+
+    // 设置用户默认模型（None 表示清除）
+    pub async fn set(
+        pool: &DatabasePool,
+        user_id: u64,
+        model: Option<&str>,
+    ) -> Result<(), AppError> {
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query(
+                    "INSERT INTO user_preferences (user_id, model) VALUES (?, ?)
+                     ON CONFLICT (user_id) DO UPDATE
+                     SET model = excluded.model, updated_at = datetime('now','localtime')",
+                )
+                .bind(user_id as i64)
+                .bind(model)
+                .execute(db)
+                .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query(
+                    "INSERT INTO user_preferences (user_id, model) VALUES ($1, $2)
+                     ON CONFLICT (user_id) DO UPDATE
+                     SET model = EXCLUDED.model, updated_at = CURRENT_TIMESTAMP",
+                )
+                .bind(user_id as i64)
+                .bind(model)
+                .execute(db)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Refusal {
+    // 记录一次拒绝回答
+    pub async fn record(
+        pool: &DatabasePool,
+        chat_id: i64,
+        model: &str,
+        prompt: &str,
+        reply: &str,
+    ) -> Result<(), AppError> {
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query(
+                    "INSERT INTO refusals (chat_id, model, prompt, reply) VALUES (?, ?, ?, ?)",
+                )
+                .bind(chat_id)
+                .bind(model)
+                .bind(prompt)
+                .bind(reply)
+                .execute(db)
+                .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query(
+                    "INSERT INTO refusals (chat_id, model, prompt, reply) VALUES ($1, $2, $3, $4)",
+                )
+                .bind(chat_id)
+                .bind(model)
+                .bind(prompt)
+                .bind(reply)
+                .execute(db)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // 获取最近的拒绝回答记录
+    pub async fn get_recent(pool: &DatabasePool, limit: i64) -> Result<Vec<Refusal>, AppError> {
+        let rows = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_as::<_, (i64, String, String, String, NaiveDateTime)>(
+                    "SELECT chat_id, model, prompt, reply, created_at FROM refusals
+                     ORDER BY id DESC LIMIT ?",
+                )
+                .bind(limit)
+                .fetch_all(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_as::<_, (i64, String, String, String, NaiveDateTime)>(
+                    "SELECT chat_id, model, prompt, reply, created_at FROM refusals
+                     ORDER BY id DESC LIMIT $1",
+                )
+                .bind(limit)
+                .fetch_all(db)
+                .await?
+            }
+        };
+
+        Ok(rows
+            .into_iter()
+            .map(|(chat_id, model, prompt, reply, created_at)| Refusal {
+                chat_id,
+                model,
+                prompt,
+                reply,
+                created_at,
+            })
+            .collect())
+    }
+}
+
+impl FinishReasonCount {
+    // 对应模型和 finish_reason 的计数加一
+    pub async fn increment(
+        pool: &DatabasePool,
+        model: &str,
+        finish_reason: &str,
+    ) -> Result<(), AppError> {
+        match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query(
+                    "INSERT INTO finish_reason_counts (model, finish_reason, count) VALUES (?, ?, 1)
+                     ON CONFLICT (model, finish_reason) DO UPDATE SET count = count + 1",
+                )
+                .bind(model)
+                .bind(finish_reason)
+                .execute(db)
+                .await?;
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query(
+                    "INSERT INTO finish_reason_counts (model, finish_reason, count) VALUES ($1, $2, 1)
+                     ON CONFLICT (model, finish_reason) DO UPDATE SET count = finish_reason_counts.count + 1",
+                )
+                .bind(model)
+                .bind(finish_reason)
+                .execute(db)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // 获取所有计数
+    pub async fn get_all(pool: &DatabasePool) -> Result<Vec<FinishReasonCount>, AppError> {
+        let rows = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_as::<_, (String, String, i64)>(
+                    "SELECT model, finish_reason, count FROM finish_reason_counts ORDER BY model ASC, count DESC",
+                )
+                .fetch_all(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_as::<_, (String, String, i64)>(
+                    "SELECT model, finish_reason, count FROM finish_reason_counts ORDER BY model ASC, count DESC",
+                )
+                .fetch_all(db)
+                .await?
+            }
+        };
+
+        Ok(rows
+            .into_iter()
+            .map(|(model, finish_reason, count)| FinishReasonCount {
+                model,
+                finish_reason,
+                count,
+            })
+            .collect())
+    }
+}
+
+impl ChatTokenUsage {
+    // 最近 days 天内各聊天的 token 用量，按总用量从高到低排列
+    pub async fn per_chat(
+        pool: &DatabasePool,
+        days: i64,
+        limit: i64,
+    ) -> Result<Vec<ChatTokenUsage>, AppError> {
+        let rows = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_as::<_, (i64, i64, i64)>(
+                    "SELECT s.chat_id,
+                            COALESCE(SUM(m.prompt_tokens), 0),
+                            COALESCE(SUM(m.completion_tokens), 0)
+                     FROM messages m JOIN sessions s ON s.id = m.session_id
+                     WHERE m.role = 'assistant'
+                       AND (m.prompt_tokens IS NOT NULL OR m.completion_tokens IS NOT NULL)
+                       AND m.timestamp >= datetime('now', 'localtime', '-' || ? || ' days')
+                     GROUP BY s.chat_id
+                     ORDER BY COALESCE(SUM(m.prompt_tokens), 0) + COALESCE(SUM(m.completion_tokens), 0) DESC
+                     LIMIT ?",
+                )
+                .bind(days)
+                .bind(limit)
+                .fetch_all(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                // SUM(BIGINT) 在 PostgreSQL 中返回 NUMERIC，需要转换回 BIGINT
+                sqlx::query_as::<_, (i64, i64, i64)>(
+                    "SELECT s.chat_id,
+                            CAST(COALESCE(SUM(m.prompt_tokens), 0) AS BIGINT),
+                            CAST(COALESCE(SUM(m.completion_tokens), 0) AS BIGINT)
+                     FROM messages m JOIN sessions s ON s.id = m.session_id
+                     WHERE m.role = 'assistant'
+                       AND (m.prompt_tokens IS NOT NULL OR m.completion_tokens IS NOT NULL)
+                       AND m.timestamp >= CURRENT_TIMESTAMP - make_interval(days => $1::INT)
+                     GROUP BY s.chat_id
+                     ORDER BY COALESCE(SUM(m.prompt_tokens), 0) + COALESCE(SUM(m.completion_tokens), 0) DESC
+                     LIMIT $2",
+                )
+                .bind(days)
+                .bind(limit)
+                .fetch_all(db)
+                .await?
+            }
+        };
+
+        Ok(rows
+            .into_iter()
+            .map(
+                |(chat_id, prompt_tokens, completion_tokens)| ChatTokenUsage {
+                    chat_id,
+                    prompt_tokens,
+                    completion_tokens,
+                },
+            )
+            .collect())
+    }
+}
@@ -0,0 +1,357 @@
+use crate::api_keys::{self, ApiKeys};
+use crate::config;
+use crate::error::AppError;
+use crate::metrics::Metrics;
+use crate::providers;
+use crate::retry::{self, RetryAction};
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde::Deserialize;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+// 服务端 Retry-After 的最长等待时间，避免用户等待过久
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+// 启动检查请求的超时时间
+const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(15);
+
+// 建立连接的超时时间
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 调用 OpenAI 兼容接口的共享客户端：启动时创建一次，所有请求复用同一个连接池
+// 克隆开销很小，克隆后仍然共用连接池和密钥的限流状态
+#[derive(Clone)]
+pub struct OpenAiClient {
+    pub http: reqwest::Client,
+    pub keys: ApiKeys,
+    // 请求耗时和结果统计，METRICS_PORT 设置时通过 /metrics 输出
+    pub metrics: Metrics,
+    // OPENAI_ORG_ID 和 OPENAI_PROJECT_ID 对应的请求头，都未设置时为空
+    scope: HeaderMap,
+}
+
+impl OpenAiClient {
+    // timeout 为单次请求（包括读取响应）的超时时间
+    pub fn new(keys: ApiKeys, timeout: Duration) -> Result<Self, AppError> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(timeout)
+            .build()?;
+        Ok(OpenAiClient {
+            http,
+            keys,
+            metrics: Metrics::default(),
+            scope: scope_headers()?,
+        })
+    }
+
+    // 计时执行一次请求（kind 如 chat、transcription），以 info 级别记录 latency_ms 并计入指标
+    pub async fn timed<T, E, F>(&self, kind: &'static str, request: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let started_at = Instant::now();
+        let result = request.await;
+        let elapsed = started_at.elapsed();
+
+        let latency_ms = elapsed.as_millis() as u64;
+        let success = result.is_ok();
+        tracing::info!(kind, latency_ms, success, "OpenAI 请求完成");
+        self.metrics.observe(kind, success, elapsed);
+        result
+    }
+
+    // 为发往默认服务（OPENAI_BASE_URL）的请求加上组织和项目请求头
+    // PROVIDERS 中的其他服务使用各自的账号，不添加
+    pub fn scoped(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request.headers(self.scope.clone())
+    }
+}
+
+// 读取 OPENAI_ORG_ID 和 OPENAI_PROJECT_ID，生成 OpenAI-Organization 和 OpenAI-Project 请求头
+fn scope_headers() -> Result<HeaderMap, AppError> {
+    let mut headers = HeaderMap::new();
+    for (name, header, value) in [
+        (
+            "OPENAI_ORG_ID",
+            "OpenAI-Organization",
+            config::openai_org_id(),
+        ),
+        (
+            "OPENAI_PROJECT_ID",
+            "OpenAI-Project",
+            config::openai_project_id(),
+        ),
+    ] {
+        let Some(value) = value else {
+            continue;
+        };
+        let value = HeaderValue::from_str(&value)
+            .map_err(|_| AppError::Config(format!("{} 包含无效字符", name)))?;
+        headers.insert(header, value);
+    }
+    Ok(headers)
+}
+
+// 聊天补全接口的响应
+#[derive(Deserialize, Debug)]
+pub struct ChatCompletion {
+    #[serde(default)]
+    pub choices: Vec<ChatChoice>,
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatChoice {
+    pub message: ChatMessage,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatMessage {
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+    // 模型请求调用的工具，只在请求中带有 tools 时出现
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ToolCall {
+    pub id: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FunctionCall {
+    pub name: String,
+    // 模型生成的参数，JSON 字符串
+    #[serde(default)]
+    pub arguments: String,
+}
+
+// content 可能是字符串，也可能是内容片段数组
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContentPart {
+    #[serde(rename = "type", default)]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+// 本次请求消耗的 token 数
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(default)]
+pub struct Usage {
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub total_tokens: i64,
+}
+
+impl std::ops::Add for Usage {
+    type Output = Usage;
+
+    fn add(self, other: Usage) -> Usage {
+        Usage {
+            prompt_tokens: self.prompt_tokens + other.prompt_tokens,
+            completion_tokens: self.completion_tokens + other.completion_tokens,
+            total_tokens: self.total_tokens + other.total_tokens,
+        }
+    }
+}
+
+impl ChatMessage {
+    // 回复的文本内容，内容片段数组时拼接其中的 text 片段
+    pub fn text(&self) -> Option<String> {
+        match self.content.as_ref()? {
+            MessageContent::Text(content) => Some(content.clone()),
+            MessageContent::Parts(parts) => {
+                let texts: Vec<&str> = parts
+                    .iter()
+                    .filter(|part| part.kind.as_deref().is_none_or(|kind| kind == "text"))
+                    .filter_map(|part| part.text.as_deref())
+                    .collect();
+
+                if texts.is_empty() {
+                    None
+                } else {
+                    Some(texts.concat())
+                }
+            }
+        }
+    }
+}
+
+// 接口返回的错误信封：{"error": {"message": "...", "type": "..."}}
+#[derive(Deserialize, Debug)]
+struct ErrorEnvelope {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorDetail {
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+}
+
+// 解析错误信封，取出 error.message 并记录到日志
+fn error_message(status: reqwest::StatusCode, body: &str) -> Option<String> {
+    let envelope: ErrorEnvelope = serde_json::from_str(body).ok()?;
+    let message = envelope.error.message?;
+    log::warn!(
+        "OpenAI 接口返回错误 ({}, {}): {}",
+        status,
+        envelope.error.kind.as_deref().unwrap_or("unknown"),
+        message
+    );
+    Some(message)
+}
+
+// 读取非成功响应的内容并转换为 API 错误
+pub async fn api_error(response: reqwest::Response) -> AppError {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    let message = error_message(status, &body);
+    AppError::OpenAi {
+        status,
+        body,
+        message,
+    }
+}
+
+// 读取聊天补全响应；非成功状态码，或成功状态码下返回错误信封时转换为 API 错误
+pub async fn read_chat_completion(response: reqwest::Response) -> Result<ChatCompletion, AppError> {
+    if !response.status().is_success() {
+        return Err(api_error(response).await);
+    }
+
+    let status = response.status();
+    let body = response.text().await?;
+    if let Some(message) = error_message(status, &body) {
+        return Err(AppError::OpenAi {
+            status,
+            body,
+            message: Some(message),
+        });
+    }
+
+    serde_json::from_str(&body).map_err(|e| AppError::InvalidResponse(e.to_string()))
+}
+
+// 单次请求的失败：发送失败，或服务端返回了可以重试的状态码
+#[derive(Debug)]
+enum AttemptError {
+    Send(reqwest::Error),
+    Status(reqwest::Response),
+}
+
+/// 发送 OpenAI 请求，遇到 429、5xx 以及连接/超时错误时按指数退避重试
+///
+/// 每次尝试从 `keys` 中取一个密钥传给 `build`（本地服务等不需要密钥时为 None）。
+/// 密钥返回 429 时会被暂停使用，还有其他可用密钥时立即换用其他密钥重试。
+/// 服务端返回 Retry-After 时按其等待（最长 60 秒）。
+/// 返回最后一次的响应，其他 4xx 不重试，直接返回给调用方处理。
+pub async fn openai_request_with_retry<F>(
+    keys: Option<&ApiKeys>,
+    build: F,
+) -> Result<reqwest::Response, AppError>
+where
+    F: Fn(Option<&str>) -> reqwest::RequestBuilder,
+{
+    let result = retry::retry_with_progress(
+        retry::DEFAULT_MAX_RETRIES,
+        retry::DEFAULT_BASE_DELAY,
+        || async {
+            let key = keys.map(ApiKeys::next);
+            let response = build(key.as_deref())
+                .send()
+                .await
+                .map_err(AttemptError::Send)?;
+            let status = response.status();
+            if status.as_u16() == 429 {
+                if let (Some(keys), Some(key)) = (keys, &key) {
+                    keys.mark_rate_limited(key, retry_after(&response));
+                }
+            }
+            if status.as_u16() == 429 || status.is_server_error() {
+                Err(AttemptError::Status(response))
+            } else {
+                Ok(response)
+            }
+        },
+        |err| classify_attempt_error(err, keys),
+        |_, _| async {},
+    )
+    .await;
+
+    match result {
+        Ok(response) => Ok(response),
+        // 重试次数用尽，交给调用方按普通的错误响应处理
+        Err(AttemptError::Status(response)) => Ok(response),
+        Err(AttemptError::Send(e)) => Err(e.into()),
+    }
+}
+
+fn classify_attempt_error(err: &AttemptError, keys: Option<&ApiKeys>) -> RetryAction {
+    match err {
+        // 连接失败可以重试；已经等满 OPENAI_TIMEOUT_SECS 的请求不再重试，避免用户等待数倍的时间
+        AttemptError::Send(e) if e.is_connect() => RetryAction::Backoff,
+        AttemptError::Send(_) => RetryAction::Fail,
+        // 被限流但还有其他密钥可用，不必等待
+        AttemptError::Status(response)
+            if response.status().as_u16() == 429 && keys.is_some_and(ApiKeys::has_available) =>
+        {
+            RetryAction::After(Duration::ZERO)
+        }
+        AttemptError::Status(response) => match retry_after(response) {
+            Some(delay) => RetryAction::After(delay.min(MAX_RETRY_AFTER)),
+            None => RetryAction::Backoff,
+        },
+    }
+}
+
+// 读取 Retry-After 响应头（秒数）
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?;
+    let seconds = value.to_str().ok()?.trim().parse::<u64>().ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+// 启动检查：用每个密钥请求一次 GET /models（不消耗 token），确认密钥有效、接口可以访问
+// 任意一个密钥失败都返回配置错误，错误信息中只显示密钥末尾几位
+pub async fn check_api_keys(client: &OpenAiClient) -> Result<(), AppError> {
+    let url = providers::default_endpoint("models");
+    for key in client.keys.keys() {
+        let result = match client
+            .scoped(client.http.get(&url))
+            .bearer_auth(key)
+            .timeout(PREFLIGHT_TIMEOUT)
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(api_error(response).await),
+            Err(e) => Err(AppError::from(e)),
+        };
+        if let Err(e) = result {
+            return Err(AppError::Config(format!(
+                "OpenAI 密钥 {} 检查失败（GET {}）: {}",
+                api_keys::mask(key),
+                url,
+                e
+            )));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::env;
+
+// 内置人设：名称和对应的系统提示词
+const BUILTIN_PERSONAS: &[(&str, &str)] = &[
+    ("assistant", "你是一个乐于助人的AI助手，回答准确、简洁。"),
+    (
+        "translator",
+        "你是一名专业翻译。用户发送中文时翻译成英文，发送其他语言时翻译成中文，只输出译文。",
+    ),
+    (
+        "coder",
+        "你是一名资深软件工程师，回答编程问题时给出可运行的代码，并简要说明思路。",
+    ),
+    (
+        "teacher",
+        "你是一位耐心的老师，用通俗易懂的语言和例子解释概念，必要时分步骤说明。",
+    ),
+];
+
+// 读取 CHAT_PERSONAS 配置（JSON 对象，聊天ID -> 人设名称或提示词）
+fn load_chat_personas() -> Result<HashMap<String, String>, String> {
+    match env::var("CHAT_PERSONAS") {
+        Ok(config) if !config.trim().is_empty() => serde_json::from_str(&config)
+            .map_err(|e| format!("CHAT_PERSONAS 不是有效的 JSON: {}", e)),
+        _ => Ok(HashMap::new()),
+    }
+}
+
+// 启动时检查 CHAT_PERSONAS 配置，返回配置了人设的聊天数量
+pub fn validate() -> Result<usize, String> {
+    let personas = load_chat_personas()?;
+    for chat_id in personas.keys() {
+        if chat_id.trim().parse::<i64>().is_err() {
+            return Err(format!("CHAT_PERSONAS 中的聊天ID无效: {}", chat_id));
+        }
+    }
+    Ok(personas.len())
+}
+
+// 获取聊天对应的人设提示词
+// 配置的值是内置人设名称时使用内置提示词，否则直接作为提示词使用
+pub fn prompt_for_chat(chat_id: i64) -> Option<String> {
+    let personas = load_chat_personas().unwrap_or_else(|e| {
+        log::warn!("{}", e);
+        HashMap::new()
+    });
+
+    let persona = personas
+        .iter()
+        .find(|(id, _)| id.trim().parse::<i64>().ok() == Some(chat_id))
+        .map(|(_, persona)| persona.trim())?;
+
+    if persona.is_empty() {
+        return None;
+    }
+
+    let prompt = BUILTIN_PERSONAS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(persona))
+        .map(|(_, prompt)| prompt.to_string())
+        .unwrap_or_else(|| persona.to_string());
+
+    Some(prompt)
+}
@@ -0,0 +1,46 @@
+use crate::db::DatabasePool;
+use crate::error::AppError;
+use crate::i18n::{t, Key, Lang};
+use crate::models;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+// 聊天的隐私同意状态，保存在 sessions.privacy_consent 中（未询问时为 NULL）
+pub const PENDING: &str = "pending";
+pub const GRANTED: &str = "granted";
+pub const DECLINED: &str = "declined";
+
+// 按钮回调数据
+pub const CALLBACK_GRANT: &str = "privacy:grant";
+pub const CALLBACK_DECLINE: &str = "privacy:decline";
+pub const CALLBACK_REVOKE: &str = "privacy:revoke";
+
+// 询问是否同意保存消息的按钮
+pub fn consent_keyboard(lang: Lang) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[
+        InlineKeyboardButton::callback(t(lang, Key::PrivacyGrantButton), CALLBACK_GRANT),
+        InlineKeyboardButton::callback(t(lang, Key::PrivacyDeclineButton), CALLBACK_DECLINE),
+    ]])
+}
+
+// 已同意时用于撤回的按钮
+pub fn revoke_keyboard(lang: Lang) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        t(lang, Key::PrivacyRevokeButton),
+        CALLBACK_REVOKE,
+    )]])
+}
+
+// 当前状态的说明
+pub fn status_text(lang: Lang, consent: Option<&str>) -> &'static str {
+    match consent {
+        Some(GRANTED) => t(lang, Key::PrivacyStatusGranted),
+        Some(DECLINED) => t(lang, Key::PrivacyStatusDeclined),
+        _ => t(lang, Key::PrivacyStatusPending),
+    }
+}
+
+// 撤回同意：删除本聊天保存的消息并记录为不同意，会话上的其他设置保留
+pub async fn revoke(pool: &DatabasePool, chat_id: i64) -> Result<(), AppError> {
+    models::Session::clear_history_by_chat_id(pool, chat_id).await?;
+    models::Session::set_privacy_consent(pool, chat_id, DECLINED).await
+}
@@ -0,0 +1,101 @@
+use crate::api_keys::ApiKeys;
+use serde::Deserialize;
+use std::env;
+
+// 未设置 OPENAI_BASE_URL 时使用的接口地址
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+// 默认服务的接口地址，可通过 OPENAI_BASE_URL 指向兼容 OpenAI 的服务，末尾的斜杠会被去掉
+pub fn default_base_url() -> String {
+    env::var("OPENAI_BASE_URL")
+        .ok()
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+}
+
+// 默认服务上指定接口的完整地址，例如 audio/transcriptions
+pub fn default_endpoint(path: &str) -> String {
+    format!("{}/{}", default_base_url(), path.trim_start_matches('/'))
+}
+
+// 一条路由规则：模型名以 prefix 开头时使用对应的接口
+#[derive(Debug, Clone, Deserialize)]
+struct Route {
+    prefix: String,
+    base_url: String,
+    #[serde(default)]
+    api_key: Option<String>,
+}
+
+// 兼容 OpenAI 接口的服务提供方
+#[derive(Clone)]
+pub struct Provider {
+    pub base_url: String,
+    // 本地服务可以不需要密钥
+    pub api_keys: Option<ApiKeys>,
+    // 是否是默认服务（没有匹配 PROVIDERS 中的路由）
+    pub is_default: bool,
+}
+
+impl Provider {
+    // 聊天补全接口地址
+    pub fn chat_completions_url(&self) -> String {
+        format!("{}/chat/completions", self.base_url.trim_end_matches('/'))
+    }
+}
+
+// 解析 PROVIDERS 环境变量，格式为 JSON 数组，例如：
+// [{"prefix": "llama", "base_url": "http://localhost:11434/v1"},
+//  {"prefix": "gpt-", "base_url": "https://api.openai.com/v1", "api_key": "sk-..."}]
+fn load_routes() -> Result<Vec<Route>, String> {
+    let config = match env::var("PROVIDERS") {
+        Ok(config) if !config.trim().is_empty() => config,
+        _ => return Ok(Vec::new()),
+    };
+
+    let routes: Vec<Route> =
+        serde_json::from_str(&config).map_err(|e| format!("PROVIDERS 不是有效的 JSON: {}", e))?;
+
+    for route in &routes {
+        if route.prefix.trim().is_empty() {
+            return Err("PROVIDERS 中的 prefix 不能为空".to_string());
+        }
+        if !route.base_url.starts_with("http://") && !route.base_url.starts_with("https://") {
+            return Err(format!(
+                "PROVIDERS 中 {} 的 base_url 必须以 http:// 或 https:// 开头",
+                route.prefix
+            ));
+        }
+    }
+
+    Ok(routes)
+}
+
+// 启动时检查 PROVIDERS 配置，返回配置的路由数量
+pub fn validate() -> Result<usize, String> {
+    load_routes().map(|routes| routes.len())
+}
+
+// 根据模型名选择服务提供方：匹配最长的前缀，没有匹配时使用默认服务（OPENAI_BASE_URL）和默认密钥
+pub fn for_model(model: &str, default_keys: &ApiKeys) -> Provider {
+    let routes = load_routes().unwrap_or_else(|e| {
+        log::warn!("{}，使用默认服务", e);
+        Vec::new()
+    });
+
+    routes
+        .into_iter()
+        .filter(|route| model.starts_with(&route.prefix))
+        .max_by_key(|route| route.prefix.len())
+        .map(|route| Provider {
+            base_url: route.base_url,
+            api_keys: route.api_key.and_then(|key| ApiKeys::new(vec![key])),
+            is_default: false,
+        })
+        .unwrap_or_else(|| Provider {
+            base_url: default_base_url(),
+            api_keys: Some(default_keys.clone()),
+            is_default: true,
+        })
+}
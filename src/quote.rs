@@ -0,0 +1,59 @@
+// 回复某条消息时作为提问上下文的引用内容
+use crate::db::DatabasePool;
+use crate::models;
+use teloxide::types::Message;
+
+// 获取被回复消息中的上下文：优先使用引用的片段，否则使用整条消息的文字
+pub fn quoted_context(msg: &Message) -> Option<String> {
+    if let Some(quote) = msg.quote() {
+        return Some(quote.text.clone());
+    }
+
+    msg.reply_to_message()
+        .and_then(|reply| reply.text().or_else(|| reply.caption()))
+        .map(|text| text.to_string())
+}
+
+// 提问使用的引用上下文；回复的是已保存的机器人回答时，那一轮对话会放进历史消息，
+// 不再把整条回答作为引用重复发送一次（引用了其中一段时仍然保留）
+pub async fn reply_quoted_context(db_pool: &DatabasePool, msg: &Message) -> Option<String> {
+    if msg.quote().is_none() && replies_to_saved_answer(db_pool, msg).await {
+        return None;
+    }
+    quoted_context(msg)
+}
+
+// 被回复的消息是否是机器人已保存的回答；数据库出错时按没有找到处理
+async fn replies_to_saved_answer(db_pool: &DatabasePool, msg: &Message) -> bool {
+    let Some(reply) = msg.reply_to_message() else {
+        return false;
+    };
+    if !reply.from.as_ref().is_some_and(|user| user.is_bot) {
+        return false;
+    }
+
+    let exchange = match models::Session::get_by_chat_id(db_pool, msg.chat.id.0).await {
+        Ok(Some(session)) => {
+            models::Message::get_exchange_by_tg_id(db_pool, session.id, reply.id.0 as i64).await
+        }
+        Ok(None) => return false,
+        Err(e) => Err(e),
+    };
+    match exchange {
+        Ok(exchange) => !exchange.is_empty(),
+        Err(e) => {
+            log::warn!("查找被回复的对话错误: {:?}", e);
+            false
+        }
+    }
+}
+
+// 将引用内容和用户的问题组合为一条提问
+pub fn with_quoted_context(quoted: Option<&str>, text: &str) -> String {
+    match quoted {
+        Some(quoted) if !quoted.trim().is_empty() => {
+            format!("引用内容：\n{}\n\n{}", quoted.trim(), text)
+        }
+        _ => text.to_string(),
+    }
+}
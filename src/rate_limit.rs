@@ -0,0 +1,72 @@
+use crate::db::DatabasePool;
+use std::error::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 限流检查的结果
+pub enum RateLimitDecision {
+    Allowed,
+    // 超出限制，等待 reset_in 后窗口重置
+    Limited { reset_in: Duration },
+}
+
+// 按用户的固定时间窗口计数，计数保存在 rate_limits 表中，重启后仍然有效
+pub struct RateLimiter;
+
+impl RateLimiter {
+    // 记录一次请求并判断是否超出 window 内 limit 次的限制
+    // 窗口过期时从本次请求重新开始计数
+    pub async fn check_and_increment(
+        pool: &DatabasePool,
+        user_id: u64,
+        limit: u32,
+        window: Duration,
+    ) -> Result<RateLimitDecision, Box<dyn Error + Send + Sync>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let expired_before = now - window.as_secs() as i64;
+
+        let (window_start, count): (i64, i64) = match pool {
+            DatabasePool::Sqlite(db) => {
+                sqlx::query_as(
+                    "INSERT INTO rate_limits (user_id, window_start, count) VALUES (?, ?, 1)
+                     ON CONFLICT (user_id) DO UPDATE SET
+                         window_start = CASE WHEN rate_limits.window_start <= ?
+                             THEN excluded.window_start ELSE rate_limits.window_start END,
+                         count = CASE WHEN rate_limits.window_start <= ?
+                             THEN 1 ELSE rate_limits.count + 1 END
+                     RETURNING window_start, count",
+                )
+                .bind(user_id as i64)
+                .bind(now)
+                .bind(expired_before)
+                .bind(expired_before)
+                .fetch_one(db)
+                .await?
+            }
+            DatabasePool::Postgres(db) => {
+                sqlx::query_as(
+                    "INSERT INTO rate_limits (user_id, window_start, count) VALUES ($1, $2, 1)
+                     ON CONFLICT (user_id) DO UPDATE SET
+                         window_start = CASE WHEN rate_limits.window_start <= $3
+                             THEN EXCLUDED.window_start ELSE rate_limits.window_start END,
+                         count = CASE WHEN rate_limits.window_start <= $3
+                             THEN 1 ELSE rate_limits.count + 1 END
+                     RETURNING window_start, count",
+                )
+                .bind(user_id as i64)
+                .bind(now)
+                .bind(expired_before)
+                .fetch_one(db)
+                .await?
+            }
+        };
+
+        if count <= limit as i64 {
+            return Ok(RateLimitDecision::Allowed);
+        }
+
+        let reset_at = window_start + window.as_secs() as i64;
+        Ok(RateLimitDecision::Limited {
+            reset_in: Duration::from_secs((reset_at - now).max(0) as u64),
+        })
+    }
+}
@@ -0,0 +1,57 @@
+use crate::db::DatabasePool;
+use crate::error::AppError;
+use crate::models;
+
+// 常见的拒绝回答措辞，只检查回复开头的部分，属于启发式判断
+const REFUSAL_PATTERNS: &[&str] = &[
+    "i'm sorry, but i can't",
+    "i'm sorry, but i cannot",
+    "i’m sorry, but i can’t",
+    "i can't help with",
+    "i can't assist with",
+    "i cannot help with",
+    "i cannot assist with",
+    "i'm unable to help",
+    "i am unable to help",
+    "抱歉，我无法",
+    "抱歉，我不能",
+    "对不起，我无法",
+    "对不起，我不能",
+    "我无法协助",
+    "我无法提供",
+];
+
+// 只在回复开头查找拒绝措辞，避免长回答中偶然出现的句子被误判
+const CHECK_PREFIX_CHARS: usize = 200;
+
+// 判断回复是否像是模型拒绝回答
+pub fn is_refusal(reply: &str) -> bool {
+    let head: String = reply
+        .chars()
+        .take(CHECK_PREFIX_CHARS)
+        .collect::<String>()
+        .to_lowercase();
+    REFUSAL_PATTERNS
+        .iter()
+        .any(|pattern| head.contains(pattern))
+}
+
+// 未同意保存消息（PRIVACY_CONSENT）的聊天中，只记录发生过拒绝回答，提问和回复以此代替
+pub const REDACTED: &str = "[未同意保存消息，内容未记录]";
+
+// 记录一次拒绝回答；store_text 为 false 时不保存提问和回复的内容
+pub async fn record(
+    pool: &DatabasePool,
+    chat_id: i64,
+    model: &str,
+    prompt: &str,
+    reply: &str,
+    store_text: bool,
+) -> Result<(), AppError> {
+    let (prompt, reply) = if store_text {
+        (prompt, reply)
+    } else {
+        (REDACTED, REDACTED)
+    };
+    models::Refusal::record(pool, chat_id, model, prompt, reply).await
+}
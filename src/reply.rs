@@ -0,0 +1,57 @@
+// Telegram 单条消息的长度上限（按 UTF-16 编码单元计算）
+pub const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+
+// 将回复拆分为不超过 Telegram 上限的多条消息，页脚附加在最后一条之后
+// 页脚放不进最后一条时单独作为一条消息发送
+pub fn split_for_telegram(content: &str, footer: Option<&str>) -> Vec<String> {
+    let mut chunks = split_text(content, TELEGRAM_MESSAGE_LIMIT);
+
+    if let Some(footer) = footer {
+        match chunks.last_mut() {
+            Some(last) if utf16_len(last) + 1 + utf16_len(footer) <= TELEGRAM_MESSAGE_LIMIT => {
+                last.push('\n');
+                last.push_str(footer);
+            }
+            _ => chunks.extend(split_text(footer, TELEGRAM_MESSAGE_LIMIT)),
+        }
+    }
+
+    chunks
+}
+
+// 按长度上限拆分文本，尽量在换行处断开
+fn split_text(text: &str, limit: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+
+    while utf16_len(rest) > limit {
+        // 找到不超过上限的最大字节位置
+        let mut end = 0;
+        let mut units = 0;
+        for (index, ch) in rest.char_indices() {
+            if units + ch.len_utf16() > limit {
+                break;
+            }
+            units += ch.len_utf16();
+            end = index + ch.len_utf8();
+        }
+
+        // 优先在最后一个换行处断开，换行本身不保留
+        let (chunk, next) = match rest[..end].rfind('\n') {
+            Some(newline) if newline > 0 => (&rest[..newline], &rest[newline + 1..]),
+            _ => (&rest[..end], &rest[end..]),
+        };
+        chunks.push(chunk.to_string());
+        rest = next;
+    }
+
+    if !rest.is_empty() || chunks.is_empty() {
+        chunks.push(rest.to_string());
+    }
+
+    chunks
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
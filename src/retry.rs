@@ -0,0 +1,106 @@
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use teloxide::{DownloadError, RequestError};
+
+// 默认最多重试次数
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+// 默认退避基准时间，第 n 次重试前等待 base * 2^n，再加上最多 base / 4 的随机抖动
+pub const DEFAULT_BASE_DELAY: Duration = Duration::from_secs(1);
+
+// 失败后的处理方式
+pub enum RetryAction {
+    // 不可重试，直接返回错误
+    Fail,
+    // 按指数退避等待后重试
+    Backoff,
+    // 等待服务端指定的时间后重试
+    After(Duration),
+}
+
+// 重试等待期间显示给用户的提示
+pub const RETRY_STATUS_TEXT: &str = "服务繁忙，正在重试...";
+
+/// 带指数退避的通用重试
+///
+/// `classify` 决定错误是否可以重试。重试次数用尽后返回最后一次的错误。
+/// 每次等待重试前会调用 `on_retry(第几次重试, 等待时间)`，可用于更新占位消息等进度提示。
+pub async fn retry_with_progress<T, E, F, Fut, C, P, PFut>(
+    max_retries: u32,
+    base_delay: Duration,
+    mut operation: F,
+    classify: C,
+    mut on_retry: P,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    C: Fn(&E) -> RetryAction,
+    E: std::fmt::Debug,
+    P: FnMut(u32, Duration) -> PFut,
+    PFut: Future<Output = ()>,
+{
+    let mut attempt = 0;
+    loop {
+        let err = match operation().await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+
+        if attempt >= max_retries {
+            return Err(err);
+        }
+
+        let delay = match classify(&err) {
+            RetryAction::Fail => return Err(err),
+            RetryAction::Backoff => base_delay * 2u32.pow(attempt) + jitter(base_delay / 4),
+            RetryAction::After(delay) => delay,
+        };
+
+        attempt += 1;
+        log::warn!(
+            "请求失败，{:?} 后进行第 {} 次重试: {:?}",
+            delay,
+            attempt,
+            err
+        );
+        on_retry(attempt, delay).await;
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// 0 到 max 之间的随机抖动，避免多个请求同时重试
+fn jitter(max: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos() as u64)
+        .unwrap_or_default();
+    match max.as_nanos() as u64 {
+        0 => Duration::ZERO,
+        max => Duration::from_nanos(nanos % max),
+    }
+}
+
+// Telegram API 请求错误：限流时按 retry_after 等待，网络错误按退避重试
+pub fn classify_request_error(err: &RequestError) -> RetryAction {
+    match err {
+        RequestError::RetryAfter(seconds) => RetryAction::After(seconds.duration()),
+        RequestError::Network(_) => RetryAction::Backoff,
+        _ => RetryAction::Fail,
+    }
+}
+
+// Telegram 文件下载错误：429、5xx 和连接/超时错误可以重试
+pub fn classify_download_error(err: &DownloadError) -> RetryAction {
+    match err {
+        DownloadError::Network(e) => match e.status() {
+            Some(status) if status.as_u16() == 429 || status.is_server_error() => {
+                RetryAction::Backoff
+            }
+            Some(_) => RetryAction::Fail,
+            None if e.is_timeout() || e.is_connect() => RetryAction::Backoff,
+            None => RetryAction::Fail,
+        },
+        DownloadError::Io(_) => RetryAction::Fail,
+    }
+}
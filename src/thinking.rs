@@ -0,0 +1,47 @@
+// 等待回复期间的"思考中"占位消息
+use crate::config;
+use crate::i18n::{t, Key, Lang};
+use teloxide::prelude::*;
+use teloxide::types::Message;
+
+// 发送"思考中"的占位消息，文字可以通过 THINKING_TEXT 自定义；关闭 SHOW_THINKING 时不发送
+pub async fn send_thinking(
+    bot: &Bot,
+    chat_id: ChatId,
+    lang: Lang,
+) -> ResponseResult<Option<Message>> {
+    if !config::show_thinking() {
+        return Ok(None);
+    }
+    let text = config::thinking_text().unwrap_or_else(|| t(lang, Key::Thinking).to_string());
+    Ok(Some(bot.send_message(chat_id, text).await?))
+}
+
+// 收到回复后删除占位消息；网络较差时删除可能失败，只记录日志，不影响发送回复
+pub async fn delete_thinking(bot: &Bot, chat_id: ChatId, thinking_message: Option<&Message>) {
+    let Some(thinking_message) = thinking_message else {
+        return;
+    };
+    if let Err(e) = bot.delete_message(chat_id, thinking_message.id).await {
+        log::warn!("删除思考中的提示失败: {:?}", e);
+    }
+}
+
+// 把占位消息改为 text（如错误提示），没有占位消息时直接发送
+pub async fn show_in_thinking(
+    bot: &Bot,
+    chat_id: ChatId,
+    thinking_message: Option<&Message>,
+    text: String,
+) -> ResponseResult<()> {
+    match thinking_message {
+        Some(thinking_message) => {
+            bot.edit_message_text(chat_id, thinking_message.id, text)
+                .await?;
+        }
+        None => {
+            bot.send_message(chat_id, text).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use chrono::{FixedOffset, Utc};
+use serde_json::Value;
+
+// 一次回复中最多执行几轮工具调用，超出后要求模型直接回答，避免模型反复调用工具
+pub const MAX_TOOL_ROUNDS: usize = 3;
+
+// 发送给模型的工具列表（OpenAI tools 格式），新增工具时在这里和 execute 中同时添加
+pub fn definitions() -> Vec<Value> {
+    vec![serde_json::json!({
+        "type": "function",
+        "function": {
+            "name": "get_current_time",
+            "description": "获取当前的日期和时间。用户询问现在几点、今天几号或星期几时调用。",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "utc_offset_hours": {
+                        "type": "number",
+                        "description": "时区相对 UTC 的小时数，例如北京时间为 8；不确定时省略，返回 UTC 时间"
+                    }
+                }
+            }
+        }
+    })]
+}
+
+// 执行模型请求的工具，arguments 为模型给出的 JSON 字符串
+// 返回交给模型的结果文本；未知工具或参数错误时返回错误说明，由模型决定如何回复
+pub fn execute(name: &str, arguments: &str) -> String {
+    let arguments: Value = match serde_json::from_str(arguments) {
+        Ok(arguments) => arguments,
+        // 没有参数的调用可能给出空字符串
+        Err(_) if arguments.trim().is_empty() => Value::Null,
+        Err(e) => return error_result(&format!("参数不是有效的 JSON: {}", e)),
+    };
+
+    match name {
+        "get_current_time" => get_current_time(&arguments),
+        _ => error_result(&format!("未知的工具: {}", name)),
+    }
+}
+
+fn get_current_time(arguments: &Value) -> String {
+    let hours = arguments["utc_offset_hours"].as_f64().unwrap_or(0.0);
+    let Some(offset) = FixedOffset::east_opt((hours * 3600.0).round() as i32) else {
+        return error_result("utc_offset_hours 超出范围");
+    };
+
+    let now = Utc::now().with_timezone(&offset);
+    serde_json::json!({
+        "datetime": now.to_rfc3339(),
+        "weekday": now.format("%A").to_string(),
+        "utc_offset": now.format("%:z").to_string(),
+    })
+    .to_string()
+}
+
+fn error_result(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
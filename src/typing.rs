@@ -0,0 +1,33 @@
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::ChatAction;
+use tokio::task::JoinHandle;
+
+// "正在输入"状态约 5 秒后失效，需要在失效前重新发送
+const RESEND_INTERVAL: Duration = Duration::from_secs(4);
+
+// 在后台持续显示"正在输入"，被丢弃时停止
+// 成功、出错或提前返回时都会随作用域结束而停止
+pub struct TypingIndicator {
+    handle: JoinHandle<()>,
+}
+
+impl TypingIndicator {
+    pub fn start(bot: Bot, chat_id: ChatId) -> Self {
+        let handle = tokio::spawn(async move {
+            loop {
+                if let Err(e) = bot.send_chat_action(chat_id, ChatAction::Typing).await {
+                    log::debug!("发送输入状态失败: {:?}", e);
+                }
+                tokio::time::sleep(RESEND_INTERVAL).await;
+            }
+        });
+        TypingIndicator { handle }
+    }
+}
+
+impl Drop for TypingIndicator {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
@@ -0,0 +1,22 @@
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+// 语音回复下方按钮的回调数据
+// 原语音消息通过回复关系（reply_to_message）找到，回调数据中只记录操作
+pub const PREFIX: &str = "voice:";
+pub const CALLBACK_RETRANSCRIBE: &str = "voice:retranscribe";
+pub const CALLBACK_TRANSCRIBE_ONLY: &str = "voice:transcribe_only";
+pub const CALLBACK_SPEAK: &str = "voice:speak";
+
+// 附加在语音提问的AI回复下方的按钮
+pub fn keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([
+        vec![
+            InlineKeyboardButton::callback("🔁 重新转录", CALLBACK_RETRANSCRIBE),
+            InlineKeyboardButton::callback("📝 仅转录不回答", CALLBACK_TRANSCRIBE_ONLY),
+        ],
+        vec![InlineKeyboardButton::callback(
+            "🔊 朗读回复",
+            CALLBACK_SPEAK,
+        )],
+    ])
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::{memory_pool, INITIAL_ADMIN_ID};
+use gpt_bot_rs::access::{sender_chat_allowed, AccessCache};
+use gpt_bot_rs::models::{Admin, WhitelistUser};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+#[tokio::test]
+async fn whitelist_changes_are_visible_after_invalidate() {
+    let pool = memory_pool().await;
+    let cache = AccessCache::new(Duration::from_secs(60));
+
+    assert!(!cache.evaluate(&pool, 42).await.unwrap().whitelisted);
+
+    // /adduser 修改数据库后，缓存中仍是旧结果，直到被 invalidate
+    WhitelistUser::add_user(&pool, 42, None, INITIAL_ADMIN_ID, None)
+        .await
+        .unwrap();
+    assert!(!cache.evaluate(&pool, 42).await.unwrap().whitelisted);
+    cache.invalidate(42);
+    assert!(cache.evaluate(&pool, 42).await.unwrap().whitelisted);
+
+    // /removeuser 同样立即生效
+    WhitelistUser::deactivate(&pool, 42).await.unwrap();
+    cache.invalidate(42);
+    assert!(!cache.evaluate(&pool, 42).await.unwrap().whitelisted);
+}
+
+#[tokio::test]
+async fn admin_changes_are_visible_after_invalidate_or_clear() {
+    let pool = memory_pool().await;
+    let cache = AccessCache::new(Duration::from_secs(60));
+
+    assert!(!cache.evaluate(&pool, 7).await.unwrap().is_admin);
+
+    // /addadmin 使对应用户的缓存失效
+    Admin::add_admin(&pool, 7, None, false).await.unwrap();
+    cache.invalidate(7);
+    assert!(cache.evaluate(&pool, 7).await.unwrap().is_admin);
+
+    // /removeadmin 清空全部缓存
+    Admin::remove_admin(&pool, 7).await.unwrap();
+    cache.clear();
+    assert!(!cache.evaluate(&pool, 7).await.unwrap().is_admin);
+}
+
+#[tokio::test]
+async fn zero_ttl_never_caches() {
+    let pool = memory_pool().await;
+    let cache = AccessCache::new(Duration::ZERO);
+
+    assert!(!cache.evaluate(&pool, 42).await.unwrap().whitelisted);
+    WhitelistUser::add_user(&pool, 42, None, INITIAL_ADMIN_ID, None)
+        .await
+        .unwrap();
+    assert!(cache.evaluate(&pool, 42).await.unwrap().whitelisted);
+}
+
+const CHANNEL_ID: i64 = -1001234567890;
+
+// 在 chat 中以 CHANNEL_ID 频道身份发送的一条消息
+fn sender_chat_message(chat: Value) -> teloxide::types::Message {
+    serde_json::from_value(json!({
+        "message_id": 1,
+        "date": 0,
+        "chat": chat,
+        "sender_chat": { "id": CHANNEL_ID, "type": "channel", "title": "channel" },
+        "text": "hello",
+    }))
+    .unwrap()
+}
+
+#[test]
+fn only_allowlisted_channel_posts_skip_the_whitelist() {
+    let channel = json!({ "id": CHANNEL_ID, "type": "channel", "title": "channel" });
+    let post = sender_chat_message(channel);
+    assert!(sender_chat_allowed(&post, &[CHANNEL_ID]));
+    assert!(!sender_chat_allowed(&post, &[]));
+    assert!(!sender_chat_allowed(&post, &[-100999]));
+
+    // 群组中以频道身份发言，即使频道在 allowlist 中也不放行
+    let group = json!({ "id": -100555, "type": "supergroup", "title": "group" });
+    let group_post = sender_chat_message(group);
+    assert!(!sender_chat_allowed(&group_post, &[CHANNEL_ID]));
+}
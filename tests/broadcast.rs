@@ -0,0 +1,131 @@
+mod common;
+
+use axum::Json;
+use axum::Router;
+use gpt_bot_rs::broadcast::{broadcast, BroadcastReport};
+use gpt_bot_rs::db::DatabasePool;
+use gpt_bot_rs::models::Session;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use teloxide::Bot;
+
+// 已屏蔽机器人的聊天和不存在的聊天
+const BLOCKED_CHAT: i64 = 2;
+const MISSING_CHAT: i64 = 3;
+
+// 模拟 Telegram Bot API 的 sendMessage，返回收到请求的聊天ID列表
+async fn mock_telegram() -> (reqwest::Url, Arc<Mutex<Vec<i64>>>) {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let chats = received.clone();
+    let app = Router::new().fallback(move |Json(body): Json<Value>| {
+        let chat_id = body["chat_id"].as_i64().unwrap();
+        chats.lock().unwrap().push(chat_id);
+        async move {
+            let response = match chat_id {
+                BLOCKED_CHAT => json!({
+                    "ok": false,
+                    "error_code": 403,
+                    "description": "Forbidden: bot was blocked by the user",
+                }),
+                MISSING_CHAT => json!({
+                    "ok": false,
+                    "error_code": 400,
+                    "description": "Bad Request: chat not found",
+                }),
+                _ => json!({
+                    "ok": true,
+                    "result": {
+                        "message_id": 1,
+                        "date": 0,
+                        "chat": { "id": chat_id, "type": "private", "first_name": "test" },
+                        "text": body["text"],
+                    },
+                }),
+            };
+            Json(response)
+        }
+    });
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let url = reqwest::Url::parse(&format!("http://{}/", address)).unwrap();
+    (url, received)
+}
+
+async fn audit_details(pool: &DatabasePool) -> Vec<(i64, String)> {
+    let DatabasePool::Sqlite(db) = pool else {
+        unreachable!()
+    };
+    sqlx::query_as("SELECT actor_id, details FROM audit_log WHERE action = 'broadcast'")
+        .fetch_all(db)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn chat_ids_are_distinct_and_sorted() {
+    let pool = common::memory_pool().await;
+    for chat_id in [5, -100123, 1, 5] {
+        Session::find_or_create_by_chat_id(&pool, chat_id)
+            .await
+            .unwrap();
+    }
+
+    let chat_ids = Session::get_all_chat_ids(&pool).await.unwrap();
+    assert_eq!(chat_ids, vec![-100123, 1, 5]);
+}
+
+#[tokio::test]
+async fn broadcast_counts_blocked_and_failed_chats_separately() {
+    let pool = common::memory_pool().await;
+    for chat_id in [1, BLOCKED_CHAT, MISSING_CHAT, 4] {
+        Session::find_or_create_by_chat_id(&pool, chat_id)
+            .await
+            .unwrap();
+    }
+    let (url, received) = mock_telegram().await;
+    let bot = Bot::new("123:test").set_api_url(url);
+
+    let report = broadcast(&bot, &pool, common::INITIAL_ADMIN_ID, "维护通知")
+        .await
+        .unwrap();
+
+    assert_eq!(
+        report,
+        BroadcastReport {
+            sent: 2,
+            blocked: 1,
+            failed: 1,
+        }
+    );
+    // 每个聊天只发送一次，屏蔽和 4xx 错误都不重试
+    assert_eq!(
+        *received.lock().unwrap(),
+        vec![1, BLOCKED_CHAT, MISSING_CHAT, 4]
+    );
+    assert_eq!(
+        audit_details(&pool).await,
+        vec![(
+            common::INITIAL_ADMIN_ID as i64,
+            "成功 2，已屏蔽 1，失败 1".to_string()
+        )]
+    );
+}
+
+#[tokio::test]
+async fn broadcast_without_sessions_sends_nothing() {
+    let pool = common::memory_pool().await;
+    let (url, received) = mock_telegram().await;
+    let bot = Bot::new("123:test").set_api_url(url);
+
+    let report = broadcast(&bot, &pool, common::INITIAL_ADMIN_ID, "维护通知")
+        .await
+        .unwrap();
+
+    assert!(received.lock().unwrap().is_empty());
+    assert_eq!(
+        report.to_text(),
+        "✅ 通知发送完成\n成功: 0\n已屏蔽机器人: 0\n失败: 0"
+    );
+}
@@ -0,0 +1,211 @@
+mod common;
+
+use common::{memory_pool, INITIAL_ADMIN_ID};
+use gpt_bot_rs::commands;
+use gpt_bot_rs::models::WhitelistUser;
+
+#[test]
+fn command_shaped_edits_are_recognized() {
+    assert!(commands::is_command_text("/adduser 12345"));
+    assert!(commands::is_command_text("/adduser@my_bot 12345"));
+    assert!(!commands::is_command_text("请把 /adduser 的用法告诉我"));
+}
+
+#[tokio::test]
+async fn edited_adduser_does_not_add_the_user_twice() {
+    let pool = memory_pool().await;
+
+    // 第一次发送的 /adduser 正常执行
+    WhitelistUser::add_user(&pool, 12345, None, INITIAL_ADMIN_ID, None)
+        .await
+        .unwrap();
+
+    // 编辑后的命令交给忽略编辑命令的处理器，不会再次执行
+    let edited = "/adduser 12345 修正后的备注";
+    assert!(commands::is_command_text(edited));
+
+    // 即使同一个用户再次被添加，白名单中也只有一条记录
+    WhitelistUser::add_user(&pool, 12345, None, INITIAL_ADMIN_ID, None)
+        .await
+        .unwrap();
+    let users = WhitelistUser::get_all_users(&pool, None).await.unwrap();
+    assert_eq!(users.iter().filter(|user| user.user_id == 12345).count(), 1);
+}
+
+#[test]
+fn adduser_arguments_split_id_username_and_notes() {
+    assert_eq!(
+        commands::parse_add_user_args("123"),
+        Some((123, None, None))
+    );
+    assert_eq!(
+        commands::parse_add_user_args("123 hello world"),
+        Some((123, None, Some("hello world")))
+    );
+    assert_eq!(
+        commands::parse_add_user_args("  123   @alice  测试用户  "),
+        Some((123, Some("alice"), Some("测试用户")))
+    );
+}
+
+#[test]
+fn adduser_rejects_invalid_ids() {
+    assert_eq!(commands::parse_add_user_args(""), None);
+    assert_eq!(commands::parse_add_user_args("abc hello"), None);
+    assert_eq!(commands::parse_add_user_args("-5"), None);
+    assert_eq!(commands::parse_add_user_args("@alice 123"), None);
+}
+
+#[test]
+fn adduser_notes_follow_the_id() {
+    // 只有用户ID
+    assert_eq!(
+        commands::parse_add_user_args("12345"),
+        Some((12345, None, None))
+    );
+    // 一个词的备注
+    assert_eq!(
+        commands::parse_add_user_args("12345 朋友"),
+        Some((12345, None, Some("朋友")))
+    );
+    // 多个词的备注保持原样
+    assert_eq!(
+        commands::parse_add_user_args("12345 my old friend"),
+        Some((12345, None, Some("my old friend")))
+    );
+    // 用户ID后面紧跟其他字符时不是有效的ID
+    assert_eq!(commands::parse_add_user_args("12345abc note"), None);
+}
+
+#[test]
+fn translate_args_split_the_language_code_from_the_text() {
+    assert_eq!(
+        commands::parse_translate_args(" en 你好，世界 "),
+        (Some("en"), Some("你好，世界".to_string()))
+    );
+    // 文本中的换行保留原样
+    assert_eq!(
+        commands::parse_translate_args("ja 第一行\n第二行"),
+        (Some("ja"), Some("第一行\n第二行".to_string()))
+    );
+    // 只有语言代码时由被回复的消息提供文本
+    assert_eq!(
+        commands::parse_translate_args("zh-TW"),
+        (Some("zh-TW"), None)
+    );
+    assert_eq!(commands::parse_translate_args("   "), (None, None));
+}
+
+#[test]
+fn language_codes_are_validated() {
+    for code in ["en", "zh", "zh-CN", "pt-BR"] {
+        assert!(commands::is_valid_language_code(code), "{}", code);
+    }
+    for code in ["e", "中文", "en_US", "zh-hans-cn-x", "en;"] {
+        assert!(!commands::is_valid_language_code(code), "{}", code);
+    }
+}
+
+#[test]
+fn removeuser_arguments_accept_purge_in_any_position() {
+    assert_eq!(commands::parse_remove_user_args("123"), Some((123, false)));
+    assert_eq!(
+        commands::parse_remove_user_args("123 --purge"),
+        Some((123, true))
+    );
+    assert_eq!(
+        commands::parse_remove_user_args("--purge 123"),
+        Some((123, true))
+    );
+    assert_eq!(commands::parse_remove_user_args("--purge"), None);
+    assert_eq!(commands::parse_remove_user_args("123 456"), None);
+    assert_eq!(commands::parse_remove_user_args("abc"), None);
+}
+
+#[tokio::test]
+async fn reactivated_user_gets_the_new_username_and_notes() {
+    let pool = memory_pool().await;
+    WhitelistUser::add_user(
+        &pool,
+        12345,
+        Some("old_name"),
+        INITIAL_ADMIN_ID,
+        Some("旧备注"),
+    )
+    .await
+    .unwrap();
+
+    // /removeuser 12345 只停用，之后 /adduser 12345 @new_name 新备注 重新启用
+    assert!(WhitelistUser::deactivate(&pool, 12345).await.unwrap());
+    assert!(!WhitelistUser::is_user_whitelisted(&pool, 12345)
+        .await
+        .unwrap());
+    let (user_id, username, notes) =
+        commands::parse_add_user_args("12345 @new_name 新备注").unwrap();
+    assert!(WhitelistUser::reactivate(&pool, user_id, username, notes)
+        .await
+        .unwrap());
+    assert!(WhitelistUser::is_user_whitelisted(&pool, 12345)
+        .await
+        .unwrap());
+
+    let users = WhitelistUser::get_all_users(&pool, None).await.unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].username.as_deref(), Some("new_name"));
+    assert_eq!(users[0].notes.as_deref(), Some("新备注"));
+    // 保留原来的添加记录
+    assert_eq!(users[0].added_by, INITIAL_ADMIN_ID);
+
+    // 没有提供用户名和备注时保留原来的值；已启用的用户不会再次启用
+    WhitelistUser::deactivate(&pool, 12345).await.unwrap();
+    assert!(WhitelistUser::reactivate(&pool, 12345, None, None)
+        .await
+        .unwrap());
+    assert!(!WhitelistUser::reactivate(&pool, 12345, None, None)
+        .await
+        .unwrap());
+    let users = WhitelistUser::get_all_users(&pool, None).await.unwrap();
+    assert_eq!(users[0].username.as_deref(), Some("new_name"));
+    assert_eq!(users[0].notes.as_deref(), Some("新备注"));
+}
+
+#[tokio::test]
+async fn purged_user_is_added_again_as_a_new_record() {
+    let pool = memory_pool().await;
+    WhitelistUser::add_user(
+        &pool,
+        12345,
+        Some("old_name"),
+        INITIAL_ADMIN_ID,
+        Some("旧备注"),
+    )
+    .await
+    .unwrap();
+    WhitelistUser::deactivate(&pool, 12345).await.unwrap();
+
+    // /removeuser 12345 --purge 连同已停用的记录一起删除
+    let (user_id, purge) = commands::parse_remove_user_args("12345 --purge").unwrap();
+    assert!(purge);
+    assert!(WhitelistUser::remove_user(&pool, user_id).await.unwrap());
+    assert!(!WhitelistUser::is_user_whitelisted(&pool, 12345)
+        .await
+        .unwrap());
+
+    // 彻底删除后没有可以重新启用的记录，/adduser 会重新添加
+    assert!(
+        !WhitelistUser::reactivate(&pool, 12345, Some("new_name"), None)
+            .await
+            .unwrap()
+    );
+    WhitelistUser::add_user(&pool, 12345, Some("new_name"), 2000, None)
+        .await
+        .unwrap();
+    assert!(WhitelistUser::is_user_whitelisted(&pool, 12345)
+        .await
+        .unwrap());
+    let users = WhitelistUser::get_all_users(&pool, None).await.unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].username.as_deref(), Some("new_name"));
+    assert_eq!(users[0].notes, None);
+    assert_eq!(users[0].added_by, 2000);
+}
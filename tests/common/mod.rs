@@ -0,0 +1,55 @@
+use gpt_bot_rs::db::{self, DatabasePool};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+// 测试数据库中的初始超级管理员
+pub const INITIAL_ADMIN_ID: u64 = 1000;
+
+static ENV: Once = Once::new();
+
+fn init_env() {
+    ENV.call_once(|| {
+        std::env::set_var("DATABASE_URL", "sqlite::memory:");
+        std::env::set_var("ADMIN_USER_IDS", INITIAL_ADMIN_ID.to_string());
+    });
+}
+
+// 每次调用都会得到一个新的空内存数据库，测试之间互不影响
+pub async fn memory_pool() -> DatabasePool {
+    init_env();
+    db::init_db().await.expect("初始化内存数据库失败")
+}
+
+// 临时目录中的 SQLite 文件数据库，可以有多个连接，用于测试并发；被丢弃时删除数据库文件
+#[allow(dead_code)]
+pub struct FileDatabase {
+    pub pool: DatabasePool,
+    path: PathBuf,
+}
+
+#[allow(dead_code)]
+pub async fn file_pool() -> FileDatabase {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    init_env();
+
+    let path = std::env::temp_dir().join(format!(
+        "gpt_bot_rs_test_{}_{}.db",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    let pool = db::connect(&format!("sqlite:{}?mode=rwc", path.display()))
+        .await
+        .expect("初始化文件数据库失败");
+    FileDatabase { pool, path }
+}
+
+impl Drop for FileDatabase {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.path.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
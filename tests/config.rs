@@ -0,0 +1,21 @@
+use gpt_bot_rs::config;
+
+// 环境变量在同一个测试进程中共享，各种设置放在同一个测试中依次检查
+#[test]
+fn history_limit_prefers_chat_setting_then_env_then_default() {
+    std::env::remove_var("HISTORY_LIMIT");
+    assert_eq!(config::history_limit(), 10);
+    assert_eq!(config::effective_history_limit(None), 10);
+
+    std::env::set_var("HISTORY_LIMIT", "20");
+    assert_eq!(config::effective_history_limit(None), 20);
+    // /context 的设置优先于 HISTORY_LIMIT
+    assert_eq!(config::effective_history_limit(Some(5)), 5);
+
+    // 超出范围时限制在 1 到 MAX_HISTORY_LIMIT 之间，无法解析时使用默认值
+    std::env::set_var("HISTORY_LIMIT", "999");
+    assert_eq!(config::history_limit(), config::MAX_HISTORY_LIMIT);
+    assert_eq!(config::effective_history_limit(Some(0)), 1);
+    std::env::set_var("HISTORY_LIMIT", "many");
+    assert_eq!(config::history_limit(), 10);
+}
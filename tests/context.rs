@@ -0,0 +1,32 @@
+use gpt_bot_rs::context::{estimate_tokens, split_transcript};
+
+fn lines(texts: &[&str]) -> Vec<String> {
+    texts.iter().map(|text| text.to_string()).collect()
+}
+
+#[test]
+fn short_transcript_stays_in_one_chunk() {
+    let chunks = split_transcript(&lines(&["user: 你好", "assistant: 你好！"]), 100);
+    assert_eq!(chunks, vec!["user: 你好\nassistant: 你好！".to_string()]);
+}
+
+#[test]
+fn long_transcript_is_split_within_the_budget() {
+    let transcript: Vec<String> = (0..50)
+        .map(|i| format!("user: 第{}条消息的内容", i))
+        .collect();
+    let chunks = split_transcript(&transcript, 40);
+
+    assert!(chunks.len() > 1);
+    for chunk in &chunks {
+        assert!(estimate_tokens(chunk) <= 40, "{}", chunk);
+    }
+    // 分段不丢失任何一行
+    assert_eq!(chunks.join("\n"), transcript.join("\n"));
+}
+
+#[test]
+fn oversized_line_is_truncated() {
+    let chunks = split_transcript(&lines(&["长".repeat(100).as_str()]), 10);
+    assert_eq!(chunks, vec!["长".repeat(10)]);
+}
@@ -0,0 +1,34 @@
+use gpt_bot_rs::cooldown::CommandCooldowns;
+use std::time::Duration;
+
+#[test]
+fn cooldown_starts_only_when_marked_and_is_per_user_and_command() {
+    let cooldowns = CommandCooldowns::new(Duration::from_secs(30));
+
+    // 只检查不会占用冷却时间（例如参数错误提前返回）
+    assert_eq!(cooldowns.remaining(1, "export"), None);
+    assert_eq!(cooldowns.remaining(1, "export"), None);
+
+    cooldowns.mark_used(1, "export");
+    let remaining = cooldowns
+        .remaining(1, "export")
+        .expect("标记后应处于冷却中");
+    assert!(remaining > Duration::from_secs(29) && remaining <= Duration::from_secs(30));
+
+    // 其他命令和其他用户不受影响
+    assert_eq!(cooldowns.remaining(1, "summary"), None);
+    assert_eq!(cooldowns.remaining(2, "export"), None);
+}
+
+#[test]
+fn cooldown_expires_and_zero_disables_it() {
+    let cooldowns = CommandCooldowns::new(Duration::from_millis(20));
+    cooldowns.mark_used(1, "export");
+    assert!(cooldowns.remaining(1, "export").is_some());
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(cooldowns.remaining(1, "export"), None);
+
+    let disabled = CommandCooldowns::new(Duration::ZERO);
+    disabled.mark_used(1, "export");
+    assert_eq!(disabled.remaining(1, "export"), None);
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use common::memory_pool;
+use gpt_bot_rs::db::{self, QueryRows};
+use gpt_bot_rs::i18n::Lang;
+use gpt_bot_rs::models::{Message, Session};
+
+#[test]
+fn only_single_select_statements_are_accepted() {
+    assert_eq!(
+        db::read_only_statement("  select * from sessions; "),
+        Some("select * from sessions")
+    );
+    assert_eq!(db::read_only_statement("DELETE FROM sessions"), None);
+    assert_eq!(
+        db::read_only_statement("insert into sessions (chat_id) values (1)"),
+        None
+    );
+    assert_eq!(
+        db::read_only_statement(
+            "WITH gone AS (DELETE FROM sessions RETURNING *) SELECT * FROM gone"
+        ),
+        None
+    );
+    assert_eq!(
+        db::read_only_statement("SELECT 1; DROP TABLE sessions"),
+        None
+    );
+    assert_eq!(db::read_only_statement("   "), None);
+}
+
+#[tokio::test]
+async fn query_rows_stringifies_values_and_counts_omitted_rows() {
+    let pool = memory_pool().await;
+    for chat_id in [1, 2, 3] {
+        Session::find_or_create_by_chat_id(&pool, chat_id)
+            .await
+            .unwrap();
+    }
+
+    let result = pool
+        .query_rows(
+            "SELECT chat_id, model, 1.5 AS ratio FROM sessions ORDER BY chat_id",
+            2,
+        )
+        .await
+        .unwrap();
+    assert_eq!(result.columns, ["chat_id", "model", "ratio"]);
+    assert_eq!(result.rows, [["1", "NULL", "1.5"], ["2", "NULL", "1.5"]]);
+    assert_eq!(result.omitted, 1);
+
+    assert!(pool.query_rows("DELETE FROM sessions", 10).await.is_err());
+    assert_eq!(
+        pool.query_rows("SELECT * FROM sessions", 10)
+            .await
+            .unwrap()
+            .rows
+            .len(),
+        3
+    );
+}
+
+#[test]
+fn table_note_reports_rows_that_were_not_shown() {
+    let rows = QueryRows {
+        columns: vec!["id".to_string(), "name".to_string()],
+        rows: vec![
+            vec!["1".to_string(), "alice".to_string()],
+            vec!["2".to_string(), "bob".to_string()],
+        ],
+        omitted: 3,
+    };
+    let table = rows.to_table();
+    assert_eq!(table.text, "id | name\n---+------\n1  | alice\n2  | bob");
+    assert_eq!((table.shown, table.omitted), (2, 3));
+    assert_eq!(table.note(Lang::Zh), "共显示 2 行，另有 3 行未显示");
+    assert_eq!(table.note(Lang::En), "Showing 2 rows, 3 more not shown");
+
+    let complete = QueryRows { omitted: 0, ..rows };
+    assert_eq!(complete.to_table().note(Lang::Zh), "共 2 行");
+    assert_eq!(complete.to_table().note(Lang::En), "2 rows in total");
+}
+
+#[test]
+fn long_tables_show_fewer_rows() {
+    let rows = QueryRows {
+        columns: vec!["text".to_string()],
+        rows: (0..200).map(|_| vec!["x".repeat(100)]).collect(),
+        omitted: 0,
+    };
+    let table = rows.to_table();
+    assert!(table.text.chars().count() <= 3000);
+    assert!(table.shown < 200);
+    assert_eq!(table.shown + table.omitted, 200);
+    // 单元格被截断
+    assert!(table.text.lines().nth(2).unwrap().ends_with('…'));
+}
+
+#[tokio::test]
+async fn sqlite_connections_enforce_foreign_keys_and_use_wal() {
+    let database = common::file_pool().await;
+    let pool = &database.pool;
+
+    let pragma = |sql: &'static str| async move {
+        pool.query_rows(sql, 1).await.unwrap().rows[0][0].clone()
+    };
+    assert_eq!(pragma("SELECT * FROM pragma_foreign_keys").await, "1");
+    assert_eq!(pragma("SELECT * FROM pragma_journal_mode").await, "wal");
+}
+
+#[tokio::test]
+async fn orphaned_messages_are_rejected() {
+    let pool = memory_pool().await;
+
+    // 不存在的会话
+    assert!(Message::create(&pool, 9999, "user", "孤立的消息")
+        .await
+        .is_err());
+
+    // 存在的会话可以正常写入
+    let session_id = Session::find_or_create_by_chat_id(&pool, 1).await.unwrap();
+    Message::create(&pool, session_id, "user", "你好")
+        .await
+        .unwrap();
+
+    // 还有消息的会话不能被直接删除，避免留下孤立的消息
+    assert!(pool
+        .execute(&format!("DELETE FROM sessions WHERE id = {}", session_id))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn migrations_run_once_and_record_every_version() {
+    // 不经过 db::connect，从空数据库开始执行迁移
+    let sqlite = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    let pool = db::DatabasePool::Sqlite(sqlite);
+
+    let applied = db::run_migrations(&pool).await.unwrap();
+    assert!(applied > 0);
+    // 再次执行时没有需要执行的迁移，也不会报错
+    assert_eq!(db::run_migrations(&pool).await.unwrap(), 0);
+
+    // 每个版本只记录一次，版本号从 1 开始连续
+    let versions = pool
+        .query_rows("SELECT version FROM schema_version ORDER BY version", 1000)
+        .await
+        .unwrap();
+    let expected: Vec<Vec<String>> = (1..=applied).map(|v| vec![v.to_string()]).collect();
+    assert_eq!(versions.rows, expected);
+
+    // 迁移后的表结构可以正常使用
+    let session_id = Session::find_or_create_by_chat_id(&pool, 1).await.unwrap();
+    Message::create(&pool, session_id, "user", "你好")
+        .await
+        .unwrap();
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use common::memory_pool;
+use gpt_bot_rs::i18n::{self, t, Key, Lang};
+use gpt_bot_rs::models::Session;
+
+#[tokio::test]
+async fn default_group_greeting_follows_the_chat_language() {
+    let pool = memory_pool().await;
+    let chat_id = -100123;
+
+    assert_eq!(
+        i18n::group_greeting(&pool, chat_id, None).await.as_deref(),
+        Some(t(Lang::Zh, Key::GroupGreeting))
+    );
+
+    Session::set_ui_lang(&pool, chat_id, "en").await.unwrap();
+    assert_eq!(
+        i18n::group_greeting(&pool, chat_id, None).await.as_deref(),
+        Some(t(Lang::En, Key::GroupGreeting))
+    );
+}
+
+#[tokio::test]
+async fn configured_group_greeting_overrides_the_default() {
+    let pool = memory_pool().await;
+    let chat_id = -100123;
+    Session::set_ui_lang(&pool, chat_id, "en").await.unwrap();
+
+    assert_eq!(
+        i18n::group_greeting(&pool, chat_id, Some("欢迎！"))
+            .await
+            .as_deref(),
+        Some("欢迎！")
+    );
+    // 设置为空时不发送
+    assert_eq!(i18n::group_greeting(&pool, chat_id, Some("")).await, None);
+    assert_eq!(i18n::group_greeting(&pool, chat_id, Some("  ")).await, None);
+}
+
+// 文字中的 {name} 占位符
+fn placeholders(text: &str) -> Vec<&str> {
+    let mut names: Vec<&str> = text
+        .split('{')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+#[test]
+fn every_key_has_chinese_and_english_text() {
+    for &key in Key::ALL {
+        let zh = t(Lang::Zh, key);
+        let en = t(Lang::En, key);
+        assert!(!zh.trim().is_empty(), "{:?} 缺少中文", key);
+        assert!(!en.trim().is_empty(), "{:?} 缺少英文", key);
+        // 英文文字中不应残留未翻译的中文
+        assert!(
+            !en.chars().any(|c| ('\u{4e00}'..='\u{9fff}').contains(&c)),
+            "{:?} 的英文文字包含中文: {}",
+            key,
+            en
+        );
+        assert_eq!(
+            placeholders(zh),
+            placeholders(en),
+            "{:?} 的占位符不一致",
+            key
+        );
+    }
+}
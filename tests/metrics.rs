@@ -0,0 +1,19 @@
+use gpt_bot_rs::metrics::Metrics;
+use std::time::Duration;
+
+#[test]
+fn observed_requests_appear_in_the_encoded_metrics() {
+    let metrics = Metrics::default();
+    metrics.observe("chat", true, Duration::from_millis(300));
+    metrics.observe("chat", false, Duration::from_secs(2));
+    metrics.observe("transcription", true, Duration::from_secs(1));
+
+    // 克隆后共用同一份统计
+    let text = metrics.clone().encode();
+    assert!(text.contains(r#"openai_requests_total{kind="chat",outcome="success"} 1"#));
+    assert!(text.contains(r#"openai_requests_total{kind="chat",outcome="error"} 1"#));
+    assert!(text.contains(r#"openai_requests_total{kind="transcription",outcome="success"} 1"#));
+    assert!(text.contains(r#"openai_request_duration_seconds_count{kind="chat"} 2"#));
+    assert!(text.contains(r#"openai_request_duration_seconds_sum{kind="transcription"} 1.0"#));
+    assert!(text.ends_with("# EOF\n"));
+}
@@ -0,0 +1,366 @@
+mod common;
+
+use common::{memory_pool, INITIAL_ADMIN_ID};
+use gpt_bot_rs::db;
+use gpt_bot_rs::models::{Admin, Message, MessageMeta, Session, WhitelistUser};
+
+#[tokio::test]
+async fn init_schema_can_run_again_on_an_existing_pool() {
+    let pool = memory_pool().await;
+    db::init_schema(&pool).await.unwrap();
+
+    // 重复执行不会重复添加初始管理员
+    assert_eq!(Admin::get_all_admins(&pool).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn find_or_create_reuses_the_session_of_a_chat() {
+    let pool = memory_pool().await;
+
+    let first = Session::find_or_create_by_chat_id(&pool, 42).await.unwrap();
+    let again = Session::find_or_create_by_chat_id(&pool, 42).await.unwrap();
+    let other = Session::find_or_create_by_chat_id(&pool, -100)
+        .await
+        .unwrap();
+
+    assert_eq!(first, again);
+    assert_ne!(first, other);
+}
+
+#[tokio::test]
+async fn recent_messages_are_limited_and_in_order() {
+    let pool = memory_pool().await;
+    let session_id = Session::find_or_create_by_chat_id(&pool, 42).await.unwrap();
+
+    for (role, content) in [
+        ("user", "第一个问题"),
+        ("assistant", "第一个回答"),
+        ("user", "第二个问题"),
+        ("assistant", "第二个回答"),
+    ] {
+        Message::create(&pool, session_id, role, content)
+            .await
+            .unwrap();
+    }
+
+    let recent = Message::get_recent_messages(&pool, session_id, 3)
+        .await
+        .unwrap();
+    let recent: Vec<(&str, &str)> = recent
+        .iter()
+        .map(|msg| (msg.role.as_str(), msg.content.as_str()))
+        .collect();
+    assert_eq!(
+        recent,
+        vec![
+            ("assistant", "第一个回答"),
+            ("user", "第二个问题"),
+            ("assistant", "第二个回答"),
+        ]
+    );
+
+    // 其他会话的消息不会混进来
+    let other = Session::find_or_create_by_chat_id(&pool, 7).await.unwrap();
+    assert!(Message::get_recent_messages(&pool, other, 10)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn whitelist_users_can_be_added_deactivated_and_removed() {
+    let pool = memory_pool().await;
+
+    assert!(!WhitelistUser::is_user_whitelisted(&pool, 5).await.unwrap());
+    WhitelistUser::add_user(&pool, 5, Some("alice"), INITIAL_ADMIN_ID, Some("测试"))
+        .await
+        .unwrap();
+    assert!(WhitelistUser::is_user_whitelisted(&pool, 5).await.unwrap());
+
+    let users = WhitelistUser::get_all_users(&pool, None).await.unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].user_id, 5);
+    assert_eq!(users[0].username.as_deref(), Some("alice"));
+    assert_eq!(users[0].added_by, INITIAL_ADMIN_ID);
+    assert!(users[0].is_active);
+
+    // 停用后保留记录，但不再有权限
+    assert!(WhitelistUser::deactivate(&pool, 5).await.unwrap());
+    assert!(!WhitelistUser::deactivate(&pool, 5).await.unwrap());
+    assert!(!WhitelistUser::is_user_whitelisted(&pool, 5).await.unwrap());
+    assert!(WhitelistUser::get_all_users(&pool, Some(true))
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        WhitelistUser::get_all_users(&pool, Some(false))
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    assert!(WhitelistUser::reactivate(&pool, 5, None, None)
+        .await
+        .unwrap());
+    assert!(WhitelistUser::is_user_whitelisted(&pool, 5).await.unwrap());
+
+    assert!(WhitelistUser::remove_user(&pool, 5).await.unwrap());
+    assert!(!WhitelistUser::remove_user(&pool, 5).await.unwrap());
+    assert!(WhitelistUser::get_all_users(&pool, None)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn admins_can_be_added_promoted_and_removed() {
+    let pool = memory_pool().await;
+
+    // ADMIN_USER_IDS 中的用户在初始化时成为超级管理员
+    assert!(Admin::is_super_admin(&pool, INITIAL_ADMIN_ID)
+        .await
+        .unwrap());
+    assert_eq!(Admin::count_super_admins(&pool).await.unwrap(), 1);
+
+    Admin::add_admin(&pool, 2000, Some("bob"), false)
+        .await
+        .unwrap();
+    assert!(Admin::is_admin(&pool, 2000).await.unwrap());
+    assert!(!Admin::is_super_admin(&pool, 2000).await.unwrap());
+
+    Admin::set_super_admin(&pool, 2000, true).await.unwrap();
+    assert!(Admin::is_super_admin(&pool, 2000).await.unwrap());
+    assert_eq!(Admin::count_super_admins(&pool).await.unwrap(), 2);
+
+    // 提升后两位都是超级管理员
+    let admins = Admin::get_all_admins(&pool).await.unwrap();
+    assert_eq!(admins.len(), 2);
+    assert!(admins.iter().all(|admin| admin.is_super));
+
+    assert!(Admin::remove_admin(&pool, 2000).await.unwrap());
+    assert!(!Admin::remove_admin(&pool, 2000).await.unwrap());
+    assert!(!Admin::is_admin(&pool, 2000).await.unwrap());
+    assert_eq!(Admin::count_super_admins(&pool).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn negative_group_chat_ids_round_trip() {
+    let pool = memory_pool().await;
+    let group_id: i64 = -1001234567890;
+
+    let group = Session::find_or_create_by_chat_id(&pool, group_id)
+        .await
+        .unwrap();
+    // 绝对值相同的私聊是另一个会话
+    let private = Session::find_or_create_by_chat_id(&pool, -group_id)
+        .await
+        .unwrap();
+    assert_ne!(group, private);
+
+    let session = Session::get_by_chat_id(&pool, group_id)
+        .await
+        .unwrap()
+        .expect("群组会话应该存在");
+    assert_eq!(session.id, group);
+    assert_eq!(session.chat_id, group_id);
+
+    Session::set_model(&pool, group_id, Some("gpt-4o"))
+        .await
+        .unwrap();
+    assert_eq!(
+        Session::get_model(&pool, group_id)
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("gpt-4o")
+    );
+    assert_eq!(Session::get_model(&pool, -group_id).await.unwrap(), None);
+
+    Message::create(&pool, group, "user", "群组里的问题")
+        .await
+        .unwrap();
+    let exported = Message::get_all_messages_by_chat_id(&pool, group_id, 0, 10)
+        .await
+        .unwrap();
+    assert_eq!(exported.len(), 1);
+    assert_eq!(exported[0].content, "群组里的问题");
+
+    let mut chat_ids = Session::get_all_chat_ids(&pool).await.unwrap();
+    chat_ids.sort();
+    assert_eq!(chat_ids, vec![group_id, -group_id]);
+}
+
+#[tokio::test]
+async fn bot_name_can_be_set_and_cleared() {
+    let pool = memory_pool().await;
+
+    assert_eq!(Session::get_bot_name(&pool, 42).await.unwrap(), None);
+    Session::set_bot_name(&pool, 42, Some("小助手"))
+        .await
+        .unwrap();
+    assert_eq!(
+        Session::get_bot_name(&pool, 42).await.unwrap().as_deref(),
+        Some("小助手")
+    );
+    assert_eq!(Session::get_bot_name(&pool, 43).await.unwrap(), None);
+
+    Session::set_bot_name(&pool, 42, None).await.unwrap();
+    assert_eq!(Session::get_bot_name(&pool, 42).await.unwrap(), None);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_creates_for_a_new_chat_produce_one_session() {
+    let database = common::file_pool().await;
+
+    let creates: Vec<_> = (0..8)
+        .map(|_| {
+            let pool = database.pool.clone();
+            tokio::spawn(async move { Session::find_or_create_by_chat_id(&pool, 777).await })
+        })
+        .collect();
+    let mut ids = Vec::new();
+    for create in creates {
+        ids.push(create.await.unwrap().unwrap());
+    }
+
+    assert!(ids.iter().all(|id| *id == ids[0]));
+    let count = database
+        .pool
+        .query_rows("SELECT COUNT(*) FROM sessions WHERE chat_id = 777", 1)
+        .await
+        .unwrap();
+    assert_eq!(count.rows, [["1"]]);
+}
+
+#[tokio::test]
+async fn failed_clear_rolls_back_deleted_messages() {
+    let pool = memory_pool().await;
+    let session_id = Session::find_or_create_by_chat_id(&pool, 55).await.unwrap();
+    Message::create(&pool, session_id, "user", "你好")
+        .await
+        .unwrap();
+    Message::create(&pool, session_id, "assistant", "你好！")
+        .await
+        .unwrap();
+
+    // 让删除 AI 回复这一步失败，此时用户消息已经在同一条语句中删除
+    pool.execute(
+        "CREATE TRIGGER fail_message_delete BEFORE DELETE ON messages
+         WHEN old.role = 'assistant'
+         BEGIN SELECT RAISE(ABORT, 'injected failure'); END",
+    )
+    .await
+    .unwrap();
+    assert!(Session::clear_history_by_chat_id(&pool, 55).await.is_err());
+
+    // 整条语句回滚，消息都还在
+    let messages = Message::get_session_messages(&pool, session_id)
+        .await
+        .unwrap();
+    assert_eq!(messages.len(), 2);
+    assert!(Session::get_by_chat_id(&pool, 55).await.unwrap().is_some());
+
+    // 失败原因消除后可以正常清除
+    pool.execute("DROP TRIGGER fail_message_delete")
+        .await
+        .unwrap();
+    Session::clear_history_by_chat_id(&pool, 55).await.unwrap();
+    assert!(Message::get_session_messages(&pool, session_id)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn clear_keeps_the_chat_settings() {
+    let pool = memory_pool().await;
+    let session_id = Session::find_or_create_by_chat_id(&pool, 55).await.unwrap();
+    Message::create(&pool, session_id, "user", "你好")
+        .await
+        .unwrap();
+    Session::set_show_timestamps(&pool, 55, true).await.unwrap();
+    Session::set_model(&pool, 55, Some("gpt-4o")).await.unwrap();
+    Session::set_ui_lang(&pool, 55, "en").await.unwrap();
+    Session::set_history_limit(&pool, 55, Some(5))
+        .await
+        .unwrap();
+
+    Session::clear_history_by_chat_id(&pool, 55).await.unwrap();
+
+    // 只删除消息，会话和设置都保留
+    assert!(Message::get_session_messages(&pool, session_id)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        Session::find_or_create_by_chat_id(&pool, 55).await.unwrap(),
+        session_id
+    );
+    assert!(Session::get_show_timestamps(&pool, 55).await.unwrap());
+    assert_eq!(
+        Session::get_model(&pool, 55).await.unwrap().as_deref(),
+        Some("gpt-4o")
+    );
+    assert_eq!(
+        Session::get_ui_lang(&pool, 55).await.unwrap().as_deref(),
+        Some("en")
+    );
+    assert_eq!(
+        Session::get_history_limit(&pool, 55).await.unwrap(),
+        Some(5)
+    );
+}
+
+#[tokio::test]
+async fn exchange_is_found_by_the_telegram_id_of_the_reply() {
+    let pool = memory_pool().await;
+    let session_id = Session::find_or_create_by_chat_id(&pool, 42).await.unwrap();
+
+    for (role, content, tg_message_id) in [
+        ("user", "第一个问题", 10),
+        ("assistant", "第一个回答", 11),
+        ("user", "第二个问题", 12),
+        ("assistant", "第二个回答", 13),
+    ] {
+        let meta = MessageMeta {
+            tg_message_id: Some(tg_message_id),
+            ..Default::default()
+        };
+        Message::create_with_meta(&pool, session_id, role, content, &meta)
+            .await
+            .unwrap();
+    }
+
+    let exchange = |tg_message_id| {
+        let pool = pool.clone();
+        async move {
+            Message::get_exchange_by_tg_id(&pool, session_id, tg_message_id)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|msg| (msg.role, msg.content))
+                .collect::<Vec<_>>()
+        }
+    };
+
+    // 较早的一轮对话：回复和它之前的提问，按时间顺序
+    assert_eq!(
+        exchange(11).await,
+        vec![
+            ("user".to_string(), "第一个问题".to_string()),
+            ("assistant".to_string(), "第一个回答".to_string()),
+        ]
+    );
+    assert_eq!(exchange(13).await[0].1, "第二个问题");
+    // 用户自己的消息和没有记录的消息都找不到
+    assert!(exchange(10).await.is_empty());
+    assert!(exchange(99).await.is_empty());
+
+    // 其他会话中相同的消息ID不会匹配
+    let other = Session::find_or_create_by_chat_id(&pool, 7).await.unwrap();
+    assert!(Message::get_exchange_by_tg_id(&pool, other, 11)
+        .await
+        .unwrap()
+        .is_empty());
+}
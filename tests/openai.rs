@@ -0,0 +1,65 @@
+use gpt_bot_rs::api_keys::ApiKeys;
+use gpt_bot_rs::openai::{ChatCompletion, OpenAiClient};
+use std::time::Duration;
+
+#[test]
+fn scoped_requests_carry_org_and_project_headers() {
+    std::env::set_var("OPENAI_ORG_ID", " org-test ");
+    std::env::set_var("OPENAI_PROJECT_ID", "proj_test");
+
+    let keys = ApiKeys::new(vec!["sk-test".to_string()]).unwrap();
+    let client = OpenAiClient::new(keys, Duration::from_secs(5)).unwrap();
+
+    let scoped = client
+        .scoped(client.http.get("http://localhost/v1/models"))
+        .build()
+        .unwrap();
+    assert_eq!(scoped.headers()["OpenAI-Organization"], "org-test");
+    assert_eq!(scoped.headers()["OpenAI-Project"], "proj_test");
+
+    // 没有经过 scoped 的请求（PROVIDERS 中的其他服务）保持不变
+    let plain = client
+        .http
+        .get("http://localhost/v1/models")
+        .build()
+        .unwrap();
+    assert!(plain.headers().get("OpenAI-Organization").is_none());
+    assert!(plain.headers().get("OpenAI-Project").is_none());
+}
+
+#[test]
+fn reply_text_is_read_from_string_content() {
+    let completion: ChatCompletion = serde_json::from_str(
+        r#"{"choices": [{"message": {"role": "assistant", "content": "你好"}, "finish_reason": "stop"}]}"#,
+    )
+    .unwrap();
+    let choice = &completion.choices[0];
+    assert_eq!(choice.message.text().as_deref(), Some("你好"));
+    assert_eq!(choice.finish_reason.as_deref(), Some("stop"));
+}
+
+#[test]
+fn reply_text_concatenates_text_parts() {
+    let completion: ChatCompletion = serde_json::from_str(
+        r#"{"choices": [{"message": {"role": "assistant", "content": [
+            {"type": "text", "text": "第一段，"},
+            {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}},
+            {"type": "text", "text": "第二段"}
+        ]}}]}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        completion.choices[0].message.text().as_deref(),
+        Some("第一段，第二段")
+    );
+
+    // 没有文本片段或没有 content 时没有回复内容
+    let empty: ChatCompletion = serde_json::from_str(
+        r#"{"choices": [{"message": {"content": [{"type": "image_url"}]}}, {"message": {}}]}"#,
+    )
+    .unwrap();
+    assert!(empty
+        .choices
+        .iter()
+        .all(|choice| choice.message.text().is_none()));
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::memory_pool;
+use gpt_bot_rs::i18n::{t, Key, Lang};
+use gpt_bot_rs::models::{Message, Session};
+use gpt_bot_rs::privacy;
+use teloxide::types::InlineKeyboardButtonKind;
+
+#[tokio::test]
+async fn revoking_consent_deletes_messages_but_keeps_settings() {
+    let pool = memory_pool().await;
+    let session_id = Session::find_or_create_by_chat_id(&pool, 42).await.unwrap();
+    Session::set_privacy_consent(&pool, 42, privacy::GRANTED)
+        .await
+        .unwrap();
+    Session::set_model(&pool, 42, Some("gpt-4o")).await.unwrap();
+    Session::set_ui_lang(&pool, 42, "en").await.unwrap();
+    Message::create(&pool, session_id, "user", "你好")
+        .await
+        .unwrap();
+
+    privacy::revoke(&pool, 42).await.unwrap();
+
+    assert!(Message::get_session_messages(&pool, session_id)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        Session::get_privacy_consent(&pool, 42)
+            .await
+            .unwrap()
+            .as_deref(),
+        Some(privacy::DECLINED)
+    );
+    assert_eq!(
+        Session::get_model(&pool, 42).await.unwrap().as_deref(),
+        Some("gpt-4o")
+    );
+    assert_eq!(
+        Session::get_ui_lang(&pool, 42).await.unwrap().as_deref(),
+        Some("en")
+    );
+}
+
+#[test]
+fn consent_texts_follow_the_interface_language() {
+    assert_eq!(
+        privacy::status_text(Lang::En, Some(privacy::GRANTED)),
+        t(Lang::En, Key::PrivacyStatusGranted)
+    );
+    assert_eq!(
+        privacy::status_text(Lang::Zh, None),
+        t(Lang::Zh, Key::PrivacyStatusPending)
+    );
+
+    let keyboard = privacy::consent_keyboard(Lang::En);
+    let buttons: Vec<(&str, &InlineKeyboardButtonKind)> = keyboard.inline_keyboard[0]
+        .iter()
+        .map(|button| (button.text.as_str(), &button.kind))
+        .collect();
+    assert_eq!(
+        buttons,
+        vec![
+            (
+                "✅ Agree",
+                &InlineKeyboardButtonKind::CallbackData(privacy::CALLBACK_GRANT.to_string())
+            ),
+            (
+                "❌ Disagree",
+                &InlineKeyboardButtonKind::CallbackData(privacy::CALLBACK_DECLINE.to_string())
+            ),
+        ]
+    );
+    assert_eq!(
+        privacy::revoke_keyboard(Lang::Zh).inline_keyboard[0][0].text,
+        t(Lang::Zh, Key::PrivacyRevokeButton)
+    );
+}
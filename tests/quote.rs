@@ -0,0 +1,111 @@
+mod common;
+
+use common::memory_pool;
+use gpt_bot_rs::models::{Message, MessageMeta, Session};
+use gpt_bot_rs::quote::{quoted_context, reply_quoted_context, with_quoted_context};
+use serde_json::{json, Value};
+
+const CHAT_ID: i64 = 42;
+// 机器人那条回答的 Telegram 消息ID
+const ANSWER_ID: i64 = 11;
+
+fn chat() -> Value {
+    json!({ "id": CHAT_ID, "type": "private", "first_name": "test" })
+}
+
+// 回复 reply_to 的一条文本消息，quote 为引用的片段
+fn reply_message(reply_to: Value, quote: Option<&str>) -> teloxide::types::Message {
+    let mut message = json!({
+        "message_id": 20,
+        "date": 0,
+        "chat": chat(),
+        "from": { "id": 7, "is_bot": false, "first_name": "user" },
+        "text": "为什么？",
+        "reply_to_message": reply_to,
+    });
+    if let Some(quote) = quote {
+        message["quote"] = json!({ "text": quote, "position": 0 });
+    }
+    serde_json::from_value(message).unwrap()
+}
+
+fn bot_answer() -> Value {
+    json!({
+        "message_id": ANSWER_ID,
+        "date": 0,
+        "chat": chat(),
+        "from": { "id": 999, "is_bot": true, "first_name": "bot" },
+        "text": "天空是蓝色的。",
+    })
+}
+
+async fn save_exchange(pool: &gpt_bot_rs::db::DatabasePool) {
+    let session_id = Session::find_or_create_by_chat_id(pool, CHAT_ID)
+        .await
+        .unwrap();
+    for (role, content, tg_message_id) in [
+        ("user", "天空是什么颜色？", 10),
+        ("assistant", "天空是蓝色的。", ANSWER_ID),
+    ] {
+        let meta = MessageMeta {
+            tg_message_id: Some(tg_message_id),
+            ..Default::default()
+        };
+        Message::create_with_meta(pool, session_id, role, content, &meta)
+            .await
+            .unwrap();
+    }
+}
+
+#[test]
+fn quoted_context_prefers_the_quoted_part() {
+    let msg = reply_message(bot_answer(), Some("蓝色"));
+    assert_eq!(quoted_context(&msg).as_deref(), Some("蓝色"));
+
+    let msg = reply_message(bot_answer(), None);
+    assert_eq!(quoted_context(&msg).as_deref(), Some("天空是蓝色的。"));
+    assert_eq!(
+        with_quoted_context(Some("天空是蓝色的。"), "为什么？"),
+        "引用内容：\n天空是蓝色的。\n\n为什么？"
+    );
+    assert_eq!(with_quoted_context(Some("  "), "为什么？"), "为什么？");
+}
+
+#[tokio::test]
+async fn replying_to_a_saved_answer_does_not_quote_it_again() {
+    let pool = memory_pool().await;
+    save_exchange(&pool).await;
+
+    // 那一轮对话会放进历史消息，不再作为引用重复发送
+    let msg = reply_message(bot_answer(), None);
+    assert_eq!(reply_quoted_context(&pool, &msg).await, None);
+
+    // 引用了其中一段时仍然保留
+    let msg = reply_message(bot_answer(), Some("蓝色"));
+    assert_eq!(
+        reply_quoted_context(&pool, &msg).await.as_deref(),
+        Some("蓝色")
+    );
+}
+
+#[tokio::test]
+async fn replies_without_a_saved_exchange_keep_the_quote() {
+    let pool = memory_pool().await;
+
+    // 没有保存的机器人回答（例如未同意保存消息）
+    let msg = reply_message(bot_answer(), None);
+    assert_eq!(
+        reply_quoted_context(&pool, &msg).await.as_deref(),
+        Some("天空是蓝色的。")
+    );
+
+    // 回复其他用户的消息
+    save_exchange(&pool).await;
+    let mut other = bot_answer();
+    other["from"] = json!({ "id": 8, "is_bot": false, "first_name": "other" });
+    let msg = reply_message(other, None);
+    assert_eq!(
+        reply_quoted_context(&pool, &msg).await.as_deref(),
+        Some("天空是蓝色的。")
+    );
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use common::memory_pool;
+use gpt_bot_rs::models::Refusal;
+use gpt_bot_rs::refusal;
+
+#[test]
+fn refusal_phrases_are_detected_at_the_start_of_a_reply() {
+    assert!(refusal::is_refusal(
+        "I'm sorry, but I can't help with that."
+    ));
+    assert!(refusal::is_refusal("抱歉，我无法回答这个问题。"));
+    assert!(!refusal::is_refusal("当然可以，以下是答案。"));
+}
+
+#[tokio::test]
+async fn refusals_without_privacy_consent_do_not_store_message_text() {
+    let pool = memory_pool().await;
+
+    // 已同意保存消息的聊天保留提问和回复
+    refusal::record(&pool, 1, "gpt-4o-mini", "问题", "抱歉，我无法回答", true)
+        .await
+        .unwrap();
+    // 未同意（拒绝或还没有回答）的聊天只记录发生过拒绝回答
+    refusal::record(
+        &pool,
+        2,
+        "gpt-4o-mini",
+        "秘密问题",
+        "抱歉，我无法回答",
+        false,
+    )
+    .await
+    .unwrap();
+
+    let recent = Refusal::get_recent(&pool, 10).await.unwrap();
+    let stored = recent.iter().find(|entry| entry.chat_id == 1).unwrap();
+    assert_eq!(stored.prompt, "问题");
+    let redacted = recent.iter().find(|entry| entry.chat_id == 2).unwrap();
+    assert_eq!(redacted.prompt, refusal::REDACTED);
+    assert_eq!(redacted.reply, refusal::REDACTED);
+}
@@ -0,0 +1,114 @@
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use gpt_bot_rs::openai::openai_request_with_retry;
+use gpt_bot_rs::retry::{self, RetryAction};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// 依次返回 responses 中的响应（最后一个重复使用），返回服务地址和请求计数
+async fn mock_server(responses: Vec<(u16, Option<&'static str>)>) -> (String, Arc<AtomicUsize>) {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+    let app = Router::new().route(
+        "/v1/chat/completions",
+        post(move || {
+            let index = counter.fetch_add(1, Ordering::SeqCst);
+            let (status, retry_after) = responses[index.min(responses.len() - 1)];
+            async move {
+                let mut headers = HeaderMap::new();
+                if let Some(retry_after) = retry_after {
+                    headers.insert("retry-after", retry_after.parse().unwrap());
+                }
+                (StatusCode::from_u16(status).unwrap(), headers, "{}")
+            }
+        }),
+    );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (format!("http://{}/v1/chat/completions", address), hits)
+}
+
+async fn send(url: &str) -> reqwest::Response {
+    let http = reqwest::Client::new();
+    openai_request_with_retry(None, |_| http.post(url).body("{}"))
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn retry_after_is_honored_instead_of_backoff() {
+    // 503 要求等待 1 秒，429 要求立即重试，之后成功
+    let (url, hits) = mock_server(vec![(503, Some("1")), (429, Some("0")), (200, None)]).await;
+
+    let started_at = Instant::now();
+    let response = send(&url).await;
+    let elapsed = started_at.elapsed();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(hits.load(Ordering::SeqCst), 3);
+    // 按退避等待至少需要 1 秒 + 2 秒
+    assert!(elapsed >= Duration::from_secs(1), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn transient_errors_stop_after_the_retry_limit() {
+    let (url, hits) = mock_server(vec![(500, Some("0"))]).await;
+
+    // 重试次数用尽后返回最后一次的响应
+    let response = send(&url).await;
+    assert_eq!(response.status(), 500);
+    assert_eq!(
+        hits.load(Ordering::SeqCst),
+        1 + retry::DEFAULT_MAX_RETRIES as usize
+    );
+}
+
+#[tokio::test]
+async fn other_client_errors_fail_fast() {
+    let (url, hits) = mock_server(vec![(400, None), (200, None)]).await;
+
+    let response = send(&url).await;
+    assert_eq!(response.status(), 400);
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn backoff_doubles_the_delay_for_each_retry() {
+    let base = Duration::from_millis(20);
+    let delays = Mutex::new(Vec::new());
+    let attempts = AtomicUsize::new(0);
+
+    let result: Result<(), &str> = retry::retry_with_progress(
+        3,
+        base,
+        || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err("503")
+        },
+        |_| RetryAction::Backoff,
+        |_, delay| {
+            delays.lock().unwrap().push(delay);
+            async {}
+        },
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert_eq!(attempts.load(Ordering::SeqCst), 4);
+    let delays = delays.into_inner().unwrap();
+    assert_eq!(delays.len(), 3);
+    for (retry, delay) in delays.iter().enumerate() {
+        // base * 2^n，再加上最多 base / 4 的抖动
+        let expected = base * 2u32.pow(retry as u32);
+        assert!(
+            *delay >= expected && *delay <= expected + base / 4,
+            "{:?}",
+            delays
+        );
+    }
+}
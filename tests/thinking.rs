@@ -0,0 +1,104 @@
+use axum::extract::Path;
+use axum::routing::post;
+use axum::{Json, Router};
+use gpt_bot_rs::i18n::Lang;
+use gpt_bot_rs::thinking::{delete_thinking, send_thinking, show_in_thinking};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use teloxide::types::ChatId;
+use teloxide::Bot;
+
+const CHAT_ID: ChatId = ChatId(42);
+
+// 模拟 Telegram Bot API，按顺序记录调用的方法名（小写）和消息文字
+async fn mock_telegram() -> (Bot, Arc<Mutex<Vec<(String, Option<String>)>>>) {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let recorded = calls.clone();
+    let app = Router::new().route(
+        "/:token/:method",
+        post(
+            move |Path((_, method)): Path<(String, String)>, Json(body): Json<Value>| {
+                let method = method.to_lowercase();
+                let text = body["text"].as_str().map(str::to_string);
+                recorded.lock().unwrap().push((method.clone(), text));
+                async move {
+                    let result = match method.as_str() {
+                        "deletemessage" => json!(true),
+                        _ => json!({
+                            "message_id": 1,
+                            "date": 0,
+                            "chat": { "id": CHAT_ID.0, "type": "private", "first_name": "test" },
+                            "text": body["text"],
+                        }),
+                    };
+                    Json(json!({ "ok": true, "result": result }))
+                }
+            },
+        ),
+    );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let url = reqwest::Url::parse(&format!("http://{}/", address)).unwrap();
+    (Bot::new("123:test").set_api_url(url), calls)
+}
+
+fn call(method: &str, text: Option<&str>) -> (String, Option<String>) {
+    (method.to_string(), text.map(str::to_string))
+}
+
+// 环境变量在同一个测试进程中共享，各种设置放在同一个测试中依次检查
+#[tokio::test]
+async fn placeholder_follows_show_thinking_and_thinking_text() {
+    let (bot, calls) = mock_telegram().await;
+
+    // 默认按界面语言发送占位消息，出错时把占位消息改为错误提示
+    std::env::remove_var("SHOW_THINKING");
+    std::env::remove_var("THINKING_TEXT");
+    let placeholder = send_thinking(&bot, CHAT_ID, Lang::En).await.unwrap();
+    assert!(placeholder.is_some());
+    show_in_thinking(&bot, CHAT_ID, placeholder.as_ref(), "出错了".to_string())
+        .await
+        .unwrap();
+    assert_eq!(
+        std::mem::take(&mut *calls.lock().unwrap()),
+        vec![
+            call("sendmessage", Some("🤔 Thinking...")),
+            call("editmessagetext", Some("出错了")),
+        ]
+    );
+
+    // 自定义文字，收到回复后删除占位消息
+    std::env::set_var("THINKING_TEXT", "  稍等…  ");
+    let placeholder = send_thinking(&bot, CHAT_ID, Lang::Zh).await.unwrap();
+    delete_thinking(&bot, CHAT_ID, placeholder.as_ref()).await;
+    assert_eq!(
+        std::mem::take(&mut *calls.lock().unwrap()),
+        vec![
+            call("sendmessage", Some("稍等…")),
+            call("deletemessage", None)
+        ]
+    );
+
+    // 空白的 THINKING_TEXT 等同于未设置
+    std::env::set_var("THINKING_TEXT", "   ");
+    send_thinking(&bot, CHAT_ID, Lang::Zh).await.unwrap();
+    assert_eq!(
+        std::mem::take(&mut *calls.lock().unwrap()),
+        vec![call("sendmessage", Some("🤔 思考中..."))]
+    );
+
+    // 关闭后不发送占位消息，错误提示作为新消息发送，也没有可以删除的消息
+    std::env::set_var("SHOW_THINKING", "false");
+    let placeholder = send_thinking(&bot, CHAT_ID, Lang::Zh).await.unwrap();
+    assert!(placeholder.is_none());
+    delete_thinking(&bot, CHAT_ID, placeholder.as_ref()).await;
+    show_in_thinking(&bot, CHAT_ID, placeholder.as_ref(), "出错了".to_string())
+        .await
+        .unwrap();
+    assert_eq!(
+        std::mem::take(&mut *calls.lock().unwrap()),
+        vec![call("sendmessage", Some("出错了"))]
+    );
+}
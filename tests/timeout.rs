@@ -0,0 +1,55 @@
+use axum::routing::post;
+use axum::Router;
+use gpt_bot_rs::api_keys::ApiKeys;
+use gpt_bot_rs::error::{self, AppError};
+use gpt_bot_rs::i18n::{Key, Lang};
+use gpt_bot_rs::openai::{openai_request_with_retry, OpenAiClient};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn hung_requests_time_out_without_retrying() {
+    // 接受请求后一直不返回的服务
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+    let app = Router::new().route(
+        "/v1/chat/completions",
+        post(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                "{}"
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "http://{}/v1/chat/completions",
+        listener.local_addr().unwrap()
+    );
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let keys = ApiKeys::new(vec!["sk-test".to_string()]).unwrap();
+    let client = OpenAiClient::new(keys, Duration::from_millis(300)).unwrap();
+    let result = openai_request_with_retry(None, |_| client.http.post(&url).body("{}")).await;
+
+    // 超时归为请求超时，给用户的提示是"请求超时，请重试"，并且不会重试
+    let err = result.unwrap_err();
+    assert!(matches!(err, AppError::Timeout(_)), "{:?}", err);
+    assert_eq!(err.user_message_key(), Key::ErrorTimeout);
+    assert_eq!(error::user_message(Lang::Zh, &err), "请求超时，请重试");
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn refused_connections_are_network_errors() {
+    // 绑定后立即释放端口，连接会被拒绝
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/v1/models", listener.local_addr().unwrap());
+    drop(listener);
+
+    let err = AppError::from(reqwest::get(&url).await.unwrap_err());
+    assert!(matches!(err, AppError::Network(_)), "{:?}", err);
+    assert_eq!(err.user_message_key(), Key::ErrorNetwork);
+}
@@ -0,0 +1,41 @@
+use gpt_bot_rs::tools;
+use serde_json::Value;
+
+#[test]
+fn get_current_time_applies_the_requested_offset() {
+    let result: Value = serde_json::from_str(&tools::execute(
+        "get_current_time",
+        r#"{"utc_offset_hours": 8}"#,
+    ))
+    .unwrap();
+    assert_eq!(result["utc_offset"], "+08:00");
+    assert!(result["datetime"].as_str().unwrap().ends_with("+08:00"));
+
+    // 没有参数时返回 UTC 时间
+    let result: Value = serde_json::from_str(&tools::execute("get_current_time", "")).unwrap();
+    assert_eq!(result["utc_offset"], "+00:00");
+}
+
+#[test]
+fn unknown_tools_and_bad_arguments_return_an_error_result() {
+    let result: Value = serde_json::from_str(&tools::execute("get_weather", "{}")).unwrap();
+    assert!(result["error"].is_string());
+
+    let result: Value =
+        serde_json::from_str(&tools::execute("get_current_time", "{not json")).unwrap();
+    assert!(result["error"].is_string());
+}
+
+#[test]
+fn every_definition_is_executable() {
+    for definition in tools::definitions() {
+        let name = definition["function"]["name"].as_str().unwrap();
+        let result: Value = serde_json::from_str(&tools::execute(name, "{}")).unwrap();
+        assert!(
+            result.get("error").is_none(),
+            "{} 执行失败: {}",
+            name,
+            result
+        );
+    }
+}